@@ -23,10 +23,6 @@ if typing.TYPE_CHECKING:
     SubPAction: Final = argparse._SubParsersAction[argparse.ArgumentParser]  # noqa: SLF001
 
 
-CMD_LIST_BRIEF: Final = [
-    ("pkgfile", "install"),
-]
-
 _PATH_APT_SOURCES = pathlib.Path("/etc/apt/sources.list.d")
 _PATH_APT_KEYRINGS = pathlib.Path("/usr/share/keyrings")
 _PATH_RPM_GPG = pathlib.Path("/etc/pki/rpm-gpg")
@@ -244,7 +240,7 @@ def cmd_command_list(cfg: defs.Config) -> None:
         for cmd_name, command in (
             (name, getattr(category, name)) for name in sorted(category._fields)
         ):
-            result = ["..."] if (cat_name, cmd_name) in CMD_LIST_BRIEF else command
+            result = ["..."] if f"{cat_name}.{cmd_name}" in var.templated_commands else command
             print(f"{cat_name}.{cmd_name}: {shlex.join(result)}")
 
 
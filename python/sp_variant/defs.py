@@ -151,6 +151,9 @@ class Variant(NamedTuple):
     commands: Commands
     """The OS commands to execute for particular purposes."""
 
+    templated_commands: list[str]
+    """The commands ("category.name") that are templates containing shell variable references."""
+
     min_sys_python: str
     """The minimum Python version that we can depend on."""
 
@@ -222,7 +225,7 @@ class RepoType(NamedTuple):
 
 
 VERSION: Final = "3.5.3"
-FORMAT_VERSION: Final = (1, 4)
+FORMAT_VERSION: Final = (1, 5)
 
 REPO_TYPES: Final = [
     RepoType(name="contrib", extension="", url="https://repo.storpool.com/public/"),
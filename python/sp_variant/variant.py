@@ -6,6 +6,7 @@ from __future__ import annotations
 
 import errno
 import pathlib
+import re
 import shlex
 import subprocess
 import typing
@@ -62,6 +63,9 @@ _DEFAULT_CONFIG = Config()
 
 SAFEENC = "Latin-1"
 
+_RE_NUMERIC_VERSION: Final = re.compile(r"[0-9]+ (?: \. [0-9]+ )*", re.X)
+"""A VERSION_ID value that may be matched against the variant definitions, e.g. "8.9"."""
+
 
 def _detect_from_os_release(cfg: Config) -> Variant | None:
     """Try to match the contents of /etc/os-release with a known variant."""
@@ -74,6 +78,10 @@ def _detect_from_os_release(cfg: Config) -> Variant | None:
     else:
         os_id, os_version = data.get("ID"), data.get("VERSION_ID")
 
+    if os_version is not None and not _RE_NUMERIC_VERSION.fullmatch(os_version):
+        cfg.diag(f"Ignoring the non-numeric os-release version {os_version!r}")
+        os_version = None
+
     if os_id is not None and os_version is not None:
         cfg.diag(f"Matching os-release id {os_id!r} version {os_version!r}")
         for var in vbuild.DETECT_ORDER:
@@ -101,6 +101,7 @@ _VARIANT_DEF: Final[list[defs.Variant | defs.VariantUpdate]] = [
                 ],
             ),
         ),
+        templated_commands=["pkgfile.install"],
         min_sys_python="3.11",
         repo=defs.DebRepo(
             vendor="debian",
@@ -422,6 +423,7 @@ fi
                 ],
             ),
         ),
+        templated_commands=["pkgfile.install"],
         min_sys_python="3.9",
         repo=defs.YumRepo(
             yumdef="redhat/repo/storpool-centos.repo",
@@ -577,6 +579,8 @@ fi
                 },
                 "pkgfile": {
                     "install": [
+                        "sh",
+                        "-c",
                         """
 unset to_install to_reinstall
 for f in $packages; do
@@ -775,6 +779,7 @@ fi
                 ],
             ),
         ),
+        templated_commands=["pkgfile.install"],
         min_sys_python="3.9",
         repo=defs.YumRepo(
             yumdef="redhat/repo/storpool-centos.repo",
@@ -908,6 +913,139 @@ fi
             },
         },
     ),
+    defs.VariantUpdate(
+        name="FEDORA38",
+        descr="Fedora 38",
+        parent="ALMA9",
+        detect=defs.Detect(
+            filename="/etc/fedora-release",
+            regex=re.compile(r"^ Fedora \s+ release \s+ 38 \s", re.X),
+            os_id="fedora",
+            os_version_regex=re.compile(r"^38$"),
+        ),
+        updates={
+            "commands": {
+                "package": {
+                    "install": [
+                        "dnf",
+                        "--disablerepo=*",
+                        "--enablerepo=fedora",
+                        "--enablerepo=updates",
+                        "--enablerepo=storpool-contrib",
+                        "install",
+                        "-q",
+                        "-y",
+                        "--",
+                    ],
+                    "purge": [
+                        "dnf",
+                        "remove",
+                        "-q",
+                        "-y",
+                        "--",
+                    ],
+                    "remove": [
+                        "dnf",
+                        "remove",
+                        "-q",
+                        "-y",
+                        "--",
+                    ],
+                },
+                "pkgfile": {
+                    "install": [
+                        "sh",
+                        "-c",
+                        """
+unset to_install to_reinstall
+for f in $packages; do
+    package="$(rpm -qp "$f")"
+    if rpm -q -- "$package"; then
+        to_reinstall="$to_reinstall ./$f"
+    else
+        to_install="$to_install ./$f"
+    fi
+done
+
+if [ -n "$to_install" ]; then
+    dnf install -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install
+fi
+if [ -n "$to_reinstall" ]; then
+    dnf reinstall -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall
+fi
+""",  # noqa: E501
+                    ],
+                },
+            },
+            "min_sys_python": "3.11",
+            "repo": {
+                "yumdef": "redhat/repo/storpool-fedora.repo",
+            },
+            "builder": {
+                "alias": "fedora38",
+                "base_image": "fedora:38",
+                "branch": "",
+            },
+        },
+    ),
+    defs.VariantUpdate(
+        name="AMZN2",
+        descr="Amazon Linux 2",
+        parent="CENTOS7",
+        detect=defs.Detect(
+            filename="/etc/system-release",
+            regex=re.compile(r"^ Amazon \s+ Linux \s+ release \s+ 2 \s", re.X),
+            os_id="amzn",
+            os_version_regex=re.compile(r"^2$"),
+        ),
+        updates={
+            "commands": {
+                "package": {
+                    "install": [
+                        "yum",
+                        "--disablerepo=*",
+                        "--enablerepo=amzn2-core",
+                        "--enablerepo=storpool-contrib",
+                        "install",
+                        "-q",
+                        "-y",
+                    ],
+                },
+                "pkgfile": {
+                    "install": [
+                        "sh",
+                        "-c",
+                        """
+unset to_install to_reinstall
+for f in $packages; do
+    package="$(rpm -qp "$f")"
+    if rpm -q -- "$package"; then
+        to_reinstall="$to_reinstall ./$f"
+    else
+        to_install="$to_install ./$f"
+    fi
+done
+
+if [ -n "$to_install" ]; then
+    yum install -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install
+fi
+if [ -n "$to_reinstall" ]; then
+    yum reinstall -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall
+fi
+""",  # noqa: E501
+                    ],
+                },
+            },
+            "repo": {
+                "yumdef": "redhat/repo/storpool-amzn.repo",
+            },
+            "builder": {
+                "alias": "amzn2",
+                "base_image": "amazonlinux:2",
+                "branch": "",
+            },
+        },
+    ),
 ]
 
 VARIANTS: Final[dict[str, defs.Variant]] = {}
@@ -1016,6 +1154,7 @@ def merge_into_parent(
             detect=child.detect,
             supported=parent.supported,
             commands=parent.commands,
+            templated_commands=list(parent.templated_commands),
             repo=parent.repo,
             package=dict(parent.package),
             min_sys_python=parent.min_sys_python,
@@ -38,6 +38,10 @@ _RE_YAIP_LINE = re.compile(
 )
 
 _SINGLE_QUOTE = "'"
+_DOUBLE_QUOTE = '"'
+
+_DOUBLE_QUOTED_ESCAPES = '"\\`$'
+"""The characters that a backslash escapes within a double-quoted value."""
 
 
 class YAIParser:
@@ -84,10 +88,27 @@ class YAIParser:
 
         return (varname, quoted)
 
-    def _parse_line_unquoted(self, line: str, varname: str, quoted: str) -> tuple[str, str] | None:
-        """Escape any characters preceded by a backslash."""
+    def _parse_line_unquoted(
+        self,
+        line: str,
+        varname: str,
+        quoted: str,
+        *,
+        double_quoted: bool,
+    ) -> tuple[str, str] | None:
+        r"""Remove the escaping backslashes from a double-quoted or unquoted value.
+
+        As in systemd's os-release parser, within a double-quoted value a backslash
+        only escapes a `"`, `\`, `` ` ``, or `$` character, and is taken literally
+        before any other one; no `$` or backtick expansion is ever performed.
+        """
         res = ""
         while quoted:
+            if double_quoted and _DOUBLE_QUOTE in quoted.split("\\", 1)[0]:
+                raise VariantYAIError(
+                    f"Weird {self.filename} line, the quoted content "
+                    f"contains the quote character: {line!r}",
+                )
             try:
                 idx = quoted.index("\\")
             except ValueError:
@@ -99,7 +120,11 @@ class YAIParser:
                     f"Weird {self.filename} line, backslash at "
                     f"the end of the quoted string: {line!r}",
                 )
-            res += quoted[:idx] + quoted[idx + 1]
+            escaped = quoted[idx + 1]
+            if double_quoted and escaped not in _DOUBLE_QUOTED_ESCAPES:
+                res += quoted[: idx + 2]
+            else:
+                res += quoted[:idx] + escaped
             quoted = quoted[idx + 2 :]
 
         return (varname, res)
@@ -129,7 +154,7 @@ class YAIParser:
                 f"Weird {self.filename} line, open/close quote mismatch: {line!r}",
             )
 
-        return self._parse_line_unquoted(line, varname, quoted)
+        return self._parse_line_unquoted(line, varname, quoted, double_quoted=oquot is not None)
 
     def parse(self) -> dict[str, str]:
         """Parse a file, store and return the result."""
@@ -65,6 +65,28 @@ def test_detect() -> None:
     assert pathlib.Path(var.detect.filename).is_file()
 
 
+@pytest.mark.parametrize(
+    ("version_id", "expected"),
+    [
+        ("8", "ALMA8"),
+        ("8.9", "ALMA8"),
+        ("8.x", None),
+        ("8.9x", None),
+        ("8.", None),
+        ("8.9\n", None),
+    ],
+)
+def test_detect_numeric_version(version_id: str, expected: str | None) -> None:
+    """Make sure that only numeric os-release versions are matched."""
+    vbuild.build_variants(variant.Config(verbose=False))
+    data: Final = {"ID": "almalinux", "VERSION_ID": version_id}
+    with mock.patch("sp_variant.yaiparser.YAIParser.parse", return_value=data):
+        var: Final = variant._detect_from_os_release(  # noqa: SLF001
+            variant.Config(verbose=False),
+        )
+    assert (var.name if var is not None else None) == expected
+
+
 def test_list_all() -> None:
     """Make sure that the package.list_all command does not go amok."""
     print()
@@ -197,3 +219,14 @@ def test_builder_branches() -> None:
         if item[1] != 1
     )
     assert not dup_branches
+
+
+def test_pkgfile_install_program() -> None:
+    """Make sure the pkgfile.install commands start with a program, not a script."""
+    bad: Final = [
+        var.name
+        for var in variant.get_all_variants_in_order()
+        if not var.commands.pkgfile.install
+        or any(char.isspace() for char in var.commands.pkgfile.install[0])
+    ]
+    assert not bad
@@ -51,6 +51,27 @@ _LINES_OK: Final = [
     ),
 ]
 
+# Cases derived from the quoting rules in systemd's os-release(5) manual page:
+# no `$` or backtick expansion, and a backslash in a double-quoted value only
+# escapes a `"`, `\`, `` ` ``, or `$` character.
+_LINES_SYSTEMD: Final = [
+    ('NAME="$HOME"', ("NAME", "$HOME")),
+    ('NAME="\\$HOME"', ("NAME", "$HOME")),
+    ('NAME="${HOME}/x"', ("NAME", "${HOME}/x")),
+    ('NAME="`uname -r`"', ("NAME", "`uname -r`")),
+    ('NAME="\\`uname -r\\`"', ("NAME", "`uname -r`")),
+    ('NAME="C:\\Windows"', ("NAME", "C:\\Windows")),
+    ('NAME="a\\\\b"', ("NAME", "a\\b")),
+    ('NAME="tab\\tnot expanded"', ("NAME", "tab\\tnot expanded")),
+    ('NAME="it\'s"', ("NAME", "it's")),
+    ("NAME='$HOME `id` \\n'", ("NAME", "$HOME `id` \\n")),
+    ("NAME=$HOME", ("NAME", "$HOME")),
+    ("NAME=\\$HOME\\`id\\`", ("NAME", "$HOME`id`")),
+]
+
+# Lines that systemd would parse differently, e.g. as a concatenation.
+_LINES_SYSTEMD_BAD: Final = ['NAME="foo"bar"', 'NAME="foo" "bar"']
+
 _CFG_TEXT: Final = """
 PRETTY_NAME="Debian GNU/Linux 11 (bullseye)"
 NAME="Debian GNU/Linux"
@@ -97,6 +118,21 @@ def test_parse_line_ok(line: str, res: tuple[str, str]) -> None:
     assert yai.parse_line(line) == res
 
 
+@pytest.mark.parametrize(("line", "res"), _LINES_SYSTEMD)
+def test_parse_line_systemd(line: str, res: tuple[str, str]) -> None:
+    """Make sure parse_line() follows the systemd os-release quoting rules."""
+    yai: Final = yaiparser.YAIParser("/dev/null")
+    assert yai.parse_line(line) == res
+
+
+@pytest.mark.parametrize("line", _LINES_SYSTEMD_BAD)
+def test_parse_line_systemd_bad(line: str) -> None:
+    """Make sure parse_line() rejects lines that systemd would parse differently."""
+    yai: Final = yaiparser.YAIParser("/dev/null")
+    with pytest.raises(defs.VariantError):
+        assert yai.parse_line(line) == ("not reached", "we hope")
+
+
 def test_parse() -> None:
     """Test the functionality of _YAIParser.parse() and .get()."""
     with tempfile.TemporaryDirectory() as tempd_obj:
@@ -0,0 +1,119 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Read the files installed by `repo add` from a tarball instead of a directory.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error as IoError, ErrorKind, Read, Result as IoResult};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use log::debug;
+use tar::{Archive, EntryType};
+
+use crate::cli::Owner;
+use crate::fsops::FsOps;
+
+/// The filename suffixes of the uncompressed and the gzip-compressed tarballs.
+const TAR_SUFFIXES: [&str; 1] = [".tar"];
+const TAR_GZ_SUFFIXES: [&str; 2] = [".tar.gz", ".tgz"];
+
+/// Check whether the repository directory path names a tarball instead.
+pub fn is_archive(path: &str) -> bool {
+    TAR_SUFFIXES
+        .iter()
+        .chain(TAR_GZ_SUFFIXES.iter())
+        .any(|suffix| path.ends_with(suffix))
+}
+
+/// Read the files within a tarball loaded into memory, pass everything else on.
+///
+/// The files in the archive are accessed as if the archive was a directory,
+/// e.g. `repo.tar.gz/DEBIAN12/storpool.sources`.
+pub struct ArchiveFs<'fs> {
+    /// The path to the archive; the prefix of the paths of the files within it.
+    path: String,
+
+    /// The contents of the regular files in the archive, keyed by their relative paths.
+    files: HashMap<String, Vec<u8>>,
+
+    /// Read the files outside the archive, write all the files.
+    inner: &'fs dyn FsOps,
+}
+
+impl<'fs> ArchiveFs<'fs> {
+    /// Load all the regular files from a .tar, .tar.gz, or .tgz archive.
+    pub fn load(path: &str, inner: &'fs dyn FsOps) -> Result<Self> {
+        let file =
+            BufReader::new(File::open(path).with_context(|| format!("Could not open {path}"))?);
+        let files = if TAR_GZ_SUFFIXES.iter().any(|suffix| path.ends_with(suffix)) {
+            read_entries(GzDecoder::new(file))
+        } else {
+            read_entries(file)
+        }
+        .with_context(|| format!("Could not read the {path} archive"))?;
+        debug!("Loaded {count} files from {path}", count = files.len());
+        Ok(Self {
+            path: path.to_owned(),
+            files,
+            inner,
+        })
+    }
+
+    /// Check whether the archive contains any files in the specified top-level directory.
+    pub fn has_dir(&self, dir: &str) -> bool {
+        let prefix = format!("{dir}/");
+        self.files.keys().any(|fname| fname.starts_with(&prefix))
+    }
+}
+
+/// Read the regular files from a tarball, ignore any other entries.
+///
+/// A leading `./` is removed from the paths of the files.
+fn read_entries<R: Read>(reader: R) -> IoResult<HashMap<String, Vec<u8>>> {
+    let mut files = HashMap::new();
+    for entry in Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let fname = match entry.path()?.to_str() {
+            Some(fname) => fname.trim_start_matches("./").to_owned(),
+            None => continue,
+        };
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        files.insert(fname, contents);
+    }
+    Ok(files)
+}
+
+impl FsOps for ArchiveFs<'_> {
+    fn read(&self, path: &str) -> IoResult<Vec<u8>> {
+        match path
+            .strip_prefix(&self.path)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            Some(fname) => self.files.get(fname).cloned().ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "No {fname} file in the {archive} archive",
+                        archive = self.path
+                    ),
+                )
+            }),
+            None => self.inner.read(path),
+        }
+    }
+
+    fn write(&self, path: &str, contents: &[u8], mode: u32, owner: Option<Owner>) -> IoResult<()> {
+        self.inner.write(path, contents, mode, owner)
+    }
+
+    fn backup(&self, path: &str, backup: &str) -> IoResult<bool> {
+        self.inner.backup(path, backup)
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Remember the detected build variant between runs.
+//!
+//! The cache file records the detected variant along with a key computed from
+//! the contents of the os-release file and the other files examined during
+//! the detection, the detection options, the variant definitions, and
+//! the program version.
+//! If any of those change, the cached result is ignored.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use sp_variant::{DetectConfig, VariantDefTop, VariantKind};
+
+/// The contents of the cache file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheData {
+    key: String,
+    variant: VariantKind,
+}
+
+/// The FNV-1a 64-bit offset basis.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The FNV-1a 64-bit prime.
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Update a FNV-1a hash; unlike `DefaultHasher`, it does not change between Rust versions.
+pub fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Update a hash with a length-prefixed chunk of data, so that the chunks cannot run together.
+fn hash_chunk(hash: u64, data: &[u8]) -> u64 {
+    let len = u64::try_from(data.len()).unwrap_or(u64::MAX);
+    fnv1a(fnv1a(hash, &len.to_le_bytes()), data)
+}
+
+/// Get the files that the detection may examine: the os-release one, the alternative
+/// os-release locations, and the release files used as a fallback, e.g. /etc/redhat-release.
+pub fn release_files<'data>(
+    varfull: &'data VariantDefTop,
+    os_release: &'data str,
+) -> Vec<&'data str> {
+    let fallback: BTreeSet<&str> = sp_variant::OS_RELEASE_CANDIDATES
+        .into_iter()
+        .chain(
+            sp_variant::get_all_variants_in_order_from(varfull)
+                .map(|var| var.detect.filename.as_str()),
+        )
+        .filter(|&fname| fname != os_release)
+        .collect();
+    [os_release].into_iter().chain(fallback).collect()
+}
+
+/// Compute the cache key for the specified release files, detection options,
+/// and variant definitions, e.g. ones loaded using `--variants-file`.
+pub fn cache_key(files: &[&str], detect: &DetectConfig, varfull: &VariantDefTop) -> Result<String> {
+    let hash = files.iter().try_fold(FNV_OFFSET, |hash, &fname| {
+        let hash = hash_chunk(hash, fname.as_bytes());
+        match fs::read(fname) {
+            Ok(contents) => Ok(hash_chunk(fnv1a(hash, &[1]), &contents)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(fnv1a(hash, &[0])),
+            Err(err) => Err(err).with_context(|| format!("Could not read {fname}")),
+        }
+    })?;
+    // `DetectConfig` is marked as non-exhaustive, so it cannot be destructured
+    // here to make sure that every field is hashed; its debug representation
+    // includes all of them, as well as any added later.
+    let hash = hash_chunk(hash, format!("{detect:?}").as_bytes());
+    // Serializing into a `Value` first sorts the keys of the variants and commands maps.
+    let defs = serde_json::to_value(varfull)
+        .and_then(|value| serde_json::to_vec(&value))
+        .context("Internal error: could not serialize the variant definitions")?;
+    let hash = hash_chunk(hash, &defs);
+    let hash = hash_chunk(hash, sp_variant::get_program_version().as_bytes());
+    Ok(format!("{hash:016x}"))
+}
+
+/// Get the cached variant if the cache file exists and its key matches.
+pub fn load(path: &str, key: &str) -> Result<Option<VariantKind>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Could not read {path}")),
+    };
+    let data: CacheData = serde_json::from_str(&contents)
+        .with_context(|| format!("Could not parse the {path} cache file"))?;
+    Ok((data.key == key).then_some(data.variant))
+}
+
+/// Record the detected variant into the cache file.
+pub fn store(path: &str, key: &str, variant: VariantKind) -> Result<()> {
+    let contents = serde_json::to_string(&CacheData {
+        key: key.to_owned(),
+        variant,
+    })
+    .context("Internal error: could not serialize the cache data")?;
+    fs::write(path, contents).with_context(|| format!("Could not write to {path}"))
+}
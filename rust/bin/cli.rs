@@ -3,10 +3,14 @@
  * SPDX-License-Identifier: BSD-2-Clause
  */
 
+use std::env;
+use std::ffi::OsString;
 use std::str::FromStr;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use clap_derive::{Parser, Subcommand};
+use sp_variant::{DetectConfig, RepoType};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,67 +18,123 @@ pub enum ParseError {
     #[error("The command identifier must consist of exactly two parts separated by a dot")]
     CommandIdNeedsTwo,
 
+    #[error("The owner must be specified as two numbers, UID:GID")]
+    OwnerInvalid,
+
     #[error(
-        "Unrecognized repository type, must be one of {}, {}, or {}",
+        "Unrecognized repository type, must be one of {}, {}, {}, or {}",
         RepoType::CONTRIB,
         RepoType::STAGING,
-        RepoType::INFRA
+        RepoType::INFRA,
+        RepoTypeSel::ALL
     )]
     RepoTypeUnknown,
+
+    #[error("The wrapper must be a non-empty command line with balanced quotes")]
+    WrapperInvalid,
 }
 
+/// A single repository type or all of them, as specified on the command line.
 #[derive(Debug, Clone)]
-pub enum RepoType {
-    Contrib,
-    Staging,
-    Infra,
-}
-
-impl RepoType {
-    pub const CONTRIB: &'static str = "contrib";
-    pub const STAGING: &'static str = "staging";
-    pub const INFRA: &'static str = "infra";
-
-    pub const EXT_CONTRIB: &'static str = "";
-    pub const EXT_STAGING: &'static str = "-staging";
-    pub const EXT_INFRA: &'static str = "-infra";
-
-    pub const fn extension(&self) -> &str {
-        match *self {
-            Self::Contrib => Self::EXT_CONTRIB,
-            Self::Staging => Self::EXT_STAGING,
-            Self::Infra => Self::EXT_INFRA,
+enum RepoTypeSel {
+    Single(RepoType),
+    All,
+}
+
+impl RepoTypeSel {
+    const ALL: &'static str = "all";
+}
+
+impl FromStr for RepoTypeSel {
+    type Err = ParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == Self::ALL {
+            Ok(Self::All)
+        } else {
+            RepoType::from_str(value)
+                .map(Self::Single)
+                .map_err(|_| ParseError::RepoTypeUnknown)
         }
     }
 }
 
-impl AsRef<str> for RepoType {
-    fn as_ref(&self) -> &str {
-        match *self {
-            Self::Contrib => Self::CONTRIB,
-            Self::Staging => Self::STAGING,
-            Self::Infra => Self::INFRA,
-        }
+/// The numeric user and group IDs to assign to the installed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    pub const ROOT: Self = Self { uid: 0, gid: 0 };
+}
+
+impl FromStr for Owner {
+    type Err = ParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (uid, gid) = value.split_once(':').ok_or(ParseError::OwnerInvalid)?;
+        Ok(Self {
+            uid: uid.parse().map_err(|_| ParseError::OwnerInvalid)?,
+            gid: gid.parse().map_err(|_| ParseError::OwnerInvalid)?,
+        })
     }
 }
 
-impl FromStr for RepoType {
+#[derive(Debug, Clone)]
+pub struct RepoAddConfig {
+    pub noop: bool,
+    pub dump_commands: bool,
+    pub offline: bool,
+    pub no_update: bool,
+    pub only_keyring: bool,
+    pub no_key_import: bool,
+    pub repodir: String,
+    pub repotypes: Vec<RepoType>,
+    pub keyring_dir: Option<String>,
+    pub owner: Option<Owner>,
+    #[cfg(feature = "json")]
+    pub manifest: Option<String>,
+    pub parallel: bool,
+    pub backup: bool,
+    pub verify_checksums: bool,
+    pub mirror: Option<String>,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub if_changed: bool,
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub assume_yes: bool,
+}
+
+/// A command to run a distribution-specific one through, e.g. `nice -n 19`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Wrapper(Vec<String>);
+
+impl FromStr for Wrapper {
     type Err = ParseError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        match value {
-            Self::CONTRIB => Ok(Self::Contrib),
-            Self::STAGING => Ok(Self::Staging),
-            Self::INFRA => Ok(Self::Infra),
-            _ => Err(ParseError::RepoTypeUnknown),
+        match shlex::split(value) {
+            Some(words) if !words.is_empty() => Ok(Self(words)),
+            _ => Err(ParseError::WrapperInvalid),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct RepoAddConfig {
+pub struct CommandListConfig {
+    pub category: Option<String>,
+    pub names_only: bool,
+    pub show_all: bool,
+}
+
+#[derive(Debug)]
+pub struct CommandBatchConfig {
+    pub file: String,
     pub noop: bool,
-    pub repodir: String,
+    pub keep_going: bool,
     pub repotype: RepoType,
 }
 
@@ -84,27 +144,111 @@ pub struct CommandRunConfig {
     pub name: String,
     pub noop: bool,
     pub args: Vec<String>,
+    pub args_stdin: bool,
+    pub shell: bool,
+    #[cfg(feature = "json")]
+    pub dump_env: bool,
+    pub repotype: RepoType,
+    pub wrap: Vec<String>,
+    pub ignore_failure: bool,
+    pub cwd: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct CommandWhichConfig {
+    pub command: CommandId,
+}
+
+#[derive(Debug)]
+pub struct DetectCmdConfig {
+    pub check: bool,
+    pub quiet: bool,
+    pub explain: bool,
+    pub all_matches: bool,
+    #[cfg(feature = "json")]
+    pub json: bool,
+}
+
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub struct DumpConfig {
+    pub pretty: bool,
 }
 
+#[derive(Debug)]
+pub struct ListVariantsConfig {
+    #[cfg(feature = "json")]
+    pub json: bool,
+}
+
+#[cfg(feature = "json")]
 #[derive(Debug)]
 pub struct ShowConfig {
     pub name: String,
+    pub field: Option<String>,
+    pub output: Option<String>,
+    /// Pretty-print the JSON data; if not specified, only if the output goes to a terminal.
+    pub pretty: Option<bool>,
+}
+
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub struct CompareConfig {
+    pub first: String,
+    pub second: String,
 }
 
 #[derive(Debug)]
 pub enum Mode {
-    CommandList,
+    CommandBatch(CommandBatchConfig),
+    CommandList(CommandListConfig),
     CommandRun(CommandRunConfig),
-    Detect,
+    CommandWhich(CommandWhichConfig),
+    Detect(DetectCmdConfig),
+    #[cfg(feature = "json")]
+    Dump(DumpConfig),
     Features,
+    #[cfg(feature = "json")]
+    Inventory,
+    ListVariants(ListVariantsConfig),
     RepoAdd(RepoAddConfig),
+    RepoRequirements,
+    #[cfg(feature = "json")]
+    Schema,
+    SelfTest,
+    #[cfg(feature = "json")]
     Show(ShowConfig),
+    #[cfg(feature = "json")]
+    ShowCompare(CompareConfig),
+    #[cfg(feature = "json")]
+    ShowDiffEmbedded,
+    #[cfg(feature = "json")]
+    Validate,
+}
+
+#[derive(Debug)]
+pub struct DetectOptions {
+    pub config: DetectConfig,
+    #[cfg(feature = "json")]
+    pub cache: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub detect: DetectOptions,
+    pub mode: Mode,
+    #[cfg(feature = "json")]
+    pub variants_file: Option<String>,
+    #[cfg(feature = "json")]
+    pub commands_file: Option<String>,
+    pub verbose: u8,
+    pub quiet: bool,
 }
 
 #[derive(Debug, Clone)]
-struct CommandId {
-    category: String,
-    name: String,
+pub struct CommandId {
+    pub category: String,
+    pub name: String,
 }
 
 impl FromStr for CommandId {
@@ -124,8 +268,39 @@ impl FromStr for CommandId {
 
 #[derive(Debug, Subcommand)]
 enum CommandCommand {
+    /// Run the distribution-specific commands listed in a file, one per line.
+    Batch {
+        /// No-operation mode; display what would be done.
+        #[clap(short('N'), long)]
+        noop: bool,
+
+        /// Run the rest of the commands even if one of them fails.
+        #[clap(short('k'), long)]
+        keep_going: bool,
+
+        /// The repository type to substitute for `${REPO_TYPE}` in the commands.
+        #[clap(short('t'), long, default_value("contrib"))]
+        repotype: RepoType,
+
+        /// The file to read `category.name [arg...]` lines from; empty lines and
+        /// comments starting with `#` are ignored.
+        file: String,
+    },
+
     /// List the distribution-specific commands.
-    List,
+    List {
+        /// Only list the commands in this category.
+        #[clap(long)]
+        category: Option<String>,
+
+        /// Only display the command identifiers, not the commands themselves.
+        #[clap(long)]
+        names_only: bool,
+
+        /// Also display the templated commands instead of replacing them with "...".
+        #[clap(long)]
+        show_all: bool,
+    },
 
     /// Run a distribution-specific command.
     Run {
@@ -133,15 +308,54 @@ enum CommandCommand {
         #[clap(short('N'), long)]
         noop: bool,
 
+        /// Read additional arguments from the standard input, one per line.
+        #[clap(long)]
+        args_stdin: bool,
+
+        /// Run the command via `sh -c`; all the words of the command and the arguments
+        /// are quoted, so they are passed to the command unchanged.
+        #[clap(long)]
+        shell: bool,
+
+        /// Output the command, the working directory, and any additional environment
+        /// variables as JSON instead of running it.
+        #[cfg(feature = "json")]
+        #[clap(long)]
+        dump_env: bool,
+
+        /// The repository type to substitute for `${REPO_TYPE}` in the command.
+        #[clap(short('t'), long, default_value("contrib"))]
+        repotype: RepoType,
+
+        /// Run the command through this one, e.g. 'nice -n 19'; split into words
+        /// using shell-like quoting rules, but still executed directly.
+        #[clap(long, value_name("COMMAND"))]
+        wrap: Option<Wrapper>,
+
+        /// Only warn about a non-zero exit code from the command, still exit with code 0.
+        #[clap(long)]
+        ignore_failure: bool,
+
+        /// Run the command in this directory instead of the current one.
+        #[clap(long, value_name("DIR"))]
+        cwd: Option<String>,
+
         /// The identifier of the command to run.
         command: CommandId,
 
         /// Arguments to pass to the command.
         args: Vec<String>,
     },
+
+    /// Display the full path to the program that a distribution-specific command runs.
+    Which {
+        /// The identifier of the command to look up.
+        command: CommandId,
+    },
 }
 
 #[derive(Debug, Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum RepoCommand {
     /// Install the StorPool repository configuration.
     Add {
@@ -149,14 +363,112 @@ enum RepoCommand {
         #[clap(short('N'), long)]
         noop: bool,
 
-        /// The path to the repo config directory.
+        /// Do not change anything; only output the commands that would be run,
+        /// one per line, with the words quoted for the shell.
+        #[clap(long)]
+        dump_commands: bool,
+
+        /// Do not run any commands that need network access, e.g. `apt-get update`;
+        /// the package database must be updated later.
+        #[clap(long)]
+        offline: bool,
+
+        /// Do not refresh the package database after installing the repository files;
+        /// unlike `--offline`, still install the required packages first.
+        #[clap(long)]
+        no_update: bool,
+
+        /// Only install the keyring file and import the keys, e.g. after a key rotation;
+        /// do not install the repository definitions or update the package database.
+        /// If the keys are embedded in the Apt sources list files, install those files
+        /// instead, since they are the ones that carry the keys.
+        #[clap(long)]
+        only_keyring: bool,
+
+        /// Yum: install the keyring file, but do not import the keys using `rpmkeys` or `rpm`.
+        #[clap(long)]
+        no_key_import: bool,
+
+        /// The path to the repo config directory or to a .tar, .tar.gz, or .tgz
+        /// archive of its contents.
         #[clap(short('d'), required(true))]
         repodir: String,
 
-        /// The type of the repository to add (default: contrib).
-        #[clap(short('t'), default_value("contrib"))]
-        repotype: RepoType,
+        /// The type of the repository to add (default: contrib); may be specified
+        /// more than once, or "all" may be used to add all of them.
+        #[clap(short('t'), long, default_value("contrib"))]
+        repotype: Vec<RepoTypeSel>,
+
+        /// The directory to install the keyring file into instead of the default one.
+        #[clap(long)]
+        keyring_dir: Option<String>,
+
+        /// The numeric user and group IDs to assign to the installed files
+        /// (default: 0:0 if running as root, otherwise leave them unchanged).
+        #[clap(long, value_name("UID:GID"))]
+        owner: Option<Owner>,
+
+        /// After a successful run, write a JSON list of the copied files and
+        /// the commands run (or, in no-operation mode, that would be) to this file.
+        #[cfg(feature = "json")]
+        #[clap(long, value_name("PATH"))]
+        manifest: Option<String>,
+
+        /// Copy the repository definition and keyring files concurrently;
+        /// the package database is still only updated after all of them.
+        #[clap(long)]
+        parallel: bool,
+
+        /// Before replacing an existing file, save its contents under
+        /// the same name with ".sp-bak" appended.
+        #[clap(long)]
+        backup: bool,
+
+        /// Read each installed file back and compare its SHA-256 digest to
+        /// that of the data written.
+        #[clap(long)]
+        verify_checksums: bool,
+
+        /// Point the installed repository definitions to this mirror instead,
+        /// replacing the scheme and host part of the repository URLs.
+        #[clap(long, value_name("URL"))]
+        mirror: Option<String>,
+
+        /// The number of times to retry a failed package database update.
+        #[clap(long, default_value("0"))]
+        retries: u32,
+
+        /// The number of seconds to wait before the first retry; doubled each time.
+        #[clap(long, default_value("5"))]
+        retry_delay: u64,
+
+        /// Do not rewrite the files that already have the same contents; if none of
+        /// them would change, do not update the package database either.
+        #[clap(long)]
+        if_changed: bool,
+
+        /// Set the http_proxy environment variable for the package manager commands.
+        #[clap(long, value_name("URL"))]
+        http_proxy: Option<String>,
+
+        /// Set the https_proxy environment variable for the package manager commands;
+        /// Yum is also told to use this proxy (or the HTTP one) via `--setopt`.
+        #[clap(long, value_name("URL"))]
+        https_proxy: Option<String>,
+
+        /// Do not ask for confirmation before making any changes, even if
+        /// the standard input is a terminal.
+        #[clap(short('y'), long, conflicts_with("assume_no"))]
+        assume_yes: bool,
+
+        /// Answer "no" to the confirmation prompt: only display what would be done,
+        /// same as `--noop`.
+        #[clap(long)]
+        assume_no: bool,
     },
+
+    /// List the OS packages that `repo add` installs before adding the repository.
+    Requirements,
 }
 
 #[derive(Debug, Subcommand)]
@@ -168,22 +480,115 @@ enum CliCommand {
     },
 
     /// Detect the build variant for the current host.
-    Detect,
+    Detect {
+        /// Output nothing, exit with code 3 if the current host is not supported.
+        #[clap(long)]
+        check: bool,
+
+        /// Output nothing at all, not even error messages; only report through
+        /// the exit code whether the current host is supported.
+        #[clap(short('q'), long, conflicts_with_all(&["explain", "all_matches"]))]
+        quiet: bool,
+
+        /// Also display how certain the detection is: an exact os-release match,
+        /// a release file version pattern match, a major version match,
+        /// a CPE_NAME match, or an ID_LIKE fallback.
+        #[clap(long, conflicts_with("check"))]
+        explain: bool,
+
+        /// List all the variants whose detection rules match the current host,
+        /// most preferred first, along with the way each one matched.
+        #[clap(long, conflicts_with_all(&["check", "explain"]))]
+        all_matches: bool,
+
+        /// Output a JSON object with the variant name, the host architecture,
+        /// environment (WSL, container), and init system, and the format version.
+        #[cfg(feature = "json")]
+        #[clap(long, conflicts_with_all(&["check", "quiet", "explain", "all_matches"]))]
+        json: bool,
+    },
+
+    /// Output the variant definitions embedded in this program.
+    #[cfg(feature = "json")]
+    Dump {
+        /// Pretty-print the JSON output.
+        #[clap(long)]
+        pretty: bool,
+    },
 
     /// Display the features supported by storpool_variant.
     Features,
 
+    /// Output a JSON object describing the current host: the detected variant,
+    /// the architecture, environment, and init system, and the supported features.
+    #[cfg(feature = "json")]
+    Inventory,
+
+    /// List the supported build variants.
+    ListVariants {
+        /// Output a JSON array of objects instead.
+        #[cfg(feature = "json")]
+        #[clap(long)]
+        json: bool,
+    },
+
     /// StorPool repository-related commands.
     Repo {
         #[clap(subcommand)]
         subc: RepoCommand,
     },
 
+    /// Output a JSON schema describing the variant definitions format.
+    #[cfg(feature = "json")]
+    Schema,
+
+    /// Check the embedded variant definitions for internal consistency.
+    Selftest,
+
     /// Display information about a build variant.
+    #[cfg(feature = "json")]
     Show {
+        /// Display the commands that differ between two build variants instead.
+        #[clap(long, num_args(2), value_names(["A", "B"]), conflicts_with("name"))]
+        compare: Option<Vec<String>>,
+
+        /// Display the differences between the variant definitions loaded using
+        /// `--variants-file` and the embedded ones instead.
+        #[clap(long, conflicts_with_all(&["compare", "name"]))]
+        diff_embedded: bool,
+
+        /// Only output the value at this dotted path within the variant data,
+        /// e.g. "name" or "commands.package.install".
+        #[clap(long, value_name("PATH"), conflicts_with_all(&["compare", "diff_embedded"]))]
+        field: Option<String>,
+
+        /// Write the output to this file (mode 0644) instead of the standard output.
+        #[clap(
+            short('o'),
+            long,
+            value_name("PATH"),
+            conflicts_with_all(&["compare", "diff_embedded"])
+        )]
+        output: Option<String>,
+
+        /// Pretty-print the JSON output; the default if the standard output is a terminal.
+        #[clap(long, conflicts_with_all(&["compare", "diff_embedded", "field"]))]
+        pretty: bool,
+
+        /// Output the JSON data on a single line instead of pretty-printing it;
+        /// the default if the output goes to a file or a pipe.
+        #[clap(long, conflicts_with_all(&["compare", "diff_embedded", "field", "pretty"]))]
+        compact: bool,
+
         /// The name of the build variant to query.
-        name: String,
+        #[clap(required_unless_present_any(&["compare", "diff_embedded"]))]
+        name: Option<String>,
     },
+
+    /// Check the variant definitions loaded using `--variants-file` for internal
+    /// consistency and a format version that this program supports.
+    #[cfg(feature = "json")]
+    Validate,
 }
 
 #[derive(Debug, Parser)]
@@ -194,18 +599,119 @@ enum CliCommand {
     version(sp_variant::get_program_version())
 )]
 pub struct Cli {
+    /// If the os-release ID is not recognized, try the distributions listed in ID_LIKE.
+    #[clap(long, global(true))]
+    id_like: bool,
+
+    /// Resolve the os-release file's symlinks; if it is missing or a dangling
+    /// symlink, try /usr/lib/os-release instead.
+    #[clap(long, global(true))]
+    dereference_symlinks: bool,
+
+    /// If the os-release VERSION_ID value (e.g. "7.10") is not recognized,
+    /// try only its major version number ("7") and warn about it.
+    #[clap(long, global(true))]
+    lenient: bool,
+
+    /// Read the vendor-provided /usr/lib/os-release file instead of
+    /// the default /etc/os-release one, e.g. when building images.
+    #[clap(long, global(true))]
+    prefer_usr_lib: bool,
+
+    /// Give up if reading the os-release file takes longer than this many seconds,
+    /// e.g. if /etc is on a hung network filesystem.
+    #[clap(long, global(true), value_name("SECONDS"))]
+    timeout_detect: Option<u64>,
+
+    /// If the os-release file has no usable VERSION_ID value, obtain it by
+    /// running `lsb_release -sr`; slow, so only as a last resort.
+    #[clap(long, global(true))]
+    lsb_release: bool,
+
+    /// Cache the detected variant in this file, reuse it while the release files are unchanged.
+    #[cfg(feature = "json")]
+    #[clap(long, global(true))]
+    cache: Option<String>,
+
+    /// Load the variant definitions from this JSON file (e.g. the output of `dump`)
+    /// instead of using the embedded ones.
+    #[cfg(feature = "json")]
+    #[clap(long, global(true), value_name("PATH"))]
+    variants_file: Option<String>,
+
+    /// Load additional distribution-specific commands from this JSON file,
+    /// a `{"category": {"name": ["program", "arg", ...]}}` object; they replace
+    /// any built-in commands with the same category and name.
+    #[cfg(feature = "json")]
+    #[clap(long, global(true), value_name("PATH"))]
+    commands_file: Option<String>,
+
+    /// Verbose operation; display diagnostic messages (specify twice for more).
+    #[clap(short('v'), long, global(true), action(ArgAction::Count))]
+    verbose: u8,
+
     #[clap(subcommand)]
     command: CliCommand,
 }
 
-pub fn parse() -> Mode {
-    let opts = Cli::parse();
+/// Build the list of the repository types to add, each of them only once.
+fn expand_repo_types(selected: &[RepoTypeSel]) -> Vec<RepoType> {
+    RepoType::VALUES
+        .into_iter()
+        .filter(|rtype| {
+            selected.iter().any(|sel| match *sel {
+                RepoTypeSel::All => true,
+                RepoTypeSel::Single(ref single) => single == rtype,
+            })
+        })
+        .collect()
+}
+
+/// Parse the program's command-line arguments.
+pub fn parse() -> Config {
+    parse_from(env::args_os())
+}
+
+/// Parse the specified command-line arguments, the first one being the program name.
+pub fn parse_from<I, T>(args: I) -> Config
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let opts = Cli::parse_from(args);
 
-    match opts.command {
+    let mode = match opts.command {
         CliCommand::Command { subc } => match subc {
-            CommandCommand::List => Mode::CommandList,
+            CommandCommand::Batch {
+                noop,
+                keep_going,
+                repotype,
+                file,
+            } => Mode::CommandBatch(CommandBatchConfig {
+                file,
+                noop,
+                keep_going,
+                repotype,
+            }),
+            CommandCommand::List {
+                category,
+                names_only,
+                show_all,
+            } => Mode::CommandList(CommandListConfig {
+                category,
+                names_only,
+                show_all,
+            }),
             CommandCommand::Run {
                 noop,
+                args_stdin,
+                shell,
+                #[cfg(feature = "json")]
+                dump_env,
+                repotype,
+                wrap,
+                ignore_failure,
+                cwd,
                 command,
                 args,
             } => Mode::CommandRun(CommandRunConfig {
@@ -213,21 +719,153 @@ pub fn parse() -> Mode {
                 name: command.name,
                 noop,
                 args,
+                args_stdin,
+                shell,
+                #[cfg(feature = "json")]
+                dump_env,
+                repotype,
+                wrap: wrap.map_or_else(Vec::new, |wrapper| wrapper.0),
+                ignore_failure,
+                cwd,
             }),
+            CommandCommand::Which { command } => Mode::CommandWhich(CommandWhichConfig { command }),
         },
-        CliCommand::Detect => Mode::Detect,
+        CliCommand::Detect {
+            check,
+            quiet,
+            explain,
+            all_matches,
+            #[cfg(feature = "json")]
+            json,
+        } => Mode::Detect(DetectCmdConfig {
+            check,
+            quiet,
+            explain,
+            all_matches,
+            #[cfg(feature = "json")]
+            json,
+        }),
+        #[cfg(feature = "json")]
+        CliCommand::Dump { pretty } => Mode::Dump(DumpConfig { pretty }),
         CliCommand::Features => Mode::Features,
-        CliCommand::Show { name } => Mode::Show(ShowConfig { name }),
+        #[cfg(feature = "json")]
+        CliCommand::Inventory => Mode::Inventory,
+        CliCommand::ListVariants {
+            #[cfg(feature = "json")]
+            json,
+        } => Mode::ListVariants(ListVariantsConfig {
+            #[cfg(feature = "json")]
+            json,
+        }),
+        #[cfg(feature = "json")]
+        CliCommand::Schema => Mode::Schema,
+        CliCommand::Selftest => Mode::SelfTest,
+        #[cfg(feature = "json")]
+        CliCommand::Show {
+            diff_embedded: true,
+            ..
+        } => Mode::ShowDiffEmbedded,
+        #[cfg(feature = "json")]
+        CliCommand::Show {
+            compare,
+            diff_embedded: false,
+            field,
+            output,
+            pretty,
+            compact,
+            name,
+        } => match (compare, name) {
+            (Some(compare), _) => match *compare {
+                [ref first, ref second] => Mode::ShowCompare(CompareConfig {
+                    first: first.clone(),
+                    second: second.clone(),
+                }),
+                _ => unreachable!(),
+            },
+            (None, Some(name)) => Mode::Show(ShowConfig {
+                name,
+                field,
+                output,
+                pretty: match (pretty, compact) {
+                    (false, false) => None,
+                    (pretty, _) => Some(pretty),
+                },
+            }),
+            (None, None) => unreachable!(),
+        },
+        #[cfg(feature = "json")]
+        CliCommand::Validate => Mode::Validate,
         CliCommand::Repo { subc } => match subc {
             RepoCommand::Add {
                 noop,
+                dump_commands,
+                offline,
+                no_update,
+                only_keyring,
+                no_key_import,
                 repodir,
                 repotype,
+                keyring_dir,
+                owner,
+                #[cfg(feature = "json")]
+                manifest,
+                parallel,
+                backup,
+                verify_checksums,
+                mirror,
+                retries,
+                retry_delay,
+                if_changed,
+                http_proxy,
+                https_proxy,
+                assume_yes,
+                assume_no,
             } => Mode::RepoAdd(RepoAddConfig {
-                noop,
+                noop: noop || dump_commands || assume_no,
+                dump_commands,
+                offline,
+                no_update,
+                only_keyring,
+                no_key_import,
                 repodir,
-                repotype,
+                repotypes: expand_repo_types(&repotype),
+                keyring_dir,
+                owner,
+                #[cfg(feature = "json")]
+                manifest,
+                parallel,
+                backup,
+                verify_checksums,
+                mirror,
+                retries,
+                retry_delay: Duration::from_secs(retry_delay),
+                if_changed,
+                http_proxy,
+                https_proxy,
+                assume_yes,
             }),
+            RepoCommand::Requirements => Mode::RepoRequirements,
+        },
+    };
+    let quiet = matches!(mode, Mode::Detect(ref detect) if detect.quiet);
+    Config {
+        detect: DetectOptions {
+            config: DetectConfig::default()
+                .with_id_like(opts.id_like)
+                .with_dereference_symlinks(opts.dereference_symlinks)
+                .with_lenient(opts.lenient)
+                .with_prefer_usr_lib(opts.prefer_usr_lib)
+                .with_timeout(opts.timeout_detect.map(Duration::from_secs))
+                .with_lsb_release(opts.lsb_release),
+            #[cfg(feature = "json")]
+            cache: opts.cache,
         },
+        mode,
+        #[cfg(feature = "json")]
+        variants_file: opts.variants_file,
+        #[cfg(feature = "json")]
+        commands_file: opts.commands_file,
+        verbose: opts.verbose,
+        quiet,
     }
 }
@@ -0,0 +1,305 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Read and write the files installed by `repo add`.
+
+use std::ffi::CString;
+use std::fs::{self, File, Permissions};
+use std::io::{self, Error as IoError, ErrorKind, Result as IoResult, Write};
+#[cfg(test)]
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use log::{debug, warn};
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::{self, Gid, Uid};
+use tempfile::NamedTempFile;
+
+use crate::cli::Owner;
+
+/// The filesystem operations needed to install a file; may be invoked from several threads.
+pub trait FsOps: Sync {
+    /// Read the whole contents of a file.
+    fn read(&self, path: &str) -> IoResult<Vec<u8>>;
+
+    /// Replace the contents of a file, setting its access mode and, optionally,
+    /// its ownership.
+    fn write(&self, path: &str, contents: &[u8], mode: u32, owner: Option<Owner>) -> IoResult<()>;
+
+    /// Copy a file, setting the access mode and, optionally, the ownership of
+    /// the copy; return the number of bytes copied.
+    fn copy(&self, src: &str, dst: &str, mode: u32, owner: Option<Owner>) -> IoResult<u64> {
+        let contents = self.read(src)?;
+        self.write(dst, &contents, mode, owner)?;
+        byte_count(contents.len())
+    }
+
+    /// Determine the size of a file in bytes.
+    fn size(&self, path: &str) -> IoResult<u64> {
+        byte_count(self.read(path)?.len())
+    }
+
+    /// Preserve the current contents of a file under another name, replacing
+    /// any previous backup; return `false` if there is no such file.
+    fn backup(&self, path: &str, backup: &str) -> IoResult<bool>;
+}
+
+/// Convert an in-memory length to a file size.
+fn byte_count(len: usize) -> IoResult<u64> {
+    u64::try_from(len).map_err(|err| IoError::new(ErrorKind::Other, err))
+}
+
+/// The maximum number of temporary files that are removed if the program is interrupted.
+const MAX_IN_PROGRESS: usize = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_FILE: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+
+/// The paths of the temporary files currently being written.
+///
+/// The signal handler may only perform async-signal-safe operations, so
+/// the paths are kept as C strings in a fixed array instead of a locked
+/// collection. The strings are never freed, so that the handler can never
+/// see a dangling pointer; `repo add` only writes a handful of files.
+static IN_PROGRESS: [AtomicPtr<c_char>; MAX_IN_PROGRESS] = [NO_FILE; MAX_IN_PROGRESS];
+
+/// The message output if the program is interrupted.
+const INTERRUPTED_MESSAGE: &[u8] =
+    b"storpool_variant: interrupted, removed any partially written files\n";
+
+/// Remove the temporary files being written, output a message, and exit.
+extern "C" fn handle_interrupt(signum: c_int) {
+    for slot in &IN_PROGRESS {
+        let path = slot.load(Ordering::SeqCst);
+        if !path.is_null() {
+            // SAFETY: the non-null pointers are C strings that are never freed.
+            unsafe {
+                libc::unlink(path);
+            }
+        }
+    }
+    // SAFETY: these are async-signal-safe, the buffer is a static one.
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            INTERRUPTED_MESSAGE.as_ptr().cast(),
+            INTERRUPTED_MESSAGE.len(),
+        );
+        libc::_exit(128_i32.saturating_add(signum));
+    }
+}
+
+/// The signals that lead to the removal of the temporary files.
+const INTERRUPT_SIGNALS: [Signal; 2] = [Signal::SIGINT, Signal::SIGTERM];
+
+/// The number of temporary files being written and the signal dispositions
+/// to restore once there are none left.
+#[derive(Debug)]
+struct HandlerState {
+    /// The number of existing [`InterruptGuard`] objects.
+    active: usize,
+
+    /// The dispositions replaced by [`handle_interrupt`].
+    previous: Vec<(Signal, SigAction)>,
+}
+
+/// The state of the SIGINT and SIGTERM handler.
+static HANDLER_STATE: Mutex<HandlerState> = Mutex::new(HandlerState {
+    active: 0,
+    previous: Vec::new(),
+});
+
+/// Lock the handler state, even if another thread panicked while holding it.
+fn handler_state() -> MutexGuard<'static, HandlerState> {
+    HANDLER_STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Install the SIGINT and SIGTERM handler, unless the signals are ignored.
+fn install_interrupt_handler() -> Vec<(Signal, SigAction)> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_interrupt),
+        SaFlags::empty(),
+        SigSet::all(),
+    );
+    let mut previous = Vec::new();
+    for sig in INTERRUPT_SIGNALS {
+        // SAFETY: the handler only performs async-signal-safe operations.
+        match unsafe { signal::sigaction(sig, &action) } {
+            Ok(old) if old.handler() == SigHandler::SigIgn => {
+                // SAFETY: restoring the previous disposition.
+                if let Err(err) = unsafe { signal::sigaction(sig, &old) } {
+                    warn!("Could not restore the {sig} disposition: {err}");
+                }
+            }
+            Ok(old) => previous.push((sig, old)),
+            Err(err) => warn!("Could not install a {sig} handler: {err}"),
+        }
+    }
+    previous
+}
+
+/// Restore the SIGINT and SIGTERM dispositions replaced by the handler.
+fn restore_interrupt_handler(previous: &[(Signal, SigAction)]) {
+    for &(sig, ref old) in previous {
+        // SAFETY: restoring the previous disposition.
+        if let Err(err) = unsafe { signal::sigaction(sig, old) } {
+            warn!("Could not restore the {sig} disposition: {err}");
+        }
+    }
+}
+
+/// Get the number of temporary files being written and check whether
+/// the SIGINT handler that removes them is currently installed.
+#[cfg(test)]
+pub fn interrupt_handler_status() -> (usize, bool) {
+    let state = handler_state();
+    let mut current = MaybeUninit::<libc::sigaction>::uninit();
+    // SAFETY: only querying the current disposition into a buffer of the right type.
+    let installed = unsafe {
+        libc::sigaction(libc::SIGINT, ptr::null(), current.as_mut_ptr()) == 0
+            && current.assume_init().sa_sigaction
+                == handle_interrupt as extern "C" fn(c_int) as libc::sighandler_t
+    };
+    (state.active, installed)
+}
+
+/// Remove a temporary file if the program is interrupted while the guard exists.
+///
+/// The signal handler is only in place while at least one guard exists, so
+/// that e.g. interrupting a command run after the files have been written
+/// has the usual effect.
+#[derive(Debug)]
+struct InterruptGuard {
+    /// The index of the path in [`IN_PROGRESS`], if there was room.
+    slot: Option<usize>,
+}
+
+impl InterruptGuard {
+    /// Register a temporary file for removal on SIGINT or SIGTERM.
+    fn new(path: &Path) -> Self {
+        {
+            let mut state = handler_state();
+            if state.active == 0 {
+                state.previous = install_interrupt_handler();
+            }
+            state.active += 1;
+        }
+        let slot = CString::new(path.as_os_str().as_bytes())
+            .ok()
+            .and_then(|cpath| {
+                let raw = cpath.into_raw();
+                let slot = IN_PROGRESS.iter().position(|slot| {
+                    slot.compare_exchange(ptr::null_mut(), raw, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                });
+                if slot.is_none() {
+                    // SAFETY: the pointer was obtained from `into_raw()` above and
+                    // was never stored, so the signal handler cannot see it.
+                    drop(unsafe { CString::from_raw(raw) });
+                }
+                slot
+            });
+        if slot.is_none() {
+            debug!(
+                "Not registering {path} for removal on interrupt",
+                path = path.display()
+            );
+        }
+        Self { slot }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.and_then(|idx| IN_PROGRESS.get(idx)) {
+            slot.store(ptr::null_mut(), Ordering::SeqCst);
+        }
+        let mut state = handler_state();
+        state.active = state.active.saturating_sub(1);
+        if state.active == 0 {
+            restore_interrupt_handler(&state.previous);
+            state.previous.clear();
+        }
+    }
+}
+
+/// Atomically replace a file with a temporary one filled in by the specified function.
+fn replace_file<F, T>(path: &str, mode: u32, owner: Option<Owner>, fill: F) -> IoResult<T>
+where
+    F: FnOnce(&mut NamedTempFile) -> IoResult<T>,
+{
+    let dir = Path::new(path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut outfile = NamedTempFile::new_in(dir)?;
+    let _guard = InterruptGuard::new(outfile.path());
+    outfile
+        .as_file()
+        .set_permissions(Permissions::from_mode(mode))?;
+    if let Some(owner) = owner {
+        unistd::fchown(
+            outfile.as_file().as_raw_fd(),
+            Some(Uid::from_raw(owner.uid)),
+            Some(Gid::from_raw(owner.gid)),
+        )?;
+    }
+    let res = fill(&mut outfile)?;
+    outfile.as_file().sync_all()?;
+    outfile.persist(path)?;
+    Ok(res)
+}
+
+/// Actually operate on the filesystem.
+#[derive(Debug)]
+pub struct RealFs;
+
+impl FsOps for RealFs {
+    fn read(&self, path: &str) -> IoResult<Vec<u8>> {
+        fs::read(path)
+    }
+
+    /// Write to a temporary file in the same directory and rename it over
+    /// the destination, so that the destination is always either the old or
+    /// the complete new file, with the correct mode and ownership.
+    /// The temporary file is removed on errors and, while it is being
+    /// written, if the program is interrupted by SIGINT or SIGTERM.
+    fn write(&self, path: &str, contents: &[u8], mode: u32, owner: Option<Owner>) -> IoResult<()> {
+        replace_file(path, mode, owner, |outfile| outfile.write_all(contents))
+    }
+
+    /// Read and write the file in chunks, replacing the destination the same
+    /// way as [`RealFs::write`] does.
+    fn copy(&self, src: &str, dst: &str, mode: u32, owner: Option<Owner>) -> IoResult<u64> {
+        let mut infile = File::open(src)?;
+        replace_file(dst, mode, owner, |outfile| io::copy(&mut infile, outfile))
+    }
+
+    fn size(&self, path: &str) -> IoResult<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    /// Create a hard link, so that the backup keeps the mode and ownership of
+    /// the original file, and the original is still replaced atomically.
+    fn backup(&self, path: &str, backup: &str) -> IoResult<bool> {
+        if let Err(err) = fs::remove_file(backup) {
+            if err.kind() != ErrorKind::NotFound {
+                return Err(err);
+            }
+        }
+        match fs::hard_link(path, backup) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
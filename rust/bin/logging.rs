@@ -0,0 +1,75 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Send diagnostic messages to the standard error stream.
+
+use std::io::{self, Write};
+
+use anyhow::{Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Write log messages at or above the configured level to the standard error stream.
+#[derive(Debug)]
+pub struct StderrLogger {
+    level: LevelFilter,
+}
+
+impl StderrLogger {
+    /// Determine the log level from the number of times `-v` was specified.
+    pub const fn from_verbosity(verbose: u8) -> Self {
+        let level = match verbose {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+        Self { level }
+    }
+
+    /// Do not output any messages at all.
+    pub const fn silent() -> Self {
+        Self {
+            level: LevelFilter::Off,
+        }
+    }
+
+    /// The most verbose level of messages that will be output.
+    pub const fn level(&self) -> LevelFilter {
+        self.level
+    }
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            // If we cannot write to the standard error stream, there is nowhere to report that.
+            #[allow(clippy::let_underscore_must_use)]
+            let _ = writeln!(
+                io::stderr().lock(),
+                "[{level}] {args}",
+                level = record.level(),
+                args = record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        #[allow(clippy::let_underscore_must_use)]
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Install the logger for the whole program.
+pub fn init(verbose: u8, quiet: bool) -> Result<()> {
+    let logger = if quiet {
+        StderrLogger::silent()
+    } else {
+        StderrLogger::from_verbosity(verbose)
+    };
+    log::set_max_level(logger.level());
+    log::set_boxed_logger(Box::new(logger)).context("Could not initialize the logger")
+}
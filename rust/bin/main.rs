@@ -14,123 +14,583 @@
 
 use std::borrow::ToOwned;
 use std::collections::HashMap;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+#[cfg(feature = "json")]
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::iter;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::AsRawFd;
-use std::os::unix::process::ExitStatusExt;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
 
 use anyhow::{bail, ensure, Context, Result};
-use nix::unistd::{self, Gid, Uid};
-use serde_json::json;
+use log::{debug, info, warn};
+use nix::errno::Errno;
+use nix::unistd::{self, Uid};
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 
-use sp_variant::{self, DebRepo, Repo, Variant, VariantDefTop, YumRepo};
+use sp_variant::keyring::{self, InstallerError, KeyringInstaller};
+use sp_variant::{
+    self, DebRepo, DetectConfig, Keyrings, Repo, RepoType, Variant, VariantDefTop, VariantError,
+    YumRepo,
+};
 
+#[cfg(feature = "tarball")]
+mod archive;
+#[cfg(feature = "json")]
+mod cache;
 mod cli;
+mod fsops;
+mod logging;
+mod manifest;
+mod runner;
 
-use cli::{CommandRunConfig, Mode, RepoAddConfig, ShowConfig};
+#[cfg(feature = "tarball")]
+use archive::ArchiveFs;
+use cli::{
+    CommandBatchConfig, CommandId, CommandListConfig, CommandRunConfig, CommandWhichConfig, Config,
+    DetectCmdConfig, DetectOptions, ListVariantsConfig, Mode, Owner, RepoAddConfig,
+};
+#[cfg(feature = "json")]
+use cli::{CompareConfig, DumpConfig, ShowConfig};
+use fsops::{FsOps, RealFs};
+use manifest::{Action, Manifest, RecordingRunner};
+use runner::{CommandError, NoopRunner, ProcessRunner, Runner, SkipRunner};
 
-fn detect_variant(varfull: &VariantDefTop) -> Result<&Variant> {
-    sp_variant::detect_from(varfull).context("Could not detect the current build variant")
+#[cfg(all(test, feature = "json"))]
+mod tests;
+
+/// Detect the current variant, using the cache file keyed on the specified os-release file
+/// and the fallback release files.
+///
+/// Problems with the cache file are not fatal; the variant is detected anew.
+#[cfg(feature = "json")]
+fn detect_cached<'defs>(
+    varfull: &'defs VariantDefTop,
+    detect: &DetectOptions,
+    os_release: &str,
+) -> Result<&'defs Variant, VariantError> {
+    let path = match detect.cache {
+        Some(ref path) => path,
+        None => return sp_variant::detect_from_with_config(varfull, &detect.config),
+    };
+    let key = match cache::cache_key(
+        &cache::release_files(varfull, os_release),
+        &detect.config,
+        varfull,
+    ) {
+        Ok(key) => key,
+        Err(err) => {
+            warn!("Not using the {path} cache file: {err:#}");
+            return sp_variant::detect_from_with_config(varfull, &detect.config);
+        }
+    };
+    match cache::load(path, &key) {
+        Ok(Some(kind)) => {
+            if let Some(var) =
+                sp_variant::get_all_variants_in_order_from(varfull).find(|var| var.kind == kind)
+            {
+                debug!("Using the {kind} build variant from the {path} cache file");
+                return Ok(var);
+            }
+        }
+        Ok(None) => debug!("The {path} cache file is missing or stale"),
+        Err(err) => warn!("Ignoring the {path} cache file: {err:#}"),
+    }
+
+    let var = sp_variant::detect_from_with_config(varfull, &detect.config)?;
+    if let Err(err) = cache::store(path, &key, var.kind) {
+        warn!("Could not update the {path} cache file: {err:#}");
+    }
+    Ok(var)
+}
+
+/// The environment variable that forces the detection to return a specific variant.
+const ENV_OVERRIDE: &str = "SP_VARIANT_OVERRIDE";
+
+/// Detect the current variant, using the cache file if one was specified.
+///
+/// If the `SP_VARIANT_OVERRIDE` environment variable is set, return
+/// the variant it names instead.
+fn detect_current<'defs>(
+    varfull: &'defs VariantDefTop,
+    detect: &DetectOptions,
+) -> Result<&'defs Variant, VariantError> {
+    match env::var(ENV_OVERRIDE) {
+        Ok(name) if !name.is_empty() => {
+            warn!("Using the {name} build variant specified in the {ENV_OVERRIDE} environment variable");
+            sp_variant::get_from(varfull, &name)
+        }
+        #[cfg(feature = "json")]
+        _ => detect_cached(varfull, detect, "/etc/os-release"),
+        #[cfg(not(feature = "json"))]
+        _ => sp_variant::detect_from_with_config(varfull, &detect.config),
+    }
+}
+
+fn detect_variant<'defs>(
+    varfull: &'defs VariantDefTop,
+    detect: &DetectOptions,
+) -> Result<&'defs Variant> {
+    let var =
+        detect_current(varfull, detect).context("Could not detect the current build variant")?;
+    debug!(
+        "Detected the {kind} build variant",
+        kind = var.kind.as_ref()
+    );
+    Ok(var)
+}
+
+/// Describe the detected variant and the way it was detected.
+///
+/// The cache file is not used; the detection is always performed anew.
+fn explain_detection(varfull: &VariantDefTop, detect: &DetectOptions) -> Result<String> {
+    match env::var(ENV_OVERRIDE) {
+        Ok(name) if !name.is_empty() => {
+            let var = sp_variant::get_from(varfull, &name)
+                .with_context(|| format!("Invalid {ENV_OVERRIDE} value"))?;
+            Ok(format!(
+                "{kind} (specified in the {ENV_OVERRIDE} environment variable)",
+                kind = var.kind
+            ))
+        }
+        _ => {
+            let res = sp_variant::detect_result_from(varfull, &detect.config)
+                .context("Could not detect the current build variant")?;
+            Ok(format!(
+                "{kind} (confidence: {confidence})",
+                kind = res.variant.kind,
+                confidence = res.confidence
+            ))
+        }
+    }
+}
+
+/// Detect the current variant and identify the detection rule that matched.
+///
+/// As with `--explain`, the cache file is not used; there is no rule to
+/// report if the variant is specified by the environment.
+#[cfg(feature = "json")]
+fn detect_with_rule<'defs>(
+    varfull: &'defs VariantDefTop,
+    detect: &DetectOptions,
+) -> Result<(&'defs Variant, Option<String>)> {
+    match env::var(ENV_OVERRIDE) {
+        Ok(name) if !name.is_empty() => Ok((detect_variant(varfull, detect)?, None)),
+        _ => {
+            let res = sp_variant::detect_result_from(varfull, &detect.config)
+                .context("Could not detect the current build variant")?;
+            Ok((res.variant, Some(res.matched_rule())))
+        }
+    }
+}
+
+/// Describe all the variants whose detection rules match the current host.
+///
+/// Neither the cache file nor the environment override is used.
+fn all_matches_lines(varfull: &VariantDefTop, detect: &DetectOptions) -> Result<Vec<String>> {
+    let found = sp_variant::detect_all_from(varfull, &detect.config)
+        .context("Could not examine the current host")?;
+    if found.is_empty() {
+        return Err(VariantError::UnknownVariant)
+            .context("Could not detect the current build variant");
+    }
+    Ok(found
+        .iter()
+        .map(|res| {
+            format!(
+                "{kind} (confidence: {confidence})",
+                kind = res.variant.kind,
+                confidence = res.confidence
+            )
+        })
+        .collect())
+}
+
+/// Build the line describing the features supported by the program.
+fn features_line(varfull: &VariantDefTop) -> String {
+    let (major, minor) = sp_variant::get_format_version_from(varfull);
+    let program_version = sp_variant::get_program_version_from(varfull);
+    format!("Features: format={major}.{minor} variant={program_version}")
 }
 
 #[allow(clippy::print_stdout)]
 fn cmd_features(varfull: &VariantDefTop) {
+    println!("{line}", line = features_line(varfull));
+}
+
+/// The exit code for any failure not covered by the more specific ones below.
+const EXIT_FAILURE: u8 = 1;
+
+// The exit code 2 is used by the command-line parser for usage errors.
+
+/// The exit code if the current host is not supported.
+const EXIT_UNSUPPORTED: u8 = 3;
+
+/// The exit code if a file could not be read or written.
+const EXIT_FILESYSTEM: u8 = 4;
+
+/// The exit code if an external command could not be run or did not succeed.
+const EXIT_SUBPROCESS: u8 = 5;
+
+/// Determine the exit code from the outermost recognized cause of the error.
+fn failure_code(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if cause.is::<CommandError>() {
+            return EXIT_SUBPROCESS;
+        }
+        match cause.downcast_ref::<VariantError>() {
+            Some(&VariantError::UnknownVariant) => return EXIT_UNSUPPORTED,
+            Some(&VariantError::DetectionTimeout(..)) => return EXIT_FILESYSTEM,
+            _ => (),
+        }
+        if cause.is::<io::Error>() || cause.is::<Errno>() {
+            return EXIT_FILESYSTEM;
+        }
+    }
+    EXIT_FAILURE
+}
+
+/// Build the JSON object describing the detected variant, the detection rule that
+/// matched, the host architecture, environment, and init system, and the format version.
+#[cfg(feature = "json")]
+fn detect_json(varfull: &VariantDefTop, var: &Variant, matched_rule: Option<&str>) -> Value {
     let (major, minor) = sp_variant::get_format_version_from(varfull);
-    let program_version = sp_variant::get_program_version_from(varfull);
-    println!("Features: format={major}.{minor} variant={program_version}");
+    let environment = sp_variant::detect_environment();
+    json!({
+        "arch": sp_variant::get_host_arch(),
+        "environment": {
+            "container": environment.container,
+            "wsl": environment.wsl,
+        },
+        "init": sp_variant::detect_init_system().as_ref(),
+        "format": {
+            "version": {
+                "major": major,
+                "minor": minor,
+            },
+        },
+        "kind": var.kind,
+        "matched_rule": matched_rule,
+        "version": sp_variant::get_program_version_from(varfull),
+    })
+}
+
+/// Build the JSON object describing the current host for inventory purposes:
+/// the `detect --json` fields and the `features` ones.
+#[cfg(feature = "json")]
+fn inventory_json(varfull: &VariantDefTop, var: &Variant, matched_rule: Option<&str>) -> Value {
+    let (major, minor) = sp_variant::get_format_version_from(varfull);
+    let mut inventory = detect_json(varfull, var, matched_rule);
+    inventory["features"] = json!({
+        "format": format!("{major}.{minor}"),
+        "variant": sp_variant::get_program_version_from(varfull),
+    });
+    inventory
 }
 
+#[cfg(feature = "json")]
 #[allow(clippy::print_stdout)]
-fn cmd_detect(varfull: &VariantDefTop) -> Result<()> {
-    let var = detect_variant(varfull)?;
-    println!("{kind}", kind = var.kind.as_ref());
+fn cmd_inventory(varfull: &VariantDefTop, detect: &DetectOptions) -> Result<()> {
+    let (var, matched_rule) = detect_with_rule(varfull, detect)?;
+    println!(
+        "{json}",
+        json = serde_json::to_string(&inventory_json(varfull, var, matched_rule.as_deref()))
+            .context("Internal error: could not serialize the inventory data")?
+    );
     Ok(())
 }
 
+/// Determine the `detect --check` exit code from the detection result.
+fn detect_check_code(res: Result<&Variant, VariantError>) -> Result<u8> {
+    match res {
+        Ok(_) => Ok(0),
+        Err(VariantError::UnknownVariant) => Ok(EXIT_UNSUPPORTED),
+        Err(err) => Err(err).context("Could not detect the current build variant"),
+    }
+}
+
 #[allow(clippy::print_stdout)]
-fn run_command(cmdvec: &[String], action: &str, noop: bool) -> Result<()> {
-    let cmdstr = cmdvec.join(" ");
-    if noop {
-        println!("Would run `{cmdstr}`");
-        return Ok(());
+fn cmd_detect(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &DetectCmdConfig,
+) -> Result<ExitCode> {
+    if config.check || config.quiet {
+        let res = detect_current(varfull, detect);
+        return detect_check_code(res).map(ExitCode::from);
     }
 
-    let (name, args) = cmdvec
-        .split_first()
-        .with_context(|| format!("Internal error: empty '{action}' command"))?;
-    let status = Command::new(name)
-        .args(args)
-        .spawn()
-        .with_context(|| format!("{action}: {cmdstr}"))?
-        .wait()
-        .with_context(|| format!("{action}: {cmdstr}"))?;
-
-    if !status.success() {
-        match status.signal() {
-            None => match status.code() {
-                Some(code) => {
-                    bail!(format!("{action}: {cmdstr}: exit code {code}"));
-                }
-                None => {
-                    bail!(format!("{action}: {cmdstr}: exit status {status:?}"));
-                }
-            },
-            Some(sig) => {
-                bail!(format!("{action}: {cmdstr}: killed by signal {sig}"));
-            }
+    if config.explain {
+        println!("{line}", line = explain_detection(varfull, detect)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if config.all_matches {
+        for line in all_matches_lines(varfull, detect)? {
+            println!("{line}");
         }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    #[cfg(feature = "json")]
+    if config.json {
+        let (var, matched_rule) = detect_with_rule(varfull, detect)?;
+        println!(
+            "{json}",
+            json = serde_json::to_string(&detect_json(varfull, var, matched_rule.as_deref()))
+                .context("Internal error: could not serialize the detection result")?
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+    let var = detect_variant(varfull, detect)?;
+    println!("{kind}", kind = var.kind.as_ref());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Determine the ownership of the installed files: the specified one or root:root if possible.
+fn target_owner(owner: Option<Owner>, euid: Uid) -> Option<Owner> {
+    match owner {
+        Some(owner) => Some(owner),
+        None if euid.is_root() => Some(Owner::ROOT),
+        None => None,
     }
-    Ok(())
 }
 
+/// Copy a file without reading all of it into memory at once.
+///
+/// Comparing the file to the installed one and verifying the checksum need
+/// the whole contents anyway, so these are left to [`install_contents`].
 #[allow(clippy::print_stdout)]
-fn copy_file(fname: &str, srcdir: &str, dstdir: &str, noop: bool) -> Result<()> {
+fn copy_file(
+    fname: &str,
+    srcdir: &str,
+    dstdir: &str,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    manifest: &Manifest,
+) -> Result<()> {
+    if config.if_changed || config.verify_checksums {
+        let src = format!("{srcdir}/{fname}");
+        let contents = fsops
+            .read(&src)
+            .with_context(|| format!("Could not read from {src}"))?;
+        return install_contents(
+            src,
+            format!("{dstdir}/{fname}"),
+            contents,
+            config,
+            fsops,
+            manifest,
+        );
+    }
+
     let src = format!("{srcdir}/{fname}");
     let dst = format!("{dstdir}/{fname}");
-    println!("Copying {src} -> {dst}");
-
-    let read_source_file = || fs::read(&src).with_context(|| format!("Could not read from {src}"));
-
-    let write_destination_file = |contents: &Vec<u8>| {
-        let mut outfile = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&dst)
-            .with_context(|| format!("Could not open {dst} for writing"))?;
-        let mut perms = outfile
-            .metadata()
-            .with_context(|| format!("Could not examine the newly-created {dst}"))?
-            .permissions();
-        perms.set_mode(0o644);
-        outfile
-            .set_permissions(perms)
-            .with_context(|| format!("Could not change the mode on {dst}"))?;
-        unistd::fchown(
-            outfile.as_raw_fd(),
-            Some(Uid::from_raw(0)),
-            Some(Gid::from_raw(0)),
-        )
-        .with_context(|| format!("Could not set the ownership of {dst}"))?;
-        outfile
-            .write_all(contents)
-            .with_context(|| format!("Could not write to {dst}"))
+    info!("Copying {src} -> {dst}");
+
+    let bytes = if config.noop {
+        let size = fsops
+            .size(&src)
+            .with_context(|| format!("Could not examine {src}"))?;
+        if !config.dump_commands {
+            println!("Would write {size} bytes to {dst}");
+        }
+        size
+    } else {
+        let owner = prepare_write(&dst, config, fsops, manifest)?;
+        fsops
+            .copy(&src, &dst, 0o644, owner)
+            .with_context(|| format!("Could not copy {src} to {dst}"))?
     };
+    manifest.record(Action::Copy {
+        source: src,
+        destination: dst,
+        bytes: usize::try_from(bytes).context("Could not record the size of a copied file")?,
+    });
+    Ok(())
+}
+
+/// Back the destination file up if requested, determine the ownership of the new one.
+fn prepare_write(
+    dst: &str,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    manifest: &Manifest,
+) -> Result<Option<Owner>> {
+    if config.backup {
+        let backup = format!("{dst}{BACKUP_SUFFIX}");
+        if fsops
+            .backup(dst, &backup)
+            .with_context(|| format!("Could not back {dst} up to {backup}"))?
+        {
+            info!("Saved the previous {dst} file as {backup}");
+            manifest.record(Action::Backup {
+                path: dst.to_owned(),
+                backup,
+            });
+        }
+    }
+    let owner = target_owner(config.owner, Uid::effective());
+    if owner.is_none() {
+        warn!("Not running as root, not changing the ownership of {dst}");
+    }
+    Ok(owner)
+}
 
-    let contents = read_source_file()?;
+/// Compute the hex-encoded SHA-256 digest of some data.
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
 
-    if noop {
-        println!("Would write {count} bytes to {dst}", count = contents.len());
+/// Read a file back and make sure it contains exactly what was written.
+fn verify_checksum(fsops: &dyn FsOps, dst: &str, contents: &[u8]) -> Result<()> {
+    let expected = sha256_hex(contents);
+    let actual = sha256_hex(
+        &fsops
+            .read(dst)
+            .with_context(|| format!("Could not read {dst} back"))?,
+    );
+    ensure!(
+        actual == expected,
+        format!("Checksum mismatch for {dst}: expected SHA-256 {expected}, got {actual}")
+    );
+    debug!("Verified {dst}: SHA-256 {actual}");
+    Ok(())
+}
+
+/// The suffix added to the name of a replaced file when backing it up.
+const BACKUP_SUFFIX: &str = ".sp-bak";
+
+/// Install a file with the specified contents, e.g. read from another file.
+#[allow(clippy::print_stdout)]
+fn install_contents(
+    src: String,
+    dst: String,
+    contents: Vec<u8>,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    manifest: &Manifest,
+) -> Result<()> {
+    info!("Copying {src} -> {dst}");
+    if config.if_changed
+        && fsops
+            .read(&dst)
+            .map_or(false, |current| current == contents)
+    {
+        if !config.dump_commands {
+            println!("{dst}: unchanged");
+        }
         return Ok(());
     }
 
-    write_destination_file(&contents)?;
+    if config.noop {
+        if !config.dump_commands {
+            println!("Would write {count} bytes to {dst}", count = contents.len());
+        }
+    } else {
+        let owner = prepare_write(&dst, config, fsops, manifest)?;
+        fsops
+            .write(&dst, &contents, 0o644, owner)
+            .with_context(|| format!("Could not write to {dst}"))?;
+        if config.verify_checksums {
+            verify_checksum(fsops, &dst, &contents)?;
+        }
+    }
+    manifest.record(Action::Copy {
+        source: src,
+        destination: dst,
+        bytes: contents.len(),
+    });
     Ok(())
 }
 
+/// Render a repository definition file using the variant data, modify it, and install it.
+fn install_repo_file<F>(
+    var: &Variant,
+    repotype: RepoType,
+    vdir: &str,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    manifest: &Manifest,
+    transform: F,
+) -> Result<()>
+where
+    F: FnOnce(Vec<u8>) -> Result<Vec<u8>>,
+{
+    let (dst, contents) = var
+        .render_repo_file_with(repotype.extension(), vdir, |path| fsops.read(path))
+        .with_context(|| {
+            format!(
+                "Could not prepare the {repotype} repository file",
+                repotype = repotype.as_ref()
+            )
+        })?;
+    let fname = dst
+        .file_name()
+        .context("Internal error: no filename in a repository file path")?
+        .to_string_lossy();
+    install_contents(
+        format!("{vdir}/{fname}"),
+        dst.to_string_lossy().into_owned(),
+        transform(contents)?,
+        config,
+        fsops,
+        manifest,
+    )
+}
+
+/// Replace the value of a field in a repository definition file.
+fn rewrite_field(contents: Vec<u8>, prefix: &str, value: &str) -> Result<Vec<u8>> {
+    let text = String::from_utf8(contents)
+        .context("Could not parse a repository definition file as valid UTF-8")?;
+    Ok(sp_variant::replace_field(&text, prefix, value).into_bytes())
+}
+
+/// Replace the scheme and host part of a repository URL, keeping the path.
+fn mirror_url(url: &str, mirror: &str) -> String {
+    let path = url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.find('/').map(|idx| &rest[idx..]))
+        .unwrap_or("/");
+    format!("{mirror}{path}", mirror = mirror.trim_end_matches('/'))
+}
+
+/// Point the repository URLs in a repository definition file to a mirror, if specified.
+fn rewrite_base_url(contents: Vec<u8>, prefix: &str, mirror: Option<&str>) -> Result<Vec<u8>> {
+    let mirror = match mirror {
+        Some(mirror) => mirror,
+        None => return Ok(contents),
+    };
+    let text = String::from_utf8(contents)
+        .context("Could not parse a repository definition file as valid UTF-8")?;
+    Ok(text
+        .split_inclusive('\n')
+        .map(|line| match line.strip_prefix(prefix) {
+            Some(value) => {
+                let eol = if line.ends_with('\n') { "\n" } else { "" };
+                let urls = value
+                    .split_whitespace()
+                    .map(|url| mirror_url(url, mirror))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{prefix}{urls}{eol}")
+            }
+            None => line.to_owned(),
+        })
+        .collect::<String>()
+        .into_bytes())
+}
+
 fn get_filename<'path>(path: &'path str, tag: &str) -> Result<&'path str> {
     path.rsplit('/').next().with_context(|| {
         format!(
@@ -139,255 +599,1767 @@ fn get_filename<'path>(path: &'path str, tag: &str) -> Result<&'path str> {
     })
 }
 
-fn get_filename_extension<'fname>(
-    filename: &'fname str,
+/// Get the filenames of the keyring files for a single repository type.
+fn get_keyring_filenames<'path>(
+    keyrings: &'path Keyrings,
+    repotype: RepoType,
     tag: &str,
-) -> Result<(&'fname str, &'fname str)> {
-    filename
-        .rsplit_once('.')
-        .with_context(|| {
-            format!(
-                "Internal error: could not parse a {tag}: Could not split '{filename}' into a filename and extension"
-            )
+) -> Result<Vec<&'path str>> {
+    keyrings
+        .for_repotype(repotype.as_ref())
+        .iter()
+        .map(|path| get_filename(path, tag))
+        .collect()
+}
+
+/// Get the filenames of the keyring files that `repo add` installs, each one listed once.
+///
+/// In keyring-only mode, these are the keyring files for all the repository types;
+/// otherwise, only the ones for the selected repository types.
+fn get_installed_keyring_filenames<'path>(
+    keyrings: &'path Keyrings,
+    config: &RepoAddConfig,
+    tag: &str,
+) -> Result<Vec<&'path str>> {
+    let paths: Vec<&str> = if config.only_keyring {
+        keyrings.all()
+    } else {
+        config
+            .repotypes
+            .iter()
+            .flat_map(|repotype| keyrings.for_repotype(repotype.as_ref()))
+            .map(String::as_str)
+            .collect()
+    };
+    let mut res: Vec<&str> = Vec::new();
+    for path in paths {
+        let fname = get_filename(path, tag)?;
+        if !res.contains(&fname) {
+            res.push(fname);
+        }
+    }
+    Ok(res)
+}
+
+/// Build the space-separated list of the installed keyring files' paths.
+fn keyring_paths(prefix: &str, keyring_dir: &str, keyring_fnames: &[&str]) -> String {
+    keyring_fnames
+        .iter()
+        .map(|fname| format!("{prefix}{keyring_dir}/{fname}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Make sure that all the files to install are present and readable in the source directory.
+///
+/// This is done before any changes are made to the system, so that a missing file
+/// does not leave the repository configuration half-installed.
+fn check_source_files(fsops: &dyn FsOps, vdir: &str, fnames: &[String]) -> Result<()> {
+    let missing: Vec<&str> = fnames
+        .iter()
+        .filter(|fname| fsops.read(&format!("{vdir}/{fname}")).is_err())
+        .map(String::as_str)
+        .collect();
+    ensure!(
+        missing.is_empty(),
+        format!(
+            "Missing or unreadable files in {vdir}: {missing}",
+            missing = missing.join(", ")
+        )
+    );
+    Ok(())
+}
+
+/// A single file copy operation that may be run in a separate thread.
+type CopyJob<'job> = Box<dyn FnOnce() -> Result<()> + Send + 'job>;
+
+/// Copy the files one by one or, in parallel mode, concurrently.
+///
+/// In parallel mode all the copies are attempted, and all the failures are reported.
+fn run_copy_jobs(jobs: Vec<CopyJob<'_>>, parallel: bool) -> Result<()> {
+    if !parallel {
+        return jobs.into_iter().try_for_each(|job| job());
+    }
+
+    let mut errors: Vec<anyhow::Error> = thread::scope(|scope| {
+        let handles: Vec<_> = jobs.into_iter().map(|job| scope.spawn(job)).collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| match handle.join() {
+                Ok(res) => res.err(),
+                Err(_) => Some(anyhow::anyhow!(
+                    "Internal error: a file copy thread panicked"
+                )),
+            })
+            .collect()
+    });
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        count => {
+            let messages = errors
+                .iter()
+                .map(|err| format!("\n  - {err:#}"))
+                .collect::<String>();
+            Err(errors
+                .remove(0)
+                .context(format!("Could not copy {count} files:{messages}")))
+        }
+    }
+}
+
+/// Select the runner to use depending on the no-operation mode setting.
+fn get_runner(noop: bool) -> &'static dyn Runner {
+    if noop {
+        &NoopRunner
+    } else {
+        &ProcessRunner { cwd: None }
+    }
+}
+
+/// Set the proxy environment variables, if any, for a package manager command.
+///
+/// The command is run via `env` so that the variables are also shown in
+/// no-operation mode and in the `--dump-commands` output.
+fn with_proxy_env(config: &RepoAddConfig, cmdvec: Vec<String>) -> Vec<String> {
+    let vars: Vec<String> = [
+        ("http_proxy", &config.http_proxy),
+        ("https_proxy", &config.https_proxy),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.as_ref().map(|value| format!("{name}={value}")))
+    .collect();
+    if vars.is_empty() {
+        return cmdvec;
+    }
+    let rest = match cmdvec.split_first() {
+        Some((first, rest)) if first == "env" => rest,
+        _ => &cmdvec,
+    };
+    iter::once("env".to_owned())
+        .chain(vars)
+        .chain(rest.iter().cloned())
+        .collect()
+}
+
+/// Build the Yum option that sets the proxy, preferring the HTTPS one if both are specified.
+fn yum_proxy_option(config: &RepoAddConfig) -> Option<String> {
+    config
+        .https_proxy
+        .as_ref()
+        .or(config.http_proxy.as_ref())
+        .map(|proxy| format!("--setopt=proxy={proxy}"))
+}
+
+/// Run a command that updates the package database, retrying it if configured.
+fn run_update_command(
+    runner: &dyn Runner,
+    config: &RepoAddConfig,
+    cmdvec: &[String],
+) -> Result<()> {
+    runner::run_command_retry(
+        runner,
+        &with_proxy_env(config, cmdvec.to_vec()),
+        "Could not update the package database",
+        config.retries,
+        config.retry_delay,
+    )
+}
+
+/// The objects that `repo add` uses to make changes to the system.
+struct RepoAddContext<'ctx> {
+    /// Run the package manager and key import commands.
+    runner: &'ctx dyn Runner,
+
+    /// Read and install the repository files.
+    fsops: &'ctx dyn FsOps,
+
+    /// Record the files copied and the commands run.
+    manifest: &'ctx Manifest,
+}
+
+impl<'ctx> RepoAddContext<'ctx> {
+    /// Bundle the objects used to make changes.
+    const fn new(
+        runner: &'ctx dyn Runner,
+        fsops: &'ctx dyn FsOps,
+        manifest: &'ctx Manifest,
+    ) -> Self {
+        Self {
+            runner,
+            fsops,
+            manifest,
+        }
+    }
+}
+
+/// The files that `repo add` installed and the commands that it ran;
+/// in no-operation mode, the ones that it would have.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RepoAddReport {
+    /// The full paths to the installed files.
+    files_written: Vec<PathBuf>,
+
+    /// The package manager and key import commands.
+    commands_run: Vec<Vec<String>>,
+}
+
+impl RepoAddReport {
+    /// Summarize the actions recorded in the manifest.
+    fn from_actions(actions: &[Action]) -> Self {
+        actions.iter().fold(Self::default(), |mut report, action| {
+            match *action {
+                Action::Copy {
+                    ref destination, ..
+                } => report.files_written.push(PathBuf::from(destination)),
+                Action::Command { ref argv, .. } => report.commands_run.push(argv.clone()),
+                Action::Backup { .. } => (),
+            }
+            report
         })
+    }
+}
+
+/// Import the keys into the RPM database unless told not to.
+fn run_key_import(runner: &dyn Runner, config: &RepoAddConfig, cmdvec: &[String]) -> Result<()> {
+    if config.no_key_import {
+        info!(
+            "Not importing the keys into the RPM database: `{cmdstr}`",
+            cmdstr = cmdvec.join(" ")
+        );
+        return Ok(());
+    }
+    runner::run_command(
+        runner,
+        cmdvec,
+        "Could not import the StorPool RPM OpenPGP keys",
+    )
+}
+
+/// Install the keyring files using the library's key handling.
+struct RepoAddKeyring<'cfg, 'ctx> {
+    /// The `repo add` settings, e.g. the no-operation mode or the files' owner.
+    config: &'cfg RepoAddConfig,
+
+    /// The objects used to make changes.
+    ctx: &'cfg RepoAddContext<'ctx>,
+}
+
+impl KeyringInstaller for RepoAddKeyring<'_, '_> {
+    fn install_file(&self, srcdir: &str, fname: &str, dstdir: &str) -> Result<(), InstallerError> {
+        Ok(copy_file(
+            fname,
+            srcdir,
+            dstdir,
+            self.config,
+            self.ctx.fsops,
+            self.ctx.manifest,
+        )?)
+    }
+
+    fn import_keys(&self, cmdvec: &[String]) -> Result<(), InstallerError> {
+        Ok(run_key_import(self.ctx.runner, self.config, cmdvec)?)
+    }
 }
 
-fn repo_add_deb(var: &Variant, config: &RepoAddConfig, vdir: &str, repo: &DebRepo) -> Result<()> {
+fn repo_add_deb(
+    var: &Variant,
+    config: &RepoAddConfig,
+    ctx: &RepoAddContext<'_>,
+    vdir: &str,
+    repo: &DebRepo,
+) -> Result<()> {
+    let RepoAddContext {
+        runner,
+        fsops,
+        manifest,
+    } = *ctx;
     let install_req_packages = || {
         // First, install the ca-certificates package if required...
-        let mut cmdvec: Vec<String> = var
-            .commands
-            .get("package")
-            .with_context(|| {
-                format!(
-                    "Internal error: no 'package' command category for {kind}",
-                    kind = var.kind.as_ref()
-                )
-            })?
-            .get("install")
-            .with_context(|| {
-                format!(
-                    "Internal error: no 'package.install' command for {kind}",
-                    kind = var.kind.as_ref()
-                )
-            })?
-            .clone();
-        cmdvec.extend(repo.req_packages.iter().cloned());
-        run_command(
-            &cmdvec,
+        let cmdvec = var
+            .package_install_command(&repo.req_packages)
+            .context("Internal error: could not build the package install command")?;
+        runner::run_command(
+            runner,
+            &with_proxy_env(config, cmdvec),
             "Could not install the required packages",
-            config.noop,
         )
     };
 
-    let copy_sources_file = || {
-        let sources_orig = get_filename(&repo.sources, "Apt sources list")?;
-        let (sources_base, sources_ext) = get_filename_extension(sources_orig, "Apt sources list")?;
-        let sources_fname = format!(
-            "{sources_base}{repotype}.{sources_ext}",
-            repotype = config.repotype.extension()
-        );
-        copy_file(&sources_fname, vdir, "/etc/apt/sources.list.d", config.noop)
+    let keyring_fnames = get_installed_keyring_filenames(&repo.keyrings, config, "Apt keyring")?;
+    let keyring_dir = config
+        .keyring_dir
+        .as_deref()
+        .unwrap_or(keyring::APT_KEYRING_DIR);
+
+    let get_sources_fname = |repotype: &RepoType| {
+        repo.sources_filename(repotype.extension(), sp_variant::get_host_arch())
+    };
+
+    let mut required: Vec<String> = keyring_fnames
+        .iter()
+        .map(|&fname| fname.to_owned())
+        .collect();
+    if !config.only_keyring || repo.inline_key {
+        required.extend(config.repotypes.iter().map(get_sources_fname));
+    }
+    check_source_files(fsops, vdir, &required)?;
+
+    let copy_sources_file = |repotype: &RepoType| {
+        let repotype_fnames = get_keyring_filenames(&repo.keyrings, *repotype, "Apt keyring")?;
+        install_repo_file(var, *repotype, vdir, config, fsops, manifest, |contents| {
+            let contents = rewrite_base_url(contents, "URIs: ", config.mirror.as_deref())?;
+            if config.keyring_dir.is_some() && !repo.inline_key {
+                rewrite_field(
+                    contents,
+                    "Signed-By: ",
+                    &keyring_paths("", keyring_dir, &repotype_fnames),
+                )
+            } else {
+                Ok(contents)
+            }
+        })
     };
 
-    let copy_keyring_file = || {
-        let keyring_fname = get_filename(&repo.keyring, "Apt keyring")?;
-        copy_file(keyring_fname, vdir, "/usr/share/keyrings", config.noop)
+    let copy_files = |with_sources: bool| {
+        let mut jobs: Vec<CopyJob<'_>> = Vec::new();
+        if with_sources {
+            for repotype in &config.repotypes {
+                jobs.push(Box::new(move || copy_sources_file(repotype)));
+            }
+        }
+        if repo.inline_key {
+            debug!("The keys are embedded in the sources list files");
+        } else {
+            for keyring_fname in &keyring_fnames {
+                jobs.push(Box::new(move || {
+                    copy_file(keyring_fname, vdir, keyring_dir, config, fsops, manifest)
+                }));
+            }
+        }
+        run_copy_jobs(jobs, config.parallel)
     };
 
     let run_apt_update = || {
-        run_command(
-            &["apt-get".to_owned(), "update".to_owned()],
-            "Could not update the package database",
-            config.noop,
+        run_update_command(
+            runner,
+            config,
+            &[var.package_manager().tool().to_owned(), "update".to_owned()],
         )
     };
 
-    if !repo.req_packages.is_empty() {
+    if config.only_keyring {
+        if repo.inline_key {
+            // With inline keys, the sources list files are the ones that carry the keys.
+            return copy_files(true);
+        }
+        return Ok(keyring::install_keyring(
+            var,
+            &config.repodir,
+            config.keyring_dir.as_deref(),
+            &RepoAddKeyring { config, ctx },
+        )?);
+    }
+
+    if !config.offline && !repo.req_packages.is_empty() {
         run_apt_update()?;
         install_req_packages()?;
     }
-    copy_sources_file()?;
-    copy_keyring_file()?;
-    run_apt_update()?;
+    copy_files(true)?;
+    if !config.offline && !config.no_update {
+        run_apt_update()?;
+    }
     Ok(())
 }
 
-fn repo_add_yum(config: &RepoAddConfig, vdir: &str, repo: &YumRepo) -> Result<()> {
+fn repo_add_yum(
+    var: &Variant,
+    config: &RepoAddConfig,
+    ctx: &RepoAddContext<'_>,
+    vdir: &str,
+    repo: &YumRepo,
+) -> Result<()> {
+    let RepoAddContext {
+        runner,
+        fsops,
+        manifest,
+    } = *ctx;
     let run_yum_install_certs = || {
-        run_command(
-            &[
-                "yum".to_owned(),
-                "--disablerepo=storpool-*".to_owned(),
-                "install".to_owned(),
-                "-q".to_owned(),
-                "-y".to_owned(),
-                "ca-certificates".to_owned(),
-            ],
+        let cmdvec: Vec<String> = iter::once(var.package_manager().tool().to_owned())
+            .chain(yum_proxy_option(config))
+            .chain(
+                ["--disablerepo=storpool-*", "install", "-q", "-y"]
+                    .into_iter()
+                    .chain(var.required_packages())
+                    .map(ToOwned::to_owned),
+            )
+            .collect();
+        runner::run_command(
+            runner,
+            &with_proxy_env(config, cmdvec),
             "Could not update the package database",
-            config.noop,
         )
     };
 
-    let copy_yumdef_file = || {
-        let yumdef_orig = get_filename(&repo.yumdef, "Yum repository definition")?;
-        let (yumdef_base, yumdef_ext) =
-            get_filename_extension(yumdef_orig, "Yum repository definition")?;
-        let yumdef_fname = format!(
-            "{yumdef_base}{repotype}.{yumdef_ext}",
-            repotype = config.repotype.extension()
-        );
-        copy_file(&yumdef_fname, vdir, "/etc/yum.repos.d", config.noop)
+    let keyring_fnames = get_installed_keyring_filenames(&repo.keyrings, config, "Yum keyring")?;
+    let keyring_dir = config
+        .keyring_dir
+        .as_deref()
+        .unwrap_or(keyring::YUM_KEYRING_DIR);
+
+    let get_yumdef_fname =
+        |repotype: &RepoType| sp_variant::repo_filename(&repo.yumdef, repotype.extension());
+
+    let copy_yumdef_file = |repotype: &RepoType| {
+        let repotype_fnames = get_keyring_filenames(&repo.keyrings, *repotype, "Yum keyring")?;
+        install_repo_file(var, *repotype, vdir, config, fsops, manifest, |contents| {
+            let contents = rewrite_base_url(contents, "baseurl=", config.mirror.as_deref())?;
+            if config.keyring_dir.is_some() {
+                rewrite_field(
+                    contents,
+                    "gpgkey=",
+                    &keyring_paths("file://", keyring_dir, &repotype_fnames),
+                )
+            } else {
+                Ok(contents)
+            }
+        })
     };
 
-    let keyring_fname = get_filename(&repo.keyring, "Yum keyring")?;
-    let copy_keyring_file = || copy_file(keyring_fname, vdir, "/etc/pki/rpm-gpg", config.noop);
-
-    let run_rpmkeys = || {
-        if Path::new("/usr/bin/rpmkeys").exists() {
-            run_command(
-                &[
-                    "rpmkeys".to_owned(),
-                    "--import".to_owned(),
-                    format!("/etc/pki/rpm-gpg/{keyring_fname}"),
-                ],
-                "Could not import the StorPool RPM OpenPGP keys",
-                config.noop,
-            )
-        } else {
-            Ok(())
+    let copy_files = |with_yumdefs: bool| {
+        let mut jobs: Vec<CopyJob<'_>> = Vec::new();
+        if with_yumdefs {
+            for repotype in &config.repotypes {
+                jobs.push(Box::new(move || copy_yumdef_file(repotype)));
+            }
         }
+        for keyring_fname in &keyring_fnames {
+            jobs.push(Box::new(move || {
+                copy_file(keyring_fname, vdir, keyring_dir, config, fsops, manifest)
+            }));
+        }
+        run_copy_jobs(jobs, config.parallel)
+    };
+
+    let run_rpmkeys = || match keyring::key_import_command_with(var, keyring_dir, &keyring_fnames) {
+        Some(cmdvec) => run_key_import(runner, config, &cmdvec),
+        None => Ok(()),
     };
 
     let run_yum_clean_metadata = || {
-        run_command(
-            &[
-                "yum".to_owned(),
-                "--disablerepo=*".to_owned(),
-                format!(
-                    "--enablerepo=storpool-{repotype}",
-                    repotype = config.repotype.as_ref()
-                ),
-                "clean".to_owned(),
-                "metadata".to_owned(),
-            ],
-            "Could not update the package database",
-            config.noop,
-        )
+        let cmdvec: Vec<String> = [
+            var.package_manager().tool().to_owned(),
+            "--disablerepo=*".to_owned(),
+        ]
+        .into_iter()
+        .chain(config.repotypes.iter().map(|repotype| {
+            format!(
+                "--enablerepo=storpool-{repotype}",
+                repotype = repotype.as_ref()
+            )
+        }))
+        .chain(["clean".to_owned(), "metadata".to_owned()])
+        .collect();
+        run_update_command(runner, config, &cmdvec)
     };
 
-    run_yum_install_certs()?;
-    copy_yumdef_file()?;
-    copy_keyring_file()?;
+    let mut required: Vec<String> = keyring_fnames
+        .iter()
+        .map(|&fname| fname.to_owned())
+        .collect();
+    if !config.only_keyring {
+        required.extend(config.repotypes.iter().map(get_yumdef_fname));
+    }
+    check_source_files(fsops, vdir, &required)?;
+
+    if config.only_keyring {
+        return Ok(keyring::install_keyring(
+            var,
+            &config.repodir,
+            config.keyring_dir.as_deref(),
+            &RepoAddKeyring { config, ctx },
+        )?);
+    }
+
+    if !config.offline {
+        run_yum_install_certs()?;
+    }
+    copy_files(true)?;
     run_rpmkeys()?;
-    run_yum_clean_metadata()?;
+    if !config.offline && !config.no_update {
+        run_yum_clean_metadata()?;
+    }
     Ok(())
 }
 
-fn cmd_repo_add(varfull: &VariantDefTop, config: &RepoAddConfig) -> Result<()> {
-    let var = detect_variant(varfull)?;
-    let vdir = format!(
-        "{repodir}/{kind}",
-        repodir = config.repodir,
-        kind = var.kind.as_ref()
-    );
-    ensure!(
-        fs::metadata(&vdir)
-            .with_context(|| format!("Could not examine {vdir:?}"))?
-            .is_dir(),
-        format!("Not a directory: {vdir:?}")
-    );
+/// Install the repository files, update the package database if needed.
+///
+/// With `--if-changed`, nothing at all is done if all the files are already
+/// installed with the same contents.
+fn repo_add(
+    var: &Variant,
+    config: &RepoAddConfig,
+    ctx: &RepoAddContext<'_>,
+    vdir: &str,
+) -> Result<()> {
+    if config.if_changed && !repo_files_changed(var, config, ctx.fsops, vdir)? {
+        if !config.dump_commands {
+            println!("The StorPool repository files are unchanged");
+        }
+        return Ok(());
+    }
+    repo_add_variant(var, config, ctx, vdir)
+}
+
+/// Install the repository files for the variant's package manager.
+fn repo_add_variant(
+    var: &Variant,
+    config: &RepoAddConfig,
+    ctx: &RepoAddContext<'_>,
+    vdir: &str,
+) -> Result<()> {
     match var.repo {
-        Repo::Deb(ref deb) => repo_add_deb(var, config, &vdir, deb),
-        Repo::Yum(ref yum) => repo_add_yum(config, &vdir, yum),
+        Repo::Deb(ref deb) => repo_add_deb(var, config, ctx, vdir, deb),
+        Repo::Yum(ref yum) => repo_add_yum(var, config, ctx, vdir, yum),
         _ => bail!("Internal error: unhandled repo type"),
-    }
+    }?;
+    run_post_add(var, config, ctx.runner)
 }
 
-#[allow(clippy::print_stdout)]
-fn cmd_command_list(varfull: &VariantDefTop) -> Result<()> {
-    fn sorted_by_key<K, T>(map: &HashMap<K, T>) -> Vec<(&K, &T)>
-    where
-        K: Ord,
+/// Run the variant-specific commands needed after the repository files are installed.
+///
+/// The commands are not run if only the keyring files are installed or in offline mode.
+fn run_post_add(var: &Variant, config: &RepoAddConfig, runner: &dyn Runner) -> Result<()> {
+    if var.post_add.is_empty() || config.only_keyring {
+        return Ok(());
+    }
+    if config.offline {
+        info!(
+            "Offline mode: not running the {count} post-add commands",
+            count = var.post_add.len()
+        );
+        return Ok(());
+    }
+    for cmdvec in &var.post_add {
+        runner::run_command(
+            runner,
+            &with_proxy_env(config, cmdvec.clone()),
+            "Could not run a post-add command",
+        )?;
+    }
+    Ok(())
+}
+
+/// Check whether `repo add --if-changed` would write any files.
+///
+/// The files are examined by a quiet no-operation offline run that records
+/// the copies it would make.
+fn repo_files_changed(
+    var: &Variant,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    vdir: &str,
+) -> Result<bool> {
+    let check_config = RepoAddConfig {
+        offline: true,
+        ..config.clone()
+    };
+    let changed = planned_actions(var, &check_config, fsops, vdir)?
+        .iter()
+        .any(|action| matches!(*action, Action::Copy { .. }));
+    Ok(changed)
+}
+
+/// Determine the files that `repo add` would install and the commands it would run.
+///
+/// The actions are recorded by a quiet no-operation run.
+fn planned_actions(
+    var: &Variant,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    vdir: &str,
+) -> Result<Vec<Action>> {
+    let check_config = RepoAddConfig {
+        noop: true,
+        dump_commands: true,
+        ..config.clone()
+    };
+    let manifest = Manifest::default();
+    let runner = RecordingRunner::new(&SkipRunner, &manifest, true);
+    repo_add_variant(
+        var,
+        &check_config,
+        &RepoAddContext::new(&runner, fsops, &manifest),
+        vdir,
+    )?;
+    let actions = manifest.actions().clone();
+    Ok(actions)
+}
+
+/// Describe the files that would be installed and the commands that would be run.
+fn describe_actions(actions: &[Action]) -> Vec<String> {
+    actions
+        .iter()
+        .filter_map(|action| match *action {
+            Action::Copy {
+                ref destination, ..
+            } => Some(format!("install {destination}")),
+            Action::Command { ref argv, .. } => {
+                Some(format!("run {cmdstr}", cmdstr = shell_command_line(argv)))
+            }
+            Action::Backup { .. } => None,
+        })
+        .collect()
+}
+
+/// Display the changes that `repo add` would make and ask whether to go ahead.
+fn confirm_changes<R: BufRead, W: Write>(
+    changes: &[String],
+    mut input: R,
+    mut output: W,
+) -> Result<bool> {
+    let mut prompt = || -> io::Result<String> {
+        writeln!(output, "The following changes will be made:")?;
+        for change in changes {
+            writeln!(output, "  {change}")?;
+        }
+        write!(output, "Proceed? [y/N] ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        input.read_line(&mut answer)?;
+        Ok(answer)
+    };
+    let answer = prompt().context("Could not ask for confirmation")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Make sure that `repo add` can write to the system directories and change the files' ownership.
+///
+/// The check is skipped in no-operation mode and if the owner of the files is specified.
+fn check_privileges(config: &RepoAddConfig, euid: Uid) -> Result<()> {
+    ensure!(
+        config.noop || config.owner.is_some() || euid.is_root(),
+        "The 'repo add' command must be run as root; use --noop to only show what it would do"
+    );
+    Ok(())
+}
+
+/// Look for an executable file in the directories listed in a search path.
+fn find_in_path(name: &str, path: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            fs::metadata(candidate).map_or(false, |meta| {
+                meta.is_file() && meta.permissions().mode() & 0o111 != 0
+            })
+        })
+}
+
+/// Make sure that the package manager is installed before making any changes.
+///
+/// The check is skipped if `repo add` will not run the package manager at all:
+/// in no-operation, offline, and keyring-only modes.
+fn check_package_manager(
+    var: &Variant,
+    config: &RepoAddConfig,
+    path: Option<&OsStr>,
+) -> Result<()> {
+    if config.noop || config.offline || config.only_keyring {
+        return Ok(());
+    }
+    let tool = var.package_manager().tool();
+    match path.and_then(|path| find_in_path(tool, path)) {
+        Some(found) => {
+            debug!("Found {found}", found = found.display());
+            Ok(())
+        }
+        None => bail!(
+            "Could not find the {tool} tool in the search path; is this really a {kind} system?",
+            kind = var.kind
+        ),
+    }
+}
+
+/// Build a command line with the words quoted for the shell.
+fn shell_command_line(argv: &[String]) -> String {
+    argv.iter()
+        .map(|word| shell_quote(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Make sure that the variant-specific repository files directory exists.
+fn check_repo_dir(vdir: &str) -> Result<()> {
+    ensure!(
+        fs::metadata(vdir)
+            .with_context(|| format!("Could not examine {vdir:?}"))?
+            .is_dir(),
+        format!("Not a directory: {vdir:?}")
+    );
+    Ok(())
+}
+
+/// Load the repository files into memory if the repository directory path names an archive.
+#[cfg(feature = "tarball")]
+fn load_archive(config: &RepoAddConfig, var: &Variant) -> Result<Option<ArchiveFs<'static>>> {
+    if !archive::is_archive(&config.repodir) {
+        return Ok(None);
+    }
+    let loaded = ArchiveFs::load(&config.repodir, &RealFs)?;
+    ensure!(
+        loaded.has_dir(var.kind.as_ref()),
+        format!(
+            "No {kind} directory in the {repodir} archive",
+            kind = var.kind.as_ref(),
+            repodir = config.repodir
+        )
+    );
+    Ok(Some(loaded))
+}
+
+fn cmd_repo_add(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &RepoAddConfig,
+) -> Result<RepoAddReport> {
+    let var = detect_variant(varfull, detect)?;
+    let vdir = format!(
+        "{repodir}/{kind}",
+        repodir = config.repodir,
+        kind = var.kind.as_ref()
+    );
+    #[cfg(feature = "tarball")]
+    let loaded = load_archive(config, var)?;
+    #[cfg(feature = "tarball")]
+    let fsops: &dyn FsOps = match loaded {
+        Some(ref loaded) => loaded,
+        None => {
+            check_repo_dir(&vdir)?;
+            &RealFs
+        }
+    };
+    #[cfg(not(feature = "tarball"))]
+    let fsops: &dyn FsOps = {
+        check_repo_dir(&vdir)?;
+        &RealFs
+    };
+    check_privileges(config, Uid::effective())?;
+    check_package_manager(var, config, env::var_os("PATH").as_deref())?;
+    for (tag, url) in [
+        ("mirror", &config.mirror),
+        ("HTTP proxy", &config.http_proxy),
+        ("HTTPS proxy", &config.https_proxy),
+    ] {
+        if let Some(ref url) = *url {
+            ensure!(
+                url.contains("://"),
+                format!("Not a valid {tag} URL: {url:?}")
+            );
+        }
+    }
+    if config.offline {
+        info!("Offline mode: the package database will not be updated");
+    }
+    if !config.noop
+        && !config.assume_yes
+        && unistd::isatty(io::stdin().as_raw_fd()).unwrap_or(false)
     {
-        let mut res: Vec<_> = map.iter().collect();
-        res.sort_by_key(|&(key, _)| key);
-        res
+        let changes = describe_actions(&planned_actions(var, config, fsops, &vdir)?);
+        if !confirm_changes(&changes, io::stdin().lock(), io::stderr())? {
+            bail!("Aborted, no changes made");
+        }
+    }
+    repo_add_report(var, config, fsops, &vdir)
+}
+
+/// Install the repository files, run the commands, and report what was done.
+fn repo_add_report(
+    var: &Variant,
+    config: &RepoAddConfig,
+    fsops: &dyn FsOps,
+    vdir: &str,
+) -> Result<RepoAddReport> {
+    let manifest = Manifest::default();
+    let inner: &dyn Runner = if config.dump_commands {
+        &SkipRunner
+    } else {
+        get_runner(config.noop)
+    };
+    let runner = RecordingRunner::new(inner, &manifest, config.noop);
+    let ctx = RepoAddContext::new(&runner, fsops, &manifest);
+    repo_add(var, config, &ctx, vdir)?;
+    #[cfg(feature = "json")]
+    if let Some(ref path) = config.manifest {
+        manifest.write(path)?;
+    }
+    let report = RepoAddReport::from_actions(&manifest.actions());
+    Ok(report)
+}
+
+/// Output the commands that `repo add --dump-commands` would run.
+#[allow(clippy::print_stdout)]
+fn show_repo_add_report(config: &RepoAddConfig, report: &RepoAddReport) {
+    if config.dump_commands {
+        for argv in &report.commands_run {
+            println!("{line}", line = shell_command_line(argv));
+        }
+    }
+}
+
+#[allow(clippy::print_stdout)]
+fn cmd_repo_requirements(varfull: &VariantDefTop, detect: &DetectOptions) -> Result<()> {
+    let var = detect_variant(varfull, detect)?;
+    for package in var.required_packages() {
+        println!("{package}");
+    }
+    Ok(())
+}
+
+/// Get the elements of a map sorted by their keys.
+fn sorted_by_key<K, T>(map: &HashMap<K, T>) -> Vec<(&K, &T)>
+where
+    K: Ord,
+{
+    let mut res: Vec<_> = map.iter().collect();
+    res.sort_by_key(|&(key, _)| key);
+    res
+}
+
+/// Build the lines describing the distribution-specific commands, optionally filtered.
+fn command_list_lines(var: &Variant, config: &CommandListConfig) -> Result<Vec<String>> {
+    if let Some(ref category) = config.category {
+        ensure!(
+            var.commands.contains_key(category),
+            format!(
+                "No '{category}' command category defined for the {kind} variant",
+                kind = var.kind
+            )
+        );
     }
 
-    let var = detect_variant(varfull)?;
+    let mut lines = Vec::new();
     for (category, cmap) in sorted_by_key(&var.commands) {
+        if config
+            .category
+            .as_ref()
+            .map_or(false, |wanted| wanted != category)
+        {
+            continue;
+        }
         for (name, cmd) in sorted_by_key(cmap) {
-            if category == "pkgfile" && name == "install" {
-                println!("{category}.{name}: ...");
+            if config.names_only {
+                lines.push(format!("{category}.{name}"));
+            } else if !config.show_all
+                && var
+                    .templated_commands
+                    .contains(&format!("{category}.{name}"))
+            {
+                lines.push(format!("{category}.{name}: ..."));
             } else {
-                println!("{category}.{name}: {cmd}", cmd = cmd.join(" "));
+                lines.push(format!("{category}.{name}: {cmd}", cmd = cmd.join(" ")));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+#[allow(clippy::print_stdout)]
+fn cmd_command_list(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &CommandListConfig,
+) -> Result<()> {
+    let var = detect_variant(varfull, detect)?;
+    for line in command_list_lines(var, config)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Get a variant's commands keyed by their "category.name" identifiers.
+#[cfg(feature = "json")]
+fn commands_by_id(var: &Variant) -> BTreeMap<String, &[String]> {
+    sorted_by_key(&var.commands)
+        .into_iter()
+        .flat_map(|(category, cmap)| {
+            sorted_by_key(cmap)
+                .into_iter()
+                .map(move |(name, cmd)| (format!("{category}.{name}"), cmd.as_slice()))
+        })
+        .collect()
+}
+
+/// Build the lines describing the commands that differ between two variants.
+#[cfg(feature = "json")]
+fn compare_command_lines(first: &Variant, second: &Variant) -> Vec<String> {
+    compare_commands_labeled((first.kind.as_ref(), first), (second.kind.as_ref(), second))
+}
+
+/// Build the lines describing the commands that differ between two variants,
+/// referring to them using the specified labels.
+#[cfg(feature = "json")]
+fn compare_commands_labeled(
+    (first_label, first): (&str, &Variant),
+    (second_label, second): (&str, &Variant),
+) -> Vec<String> {
+    let first_cmds = commands_by_id(first);
+    let second_cmds = commands_by_id(second);
+    let all_ids: BTreeSet<&String> = first_cmds.keys().chain(second_cmds.keys()).collect();
+
+    let mut lines = Vec::new();
+    for cmd_id in all_ids {
+        match (first_cmds.get(cmd_id), second_cmds.get(cmd_id)) {
+            (Some(first_cmd), Some(second_cmd)) => {
+                if first_cmd != second_cmd {
+                    lines.push(format!("{cmd_id}: differs"));
+                    lines.push(format!("  {first_label}: {cmd}", cmd = first_cmd.join(" ")));
+                    lines.push(format!(
+                        "  {second_label}: {cmd}",
+                        cmd = second_cmd.join(" ")
+                    ));
+                }
+            }
+            (Some(_), None) => lines.push(format!("{cmd_id}: only in {first_label}")),
+            (None, Some(_)) => lines.push(format!("{cmd_id}: only in {second_label}")),
+            (None, None) => unreachable!(),
+        }
+    }
+    lines
+}
+
+/// Build the lines describing the differences between the embedded variant
+/// definitions and the ones loaded from a file.
+///
+/// The variants that are only defined on one side are reported as added or
+/// removed; for the rest, the differing commands are listed in detail and
+/// any other differing fields (e.g. `repo`) only by name.
+#[cfg(feature = "json")]
+fn diff_embedded_lines(embedded: &VariantDefTop, loaded: &VariantDefTop) -> Result<Vec<String>> {
+    let embedded_vars = sp_variant::get_all_variants_from(embedded);
+    let loaded_vars = sp_variant::get_all_variants_from(loaded);
+    let all_kinds: BTreeSet<&str> = embedded_vars
+        .keys()
+        .chain(loaded_vars.keys())
+        .map(AsRef::as_ref)
+        .collect();
+
+    let mut lines = Vec::new();
+    for kind in all_kinds {
+        let (old, new) = match (
+            sp_variant::get_from(embedded, kind),
+            sp_variant::get_from(loaded, kind),
+        ) {
+            (Ok(old), Ok(new)) => (old, new),
+            (Ok(_), Err(_)) => {
+                lines.push(format!("{kind}: removed"));
+                continue;
+            }
+            (Err(_), Ok(_)) => {
+                lines.push(format!("{kind}: added"));
+                continue;
+            }
+            (Err(_), Err(_)) => unreachable!(),
+        };
+        if old == new {
+            continue;
+        }
+
+        lines.push(format!("{kind}: changed"));
+        lines.extend(
+            compare_commands_labeled(("embedded", old), ("file", new))
+                .into_iter()
+                .map(|line| format!("  {line}")),
+        );
+        let to_object = |var: &Variant| -> Result<serde_json::Map<String, Value>> {
+            match serde_json::to_value(var)
+                .context("Internal error: could not serialize a variant")?
+            {
+                Value::Object(fields) => Ok(fields),
+                _ => bail!("Internal error: a variant was not serialized as an object"),
+            }
+        };
+        let (old_fields, new_fields) = (to_object(old)?, to_object(new)?);
+        let all_fields: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+        for field in all_fields {
+            if field != "commands" && old_fields.get(field) != new_fields.get(field) {
+                lines.push(format!("  {field}: differs"));
             }
         }
     }
+    Ok(lines)
+}
+
+#[cfg(feature = "json")]
+#[allow(clippy::print_stdout)]
+fn cmd_show_diff_embedded(varfull: &VariantDefTop, path: Option<&str>) -> Result<()> {
+    path.context("The show --diff-embedded command needs the --variants-file option")?;
+    for line in diff_embedded_lines(sp_variant::build_variants(), varfull)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[allow(clippy::print_stdout)]
+fn cmd_show_compare(varfull: &VariantDefTop, config: &CompareConfig) -> Result<()> {
+    let first = sp_variant::get_from(varfull, &config.first).context("Invalid variant name")?;
+    let second = sp_variant::get_from(varfull, &config.second).context("Invalid variant name")?;
+    for line in compare_command_lines(first, second) {
+        println!("{line}");
+    }
     Ok(())
 }
 
-fn cmd_command_run(varfull: &VariantDefTop, config: CommandRunConfig) -> Result<()> {
-    let var = detect_variant(varfull)?;
-    let cmap = var
-        .commands
-        .get(&config.category)
-        .context("Unknown command category identifier")?;
-    let mut cmd_vec: Vec<String> = cmap
-        .get(&config.name)
+/// Read additional command arguments, one per line, skipping empty lines.
+fn read_args<R: BufRead>(reader: R) -> Result<Vec<String>> {
+    reader
+        .lines()
+        .map(|line| {
+            line.map(|line| line.trim_end().to_owned())
+                .context("Could not read the command arguments")
+        })
+        .filter(|line| line.as_ref().map_or(true, |line| !line.is_empty()))
+        .collect()
+}
+
+/// Quote a string so that a POSIX shell will pass it as a single word.
+fn shell_quote(word: &str) -> String {
+    let is_safe = |chr: char| chr.is_ascii_alphanumeric() || "%+,-./:=@_".contains(chr);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.to_owned()
+    } else {
+        format!("'{quoted}'", quoted = word.replace('\'', "'\\''"))
+    }
+}
+
+/// Check whether a `${NAME}` token names one of our variables, e.g. `${CODENAME}`.
+///
+/// Only uppercase names are recognized, so that other tokens, e.g. the `${Package}`
+/// field references in the `dpkg-query` output format, are left alone.
+fn is_template_name(name: &str) -> bool {
+    name.starts_with(|chr: char| chr.is_ascii_uppercase())
+        && name
+            .chars()
+            .all(|chr| chr.is_ascii_uppercase() || chr.is_ascii_digit() || chr == '_')
+}
+
+/// Replace the `${NAME}` tokens in a single word of a command.
+fn expand_word(word: &str, lookup: &dyn Fn(&str) -> Result<String>) -> Result<String> {
+    let mut res = String::with_capacity(word.len());
+    let mut rest = word;
+    while let Some((before, after)) = rest.split_once("${") {
+        res.push_str(before);
+        match after.split_once('}') {
+            Some((name, tail)) if is_template_name(name) => {
+                res.push_str(&lookup(name)?);
+                rest = tail;
+            }
+            _ => {
+                res.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// Get the value to substitute for a `${NAME}` token in a distribution-specific command.
+///
+/// The os-release file is only looked up if the `${VERSION_ID}` value is needed.
+fn template_value(
+    var: &Variant,
+    config: &CommandRunConfig,
+    os_release: &dyn Fn() -> Result<PathBuf>,
+    name: &str,
+) -> Result<String> {
+    match name {
+        "REPO_TYPE" => Ok(config.repotype.as_ref().to_owned()),
+        "CODENAME" => match var.repo {
+            Repo::Deb(ref deb) => Ok(deb.codename.clone()),
+            _ => bail!(
+                "No codename defined for the {kind} variant",
+                kind = var.kind
+            ),
+        },
+        "VERSION_ID" => {
+            let os_release = os_release()?;
+            sp_variant::yai::parse(&os_release)
+                .with_context(|| format!("Could not parse {path}", path = os_release.display()))?
+                .remove("VERSION_ID")
+                .with_context(|| format!("No VERSION_ID in {path}", path = os_release.display()))
+        }
+        other => bail!("Unknown variable '${{{other}}}' in the command"),
+    }
+}
+
+/// Build the command to run: the distribution-specific command followed by the arguments.
+///
+/// The `${REPO_TYPE}`, `${CODENAME}`, and `${VERSION_ID}` tokens in
+/// the distribution-specific command are replaced with their values.
+/// The `${VERSION_ID}` value is read from the same os-release file as the one
+/// used for detecting the variant.
+/// In shell mode, all the words are quoted and passed to `sh -c` as a single string.
+/// The wrapper command, if any, is prepended to the result.
+fn build_command(
+    var: &Variant,
+    config: &CommandRunConfig,
+    detect: &DetectConfig,
+    args: &[String],
+) -> Result<Vec<String>> {
+    let os_release = || {
+        sp_variant::os_release_path(detect)
+            .context("Could not look for the os-release file")?
+            .context("No os-release file found")
+    };
+    let lookup = |name: &str| template_value(var, config, &os_release, name);
+    let command = var
+        .command(&config.category, &config.name)
         .context("Unknown command identifier")?
-        .clone();
-    cmd_vec.extend(config.args);
-    run_command(&cmd_vec, "Command failed", config.noop)
+        .iter()
+        .map(|word| expand_word(word, &lookup))
+        .collect::<Result<Vec<_>>>()?;
+    let all_args = config.args.iter().chain(args.iter());
+    let cmd_vec: Vec<String> = if config.shell {
+        let cmdstr = command
+            .iter()
+            .chain(all_args)
+            .map(|word| shell_quote(word))
+            .collect::<Vec<_>>()
+            .join(" ");
+        vec!["sh".to_owned(), "-c".to_owned(), cmdstr]
+    } else {
+        command.iter().chain(all_args).cloned().collect()
+    };
+    Ok(config.wrap.iter().cloned().chain(cmd_vec).collect())
+}
+
+/// Check whether a word is a `NAME=value` assignment to an environment variable.
+#[cfg(feature = "json")]
+fn env_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|&(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|chr: char| chr.is_ascii_digit())
+            && name
+                .chars()
+                .all(|chr| chr.is_ascii_alphanumeric() || chr == '_')
+    })
+}
+
+/// Get the variables that an `env NAME=value... command` invocation sets.
+///
+/// The assignments end at the first word that does not look like one; if that is
+/// another `env` invocation, e.g. a wrapper around a variant's command, its
+/// assignments are also collected, the later ones overriding the earlier ones.
+#[cfg(feature = "json")]
+fn env_assignments(cmd_vec: &[String]) -> BTreeMap<&str, &str> {
+    let mut res = BTreeMap::new();
+    let mut rest = cmd_vec;
+    while let Some((first, args)) = rest.split_first() {
+        if first != "env" {
+            break;
+        }
+        rest = args;
+        while let Some((name, value)) = rest.first().and_then(|word| env_assignment(word)) {
+            res.insert(name, value);
+            rest = &rest[1..];
+        }
+    }
+    res
+}
+
+/// Describe the way the command would be run: the arguments, directory, and environment.
+///
+/// The environment only lists the variables set via an `env` wrapper; the command
+/// otherwise inherits the environment of the storpool_variant process.
+#[cfg(feature = "json")]
+fn command_invocation(cmd_vec: &[String], dir: Option<&Path>) -> Result<Value> {
+    let current = env::current_dir().context("Could not determine the current directory")?;
+    let cwd = dir.map_or_else(|| current.clone(), |dir| current.join(dir));
+    Ok(json!({
+        "command": cmd_vec,
+        "cwd": cwd,
+        "env": env_assignments(cmd_vec),
+    }))
 }
 
 #[allow(clippy::print_stdout)]
-fn cmd_show(varfull: &VariantDefTop, config: &ShowConfig) -> Result<()> {
-    if config.name == "all" {
+fn cmd_command_run(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &CommandRunConfig,
+) -> Result<()> {
+    let cwd = config.cwd.as_deref().map(check_cwd).transpose()?;
+    let var = detect_variant(varfull, detect)?;
+    let stdin_args = if config.args_stdin {
+        read_args(io::stdin().lock())?
+    } else {
+        Vec::new()
+    };
+    if config.shell {
+        info!("Running the command via `sh -c`");
+    }
+    let cmd_vec = build_command(var, config, &detect.config, &stdin_args)?;
+    #[cfg(feature = "json")]
+    if config.dump_env {
         println!(
             "{json}",
-            json = serde_json::to_string(varfull)
-                .context("Internal error: could not serialize the variant data")?
+            json = serde_json::to_string_pretty(&command_invocation(&cmd_vec, cwd)?)
+                .context("Internal error: could not serialize the command invocation")?
         );
+        return Ok(());
+    }
+    if config.noop {
+        run_distro_command(&NoopRunner, &cmd_vec, config.ignore_failure)
     } else {
-        let var = match &*config.name {
-            "current" => {
-                sp_variant::detect_from(varfull).context("Cannot detect the current variant")?
-            }
-            other => sp_variant::get_from(varfull, other).context("Invalid variant name")?,
+        run_distro_command(&ProcessRunner { cwd }, &cmd_vec, config.ignore_failure)
+    }
+}
+
+/// Make sure that the directory to run a command in exists.
+fn check_cwd(dir: &str) -> Result<&Path> {
+    let path = Path::new(dir);
+    ensure!(
+        path.is_dir(),
+        format!("The {dir} working directory does not exist")
+    );
+    Ok(path)
+}
+
+/// Parse a `category.name [arg...]` batch file line, skip empty lines and comments.
+fn parse_batch_line(line: &str) -> Result<Option<(CommandId, Vec<String>)>> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return Ok(None);
+    }
+    let words = shlex::split(trimmed).context("Unbalanced quotes")?;
+    let (first, args) = words
+        .split_first()
+        .context("Internal error: no words in a non-empty line")?;
+    let command_id: CommandId = first
+        .parse()
+        .with_context(|| format!("Invalid command identifier {first:?}"))?;
+    Ok(Some((command_id, args.to_vec())))
+}
+
+/// Run the commands listed in a batch file one by one.
+///
+/// Unless told to keep going, stop at the first command that fails.
+fn run_batch(
+    var: &Variant,
+    config: &CommandBatchConfig,
+    detect: &DetectConfig,
+    contents: &str,
+    runner: &dyn Runner,
+) -> Result<()> {
+    let commands = contents
+        .lines()
+        .zip(1_usize..)
+        .filter_map(|(line, line_no)| {
+            parse_batch_line(line)
+                .with_context(|| format!("{file}: line {line_no}", file = config.file))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut failed = 0_usize;
+    for (command_id, args) in &commands {
+        let run_config = CommandRunConfig {
+            category: command_id.category.clone(),
+            name: command_id.name.clone(),
+            noop: config.noop,
+            args: args.clone(),
+            args_stdin: false,
+            shell: false,
+            #[cfg(feature = "json")]
+            dump_env: false,
+            repotype: config.repotype,
+            wrap: Vec::new(),
+            ignore_failure: false,
+            cwd: None,
         };
-        let (major, minor) = sp_variant::get_format_version_from(varfull);
-        let single = json!({
-            "format": {
-                "version": {
-                    "major": major,
-                    "minor": minor,
-                },
-            },
-            "variant": var.clone(),
-            "version": sp_variant::get_program_version().to_owned(),
-        });
+        let res = build_command(var, &run_config, detect, &[])
+            .and_then(|cmd_vec| runner::run_command(runner, &cmd_vec, "Command failed"));
+        if let Err(err) = res {
+            if !config.keep_going {
+                return Err(err);
+            }
+            warn!("{err:#}");
+            failed += 1;
+        }
+    }
+    ensure!(
+        failed == 0,
+        format!(
+            "{failed} of {total} commands failed",
+            total = commands.len()
+        )
+    );
+    Ok(())
+}
+
+fn cmd_command_batch(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &CommandBatchConfig,
+) -> Result<()> {
+    let contents = fs::read_to_string(&config.file)
+        .with_context(|| format!("Could not read {file}", file = config.file))?;
+    let var = detect_variant(varfull, detect)?;
+    run_batch(
+        var,
+        config,
+        &detect.config,
+        &contents,
+        get_runner(config.noop),
+    )
+}
+
+/// Run a distribution-specific command, only warn about a non-zero exit code if requested.
+fn run_distro_command(runner: &dyn Runner, cmd_vec: &[String], ignore_failure: bool) -> Result<()> {
+    match runner::run_command(runner, cmd_vec, "Command failed") {
+        Err(err)
+            if ignore_failure
+                && matches!(
+                    err.downcast_ref::<CommandError>(),
+                    Some(&CommandError::ExitCode(..))
+                ) =>
+        {
+            warn!("{err}; ignoring the failure");
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+/// Find the program that a distribution-specific command runs.
+///
+/// A program name containing a slash is used as it is, relative to the current
+/// directory if needed; any other name is looked up in the search path.
+fn resolve_command(var: &Variant, command_id: &CommandId, path: Option<&OsStr>) -> Result<PathBuf> {
+    let program = var
+        .command(&command_id.category, &command_id.name)
+        .context("Unknown command identifier")?
+        .first()
+        .with_context(|| {
+            format!(
+                "Internal error: an empty {category}.{name} command",
+                category = command_id.category,
+                name = command_id.name
+            )
+        })?;
+    let found = if program.contains('/') {
+        Some(PathBuf::from(program)).filter(|candidate| candidate.is_file())
+    } else {
+        path.and_then(|path| find_in_path(program, path))
+    }
+    .with_context(|| {
+        format!(
+            "Could not find the {program} program for {category}.{name}",
+            category = command_id.category,
+            name = command_id.name
+        )
+    })?;
+    if found.is_absolute() {
+        Ok(found)
+    } else {
+        Ok(env::current_dir()
+            .context("Could not determine the current directory")?
+            .join(found))
+    }
+}
+
+#[allow(clippy::print_stdout)]
+fn cmd_command_which(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &CommandWhichConfig,
+) -> Result<()> {
+    let var = detect_variant(varfull, detect)?;
+    let found = resolve_command(var, &config.command, env::var_os("PATH").as_deref())?;
+    println!("{found}", found = found.display());
+    Ok(())
+}
+
+/// Serialize the variant definitions with the object keys sorted.
+///
+/// The variant data is stored in hash maps, so it goes through a JSON value
+/// first to make the output the same on each run.
+#[cfg(feature = "json")]
+fn serialize_variants(varfull: &VariantDefTop, pretty: bool) -> Result<String> {
+    let value = serde_json::to_value(varfull)
+        .context("Internal error: could not serialize the variant data")?;
+    if pretty {
+        serde_json::to_string_pretty(&value)
+    } else {
+        serde_json::to_string(&value)
+    }
+    .context("Internal error: could not serialize the variant data")
+}
+
+#[cfg(feature = "json")]
+#[allow(clippy::print_stdout)]
+fn cmd_dump(varfull: &VariantDefTop, config: &DumpConfig) -> Result<()> {
+    println!("{json}", json = serialize_variants(varfull, config.pretty)?);
+    Ok(())
+}
+
+/// Get the name of the package manager that the variant's repository is for.
+fn repo_type_name(var: &Variant) -> Result<&'static str> {
+    match var.repo {
+        Repo::Deb(_) => Ok("deb"),
+        Repo::Yum(_) => Ok("yum"),
+        _ => bail!("Internal error: unhandled repo type"),
+    }
+}
+
+/// Build the JSON array describing the supported variants.
+#[cfg(feature = "json")]
+fn list_variants_json(varfull: &VariantDefTop) -> Result<Value> {
+    sp_variant::get_all_variants_in_order_from(varfull)
+        .map(|var| {
+            Ok(json!({
+                "name": var.kind.as_ref(),
+                "descr": var.descr,
+                "repo": repo_type_name(var)?,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Value::Array)
+}
+
+/// Build the lines listing the supported variants: name, repository type, description.
+fn list_variants_lines(varfull: &VariantDefTop) -> Result<Vec<String>> {
+    let width = sp_variant::get_all_variants_in_order_from(varfull)
+        .map(|var| var.kind.as_ref().len())
+        .max()
+        .unwrap_or(0);
+    sp_variant::get_all_variants_in_order_from(varfull)
+        .map(|var| {
+            Ok(format!(
+                "{name:<width$}  {repo}  {descr}",
+                name = var.kind.as_ref(),
+                repo = repo_type_name(var)?,
+                descr = var.descr
+            ))
+        })
+        .collect()
+}
+
+#[allow(clippy::print_stdout)]
+#[cfg_attr(not(feature = "json"), allow(unused_variables))]
+fn cmd_list_variants(varfull: &VariantDefTop, config: &ListVariantsConfig) -> Result<()> {
+    #[cfg(feature = "json")]
+    if config.json {
         println!(
             "{json}",
-            json = serde_json::to_string_pretty(&single)
-                .context("Internal error: could not serialize the variant data")?
+            json = serde_json::to_string_pretty(&list_variants_json(varfull)?)
+                .context("Internal error: could not serialize the variants list")?
+        );
+        return Ok(());
+    }
+    for line in list_variants_lines(varfull)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+fn variants_schema() -> Result<String> {
+    serde_json::to_string_pretty(&schemars::schema_for!(VariantDefTop))
+        .context("Internal error: could not serialize the variant data schema")
+}
+
+#[cfg(feature = "json")]
+#[allow(clippy::print_stdout)]
+fn cmd_schema() -> Result<()> {
+    println!("{json}", json = variants_schema()?);
+    Ok(())
+}
+
+#[allow(clippy::print_stdout)]
+fn cmd_selftest(varfull: &VariantDefTop) -> Result<()> {
+    let problems = sp_variant::check_variants(varfull);
+    for problem in &problems {
+        println!("{problem}");
+    }
+    ensure!(
+        problems.is_empty(),
+        format!(
+            "The embedded variant definitions are inconsistent: {count} problem(s) found",
+            count = problems.len()
+        )
+    );
+    println!("The embedded variant definitions are consistent");
+    Ok(())
+}
+
+/// Build the JSON object describing a single variant.
+#[cfg(feature = "json")]
+fn show_variant_json(varfull: &VariantDefTop, var: &Variant) -> Value {
+    let (major, minor) = sp_variant::get_format_version_from(varfull);
+    json!({
+        "format": {
+            "version": {
+                "major": major,
+                "minor": minor,
+            },
+        },
+        "variant": var.clone(),
+        "version": sp_variant::get_program_version().to_owned(),
+    })
+}
+
+/// Build the JSON object describing the current host's variant: the same as for
+/// a single variant, with the host environment, the init system, and
+/// the detection rule that matched added.
+#[cfg(feature = "json")]
+fn show_current_json(varfull: &VariantDefTop, var: &Variant, matched_rule: Option<&str>) -> Value {
+    let mut single = show_variant_json(varfull, var);
+    let environment = sp_variant::detect_environment();
+    single["environment"] = json!({
+        "container": environment.container,
+        "wsl": environment.wsl,
+    });
+    single["init"] = json!(sp_variant::detect_init_system().as_ref());
+    single["matched_rule"] = json!(matched_rule);
+    single
+}
+
+/// Get the value at the specified dotted path, e.g. "commands.package.install".
+#[cfg(feature = "json")]
+fn extract_field<'data>(value: &'data Value, path: &str) -> Result<&'data Value> {
+    path.split('.').try_fold(value, |current, component| {
+        match *current {
+            Value::Object(ref obj) => obj.get(component),
+            Value::Array(ref arr) => component.parse::<usize>().ok().and_then(|idx| arr.get(idx)),
+            _ => None,
+        }
+        .with_context(|| format!("No '{component}' element in the '{path}' path"))
+    })
+}
+
+/// Format a single value: strings and other scalars as they are, anything else as JSON.
+#[cfg(feature = "json")]
+fn field_text(value: &Value) -> Result<String> {
+    match *value {
+        Value::String(ref text) => Ok(text.clone()),
+        Value::Array(_) | Value::Object(_) => serde_json::to_string(value)
+            .context("Internal error: could not serialize the variant data"),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// Build the text that the `show` subcommand outputs.
+#[cfg(feature = "json")]
+fn show_text(
+    varfull: &VariantDefTop,
+    detect: &DetectOptions,
+    config: &ShowConfig,
+    pretty: bool,
+) -> Result<String> {
+    if config.name == "all" {
+        ensure!(
+            config.field.is_none(),
+            "The --field option may only be used for a single variant"
         );
+        return serialize_variants(varfull, pretty);
+    }
+
+    let single = match &*config.name {
+        "current" => {
+            let (var, matched_rule) =
+                detect_with_rule(varfull, detect).context("Cannot detect the current variant")?;
+            show_current_json(varfull, var, matched_rule.as_deref())
+        }
+        other => show_variant_json(
+            varfull,
+            sp_variant::get_from(varfull, other).context("Invalid variant name")?,
+        ),
+    };
+    match config.field {
+        Some(ref path) => field_text(extract_field(&single["variant"], path)?),
+        None if pretty => serde_json::to_string_pretty(&single)
+            .context("Internal error: could not serialize the variant data"),
+        None => serde_json::to_string(&single)
+            .context("Internal error: could not serialize the variant data"),
+    }
+}
+
+/// Decide whether to pretty-print the `show` output: if requested or,
+/// by default, if it goes to a terminal.
+#[cfg(feature = "json")]
+fn show_pretty(config: &ShowConfig, stdout_is_tty: bool) -> bool {
+    config
+        .pretty
+        .unwrap_or_else(|| config.output.is_none() && stdout_is_tty)
+}
+
+#[cfg(feature = "json")]
+#[allow(clippy::print_stdout)]
+fn cmd_show(varfull: &VariantDefTop, detect: &DetectOptions, config: &ShowConfig) -> Result<()> {
+    let pretty = show_pretty(
+        config,
+        unistd::isatty(io::stdout().as_raw_fd()).unwrap_or(false),
+    );
+    let text = show_text(varfull, detect, config, pretty)?;
+    match config.output {
+        Some(ref path) => RealFs
+            .write(path, format!("{text}\n").as_bytes(), 0o644, None)
+            .with_context(|| format!("Could not write to {path}")),
+        None => {
+            println!("{text}");
+            Ok(())
+        }
+    }
+}
+
+/// Check loaded variant definitions for consistency and format compatibility.
+#[cfg(feature = "json")]
+fn validate_problems(varfull: &VariantDefTop) -> Vec<String> {
+    let mut problems = sp_variant::check_variants(varfull);
+    if !sp_variant::check_format_version_from(varfull) {
+        let (major, minor) = sp_variant::get_format_version_from(varfull);
+        let (s_major, s_minor) = sp_variant::get_format_version();
+        problems.push(format!(
+            "Unsupported format version {major}.{minor}, this program handles {s_major}.{s_minor}"
+        ));
+    }
+    problems
+}
+
+#[cfg(feature = "json")]
+#[allow(clippy::print_stdout)]
+fn cmd_validate(varfull: &VariantDefTop, path: Option<&str>) -> Result<()> {
+    let path = path.context("The validate command needs the --variants-file option")?;
+    let problems = validate_problems(varfull);
+    for problem in &problems {
+        println!("{problem}");
     }
+    ensure!(
+        problems.is_empty(),
+        format!(
+            "The variant definitions in {path} are not valid: {count} problem(s) found",
+            count = problems.len()
+        )
+    );
+    println!("The variant definitions in {path} are valid");
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let varfull = sp_variant::build_variants();
-    match cli::parse() {
+/// Load the variant definitions from a file instead of using the embedded ones.
+#[cfg(feature = "json")]
+fn load_variants_file(path: &str) -> Result<VariantDefTop> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Could not read the variant definitions from {path}"))?;
+    let varfull: VariantDefTop = serde_json::from_str(&contents)
+        .with_context(|| format!("Could not parse the variant definitions in {path}"))?;
+    if !sp_variant::check_format_version_from(&varfull) {
+        warn!("The variant definitions in {path} may not be handled correctly");
+    }
+    Ok(varfull)
+}
+
+/// Load additional distribution-specific commands from a file and check them.
+#[cfg(feature = "json")]
+fn load_commands_file(path: &str) -> Result<HashMap<String, HashMap<String, Vec<String>>>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Could not read the commands from {path}"))?;
+    let extra: HashMap<String, HashMap<String, Vec<String>>> = serde_json::from_str(&contents)
+        .with_context(|| format!("Could not parse the commands in {path}"))?;
+    for (category, cmds) in &extra {
+        for (name, cmd) in cmds {
+            let cmd_id = format!("{category}.{name}");
+            ensure!(
+                [category, name]
+                    .into_iter()
+                    .all(|part| !part.is_empty() && !part.contains(['.', ' '])),
+                "{path}: invalid command identifier {cmd_id:?}"
+            );
+            ensure!(
+                cmd.first().map_or(false, |program| !program.is_empty()),
+                "{path}: no program specified for the {cmd_id} command"
+            );
+        }
+    }
+    debug!(
+        "Loaded {count} commands from {path}",
+        count = extra.values().map(HashMap::len).sum::<usize>()
+    );
+    Ok(extra)
+}
+
+/// Perform the action requested on the command line.
+fn run_config(varfull: &VariantDefTop, config: Config) -> Result<ExitCode> {
+    let detect = &config.detect;
+    match config.mode {
         Mode::Features => {
             cmd_features(varfull);
             Ok(())
         }
-        Mode::CommandList => cmd_command_list(varfull),
-        Mode::CommandRun(config) => cmd_command_run(varfull, config),
-        Mode::Detect => cmd_detect(varfull),
-        Mode::RepoAdd(config) => cmd_repo_add(varfull, &config),
-        Mode::Show(config) => cmd_show(varfull, &config),
+        Mode::CommandBatch(config) => cmd_command_batch(varfull, detect, &config),
+        Mode::CommandList(config) => cmd_command_list(varfull, detect, &config),
+        Mode::CommandRun(config) => cmd_command_run(varfull, detect, &config),
+        Mode::CommandWhich(config) => cmd_command_which(varfull, detect, &config),
+        Mode::Detect(config) => return cmd_detect(varfull, detect, &config),
+        #[cfg(feature = "json")]
+        Mode::Dump(config) => cmd_dump(varfull, &config),
+        #[cfg(feature = "json")]
+        Mode::Inventory => cmd_inventory(varfull, detect),
+        Mode::ListVariants(config) => cmd_list_variants(varfull, &config),
+        Mode::RepoAdd(config) => cmd_repo_add(varfull, detect, &config)
+            .map(|report| show_repo_add_report(&config, &report)),
+        Mode::RepoRequirements => cmd_repo_requirements(varfull, detect),
+        #[cfg(feature = "json")]
+        Mode::Schema => cmd_schema(),
+        Mode::SelfTest => cmd_selftest(varfull),
+        #[cfg(feature = "json")]
+        Mode::Show(config) => cmd_show(varfull, detect, &config),
+        #[cfg(feature = "json")]
+        Mode::ShowCompare(config) => cmd_show_compare(varfull, &config),
+        #[cfg(feature = "json")]
+        Mode::ShowDiffEmbedded => cmd_show_diff_embedded(varfull, config.variants_file.as_deref()),
+        #[cfg(feature = "json")]
+        Mode::Validate => cmd_validate(varfull, config.variants_file.as_deref()),
+    }?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run() -> Result<ExitCode> {
+    let config = cli::parse();
+    logging::init(config.verbose, config.quiet)?;
+    if config.quiet {
+        // Report the outcome only through the exit code, not even the error message.
+        return Ok(
+            run_with_variants(config).unwrap_or_else(|err| ExitCode::from(failure_code(&err)))
+        );
+    }
+    run_with_variants(config)
+}
+
+/// Load the variant definitions, then do what the command line says.
+fn run_with_variants(config: Config) -> Result<ExitCode> {
+    #[cfg(feature = "json")]
+    if config.variants_file.is_some() || config.commands_file.is_some() {
+        let mut varfull = match config.variants_file {
+            Some(ref path) => load_variants_file(path)?,
+            None => sp_variant::build_variants().clone(),
+        };
+        if let Some(ref path) = config.commands_file {
+            sp_variant::merge_commands_into(&mut varfull, &load_commands_file(path)?);
+        }
+        return run_config(&varfull, config);
+    }
+    run_config(sp_variant::build_variants(), config)
+}
+
+#[allow(clippy::print_stderr)]
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(failure_code(&err))
+        }
     }
 }
@@ -0,0 +1,96 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Keep a record of the actions taken while adding the StorPool repository.
+
+#[cfg(feature = "json")]
+use std::fs;
+use std::io::Result as IoResult;
+use std::process::ExitStatus;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[cfg(feature = "json")]
+use anyhow::{Context, Result};
+use serde_derive::Serialize;
+
+use crate::runner::Runner;
+
+/// A single action taken (or, in no-operation mode, that would have been taken).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// A file was copied, possibly modifying its contents.
+    Copy {
+        source: String,
+        destination: String,
+        bytes: usize,
+    },
+
+    /// The previous contents of a file were preserved under another name.
+    Backup { path: String, backup: String },
+
+    /// An external command was run; the exit code is null if it was not
+    /// actually run or if it was killed by a signal.
+    Command {
+        argv: Vec<String>,
+        exit_code: Option<i32>,
+    },
+}
+
+/// The list of the actions taken so far; may be shared between threads.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    actions: Mutex<Vec<Action>>,
+}
+
+impl Manifest {
+    /// Add an action to the list.
+    pub fn record(&self, action: Action) {
+        self.actions().push(action);
+    }
+
+    /// The actions recorded so far.
+    pub fn actions(&self) -> MutexGuard<'_, Vec<Action>> {
+        self.actions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Write the list of actions out as a JSON array.
+    #[cfg(feature = "json")]
+    pub fn write(&self, path: &str) -> Result<()> {
+        let contents = serde_json::to_string_pretty(&*self.actions())
+            .context("Internal error: could not serialize the manifest")?;
+        fs::write(path, format!("{contents}\n"))
+            .with_context(|| format!("Could not write the manifest to {path}"))
+    }
+}
+
+/// Record the commands run by another runner.
+#[derive(Debug)]
+pub struct RecordingRunner<'rec, R: Runner + ?Sized> {
+    inner: &'rec R,
+    manifest: &'rec Manifest,
+    noop: bool,
+}
+
+impl<'rec, R: Runner + ?Sized> RecordingRunner<'rec, R> {
+    /// Wrap a runner; in no-operation mode, do not record any exit codes.
+    pub const fn new(inner: &'rec R, manifest: &'rec Manifest, noop: bool) -> Self {
+        Self {
+            inner,
+            manifest,
+            noop,
+        }
+    }
+}
+
+impl<R: Runner + ?Sized> Runner for RecordingRunner<'_, R> {
+    fn run(&self, cmdvec: &[String]) -> IoResult<ExitStatus> {
+        let status = self.inner.run(cmdvec)?;
+        self.manifest.record(Action::Command {
+            argv: cmdvec.to_vec(),
+            exit_code: if self.noop { None } else { status.code() },
+        });
+        Ok(status)
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Run external commands or only display them in no-operation mode.
+
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use log::{debug, warn};
+use thiserror::Error;
+
+/// Run an external command and report its exit status.
+pub trait Runner {
+    /// Run the command, wait for it to complete.
+    fn run(&self, cmdvec: &[String]) -> IoResult<ExitStatus>;
+}
+
+/// Actually spawn the external commands.
+#[derive(Debug)]
+pub struct ProcessRunner<'dir> {
+    /// The directory to run the commands in instead of the current one.
+    pub cwd: Option<&'dir Path>,
+}
+
+impl Runner for ProcessRunner<'_> {
+    fn run(&self, cmdvec: &[String]) -> IoResult<ExitStatus> {
+        let (name, args) = cmdvec
+            .split_first()
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Empty command"))?;
+        let mut cmd = Command::new(name);
+        cmd.args(args);
+        match self.cwd {
+            Some(cwd) => {
+                debug!(
+                    "Running `{cmdstr}` in {cwd}",
+                    cmdstr = cmdvec.join(" "),
+                    cwd = cwd.display()
+                );
+                cmd.current_dir(cwd);
+            }
+            None => debug!("Running `{cmdstr}`", cmdstr = cmdvec.join(" ")),
+        }
+        cmd.spawn()?.wait()
+    }
+}
+
+/// Only display the commands that would be run.
+#[derive(Debug)]
+pub struct NoopRunner;
+
+impl Runner for NoopRunner {
+    #[allow(clippy::print_stdout)]
+    fn run(&self, cmdvec: &[String]) -> IoResult<ExitStatus> {
+        println!("Would run `{cmdstr}`", cmdstr = cmdvec.join(" "));
+        Ok(ExitStatus::from_raw(0))
+    }
+}
+
+/// Do not run or display the commands, only pretend that they succeeded.
+#[derive(Debug)]
+pub struct SkipRunner;
+
+impl Runner for SkipRunner {
+    fn run(&self, _cmdvec: &[String]) -> IoResult<ExitStatus> {
+        Ok(ExitStatus::from_raw(0))
+    }
+}
+
+/// An external command could not be run or did not succeed.
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("{0}: {1}")]
+    Spawn(String, String, #[source] IoError),
+
+    #[error("{0}: {1}: exit code {2}")]
+    ExitCode(String, String, i32),
+
+    #[error("{0}: {1}: exit status {2:?}")]
+    ExitStatus(String, String, ExitStatus),
+
+    #[error("{0}: {1}: killed by signal {2}")]
+    Signal(String, String, i32),
+}
+
+/// Return an error describing the way the command failed, if it did.
+fn check_status(status: ExitStatus, action: &str, cmdstr: &str) -> Result<(), CommandError> {
+    if status.success() {
+        return Ok(());
+    }
+    let (action, cmdstr) = (action.to_owned(), cmdstr.to_owned());
+    match status.signal() {
+        None => match status.code() {
+            Some(code) => Err(CommandError::ExitCode(action, cmdstr, code)),
+            None => Err(CommandError::ExitStatus(action, cmdstr, status)),
+        },
+        Some(sig) => Err(CommandError::Signal(action, cmdstr, sig)),
+    }
+}
+
+/// Run the command, report a failure to spawn it.
+fn run_once(
+    runner: &dyn Runner,
+    cmdvec: &[String],
+    action: &str,
+    cmdstr: &str,
+) -> Result<ExitStatus, CommandError> {
+    runner
+        .run(cmdvec)
+        .map_err(|err| CommandError::Spawn(action.to_owned(), cmdstr.to_owned(), err))
+}
+
+/// Run a command once, fail if it does not succeed.
+pub fn run_command(runner: &dyn Runner, cmdvec: &[String], action: &str) -> Result<()> {
+    run_command_retry(runner, cmdvec, action, 0, Duration::ZERO)
+}
+
+/// Run a command, retry it if it exits with a non-zero code.
+///
+/// The delay between the attempts is doubled after each failure.
+/// A command killed by a signal is not retried.
+pub fn run_command_retry(
+    runner: &dyn Runner,
+    cmdvec: &[String],
+    action: &str,
+    retries: u32,
+    retry_delay: Duration,
+) -> Result<()> {
+    ensure!(
+        !cmdvec.is_empty(),
+        format!("Internal error: empty '{action}' command")
+    );
+    let cmdstr = cmdvec.join(" ");
+    let mut delay = retry_delay;
+    for _ in 0..retries {
+        let status = run_once(runner, cmdvec, action, &cmdstr)?;
+        match status.code() {
+            Some(code) if code != 0 => {
+                warn!(
+                    "{action}: {cmdstr}: exit code {code}, retrying in {secs} seconds",
+                    secs = delay.as_secs()
+                );
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            }
+            _ => return Ok(check_status(status, action, &cmdstr)?),
+        }
+    }
+
+    let status = run_once(runner, cmdvec, action, &cmdstr)?;
+    Ok(check_status(status, action, &cmdstr)?)
+}
@@ -0,0 +1,3313 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+#![allow(clippy::print_stdout)]
+#![allow(clippy::unwrap_used)]
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::Parser as _;
+use enum_iterator::cardinality;
+use log::Log;
+use nix::unistd::{Gid, Uid};
+use sp_variant::{
+    DebRepo, DetectConfig, Keyrings, Repo, RepoType, Variant, VariantDefTop, VariantError,
+    VariantKind, YumRepo,
+};
+use tempfile::TempDir;
+
+use super::cache;
+use super::cli::{
+    self, CommandBatchConfig, CommandListConfig, CommandRunConfig, DetectOptions,
+    ListVariantsConfig, Mode, Owner, RepoAddConfig,
+};
+use super::fsops::{self, FsOps, RealFs};
+use super::logging::StderrLogger;
+use super::manifest::{Action, Manifest, RecordingRunner};
+use super::runner::{self, ProcessRunner, Runner, SkipRunner};
+use super::RepoAddContext;
+
+/// Record the commands and return the predefined raw wait statuses.
+#[derive(Debug, Default)]
+struct FakeRunner {
+    statuses: RefCell<VecDeque<i32>>,
+    calls: RefCell<Vec<Vec<String>>>,
+}
+
+impl FakeRunner {
+    fn with_statuses(statuses: &[i32]) -> Self {
+        Self {
+            statuses: RefCell::new(statuses.iter().copied().collect()),
+            calls: RefCell::default(),
+        }
+    }
+}
+
+impl Runner for FakeRunner {
+    fn run(&self, cmdvec: &[String]) -> IoResult<ExitStatus> {
+        self.calls.borrow_mut().push(cmdvec.to_vec());
+        Ok(ExitStatus::from_raw(
+            self.statuses.borrow_mut().pop_front().unwrap_or(0),
+        ))
+    }
+}
+
+#[test]
+fn test_dump_roundtrip() {
+    let varfull = sp_variant::build_variants();
+    for pretty in [false, true] {
+        println!("Serializing the variant data, pretty: {pretty}");
+        let dumped = super::serialize_variants(varfull, pretty).unwrap();
+        let parsed: VariantDefTop = serde_json::from_str(&dumped).unwrap();
+        assert_eq!(
+            sp_variant::get_format_version_from(&parsed),
+            sp_variant::get_format_version_from(varfull)
+        );
+        assert_eq!(
+            sp_variant::get_program_version_from(&parsed),
+            sp_variant::get_program_version_from(varfull)
+        );
+        assert_eq!(
+            sp_variant::get_all_variants_in_order_from(&parsed).collect::<Vec<_>>(),
+            sp_variant::get_all_variants_in_order_from(varfull).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn test_retry_exit_code() {
+    let cmdvec = vec!["apt-get".to_owned(), "update".to_owned()];
+    let fake = FakeRunner::with_statuses(&[1 << 8, 1 << 8, 0]);
+    runner::run_command_retry(&fake, &cmdvec, "Updating", 3, Duration::ZERO).unwrap();
+    assert_eq!(*fake.calls.borrow(), vec![cmdvec.clone(); 3]);
+
+    let fake = FakeRunner::with_statuses(&[1 << 8, 1 << 8, 0]);
+    runner::run_command_retry(&fake, &cmdvec, "Updating", 1, Duration::ZERO).unwrap_err();
+    assert_eq!(fake.calls.borrow().len(), 2);
+}
+
+#[test]
+fn test_retry_signal() {
+    let cmdvec = vec!["apt-get".to_owned(), "update".to_owned()];
+    let fake = FakeRunner::with_statuses(&[9, 0]);
+    runner::run_command_retry(&fake, &cmdvec, "Updating", 3, Duration::ZERO).unwrap_err();
+    assert_eq!(fake.calls.borrow().len(), 1);
+}
+
+/// Build a no-operation configuration for adding the repository from a temporary directory.
+fn repo_add_config(tempd: &TempDir) -> RepoAddConfig {
+    RepoAddConfig {
+        noop: true,
+        dump_commands: false,
+        offline: false,
+        no_update: false,
+        only_keyring: false,
+        no_key_import: false,
+        repodir: tempd.path().to_str().unwrap().to_owned(),
+        repotypes: vec![RepoType::Contrib],
+        keyring_dir: None,
+        owner: None,
+        manifest: None,
+        parallel: false,
+        backup: false,
+        verify_checksums: false,
+        mirror: None,
+        retries: 0,
+        retry_delay: Duration::ZERO,
+        if_changed: false,
+        http_proxy: None,
+        https_proxy: None,
+        assume_yes: false,
+    }
+}
+
+/// Get a variant that uses an Apt repository.
+fn deb_repo(name: &str) -> (&'static Variant, &'static DebRepo) {
+    let var = sp_variant::get_from(sp_variant::build_variants(), name).unwrap();
+    match var.repo {
+        Repo::Deb(ref deb) => (var, deb),
+        _ => panic!("Expected a Debian repository for {var:?}"),
+    }
+}
+
+/// Get a variant that uses a Yum repository.
+fn yum_repo(name: &str) -> (&'static Variant, &'static YumRepo) {
+    let var = sp_variant::get_from(sp_variant::build_variants(), name).unwrap();
+    match var.repo {
+        Repo::Yum(ref yum) => (var, yum),
+        _ => panic!("Expected a Yum repository for {var:?}"),
+    }
+}
+
+/// Create the repository files for the specified variant in a temporary directory.
+fn repo_add_prepare(name: &str) -> (TempDir, String) {
+    let tempd = tempfile::tempdir().unwrap();
+    let vdir = tempd.path().join(name);
+    fs::create_dir(&vdir).unwrap();
+    for fname in [
+        "storpool.sources",
+        "storpool-staging.sources",
+        "storpool-infra.sources",
+        "storpool-keyring.gpg",
+        "storpool-centos.repo",
+        "storpool-centos-staging.repo",
+        "storpool-centos-infra.repo",
+        "RPM-GPG-KEY-StorPool",
+    ] {
+        fs::write(vdir.join(fname), fname).unwrap();
+    }
+    let vdir_str = vdir.to_str().unwrap().to_owned();
+    (tempd, vdir_str)
+}
+
+#[test]
+fn test_repo_add_offline() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+
+    let config = repo_add_config(&tempd);
+    let fake = FakeRunner::default();
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        &vdir,
+        deb,
+    )
+    .unwrap();
+    let calls = fake.calls.borrow();
+    println!("Online mode: {calls:?}");
+    assert!(calls.contains(&vec!["apt-get".to_owned(), "update".to_owned()]));
+
+    let config = RepoAddConfig {
+        offline: true,
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeRunner::default();
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        &vdir,
+        deb,
+    )
+    .unwrap();
+    let calls = fake.calls.borrow();
+    println!("Offline mode: {calls:?}");
+    assert!(calls.is_empty());
+}
+
+#[test]
+fn test_schema_validate() {
+    let schema: serde_json::Value =
+        serde_json::from_str(&super::variants_schema().unwrap()).unwrap();
+    let compiled = jsonschema::JSONSchema::compile(&schema).unwrap();
+    let data: serde_json::Value = serde_json::from_str(
+        &super::serialize_variants(sp_variant::build_variants(), false).unwrap(),
+    )
+    .unwrap();
+    let errors: Vec<String> = match compiled.validate(&data) {
+        Ok(()) => vec![],
+        Err(errors) => errors.map(|err| err.to_string()).collect(),
+    };
+    println!("Schema validation errors: {errors:?}");
+    assert!(errors.is_empty());
+}
+
+#[test]
+fn test_command_run_args_stdin() {
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    let config = CommandRunConfig {
+        category: "package".to_owned(),
+        name: "install".to_owned(),
+        noop: true,
+        args: vec!["first".to_owned()],
+        args_stdin: true,
+        shell: false,
+        dump_env: false,
+        repotype: RepoType::Contrib,
+        wrap: vec![],
+        ignore_failure: false,
+        cwd: None,
+    };
+    let stdin_args = super::read_args(&b"curl\n\n  \njq \t\nvim\n"[..]).unwrap();
+    assert_eq!(stdin_args, ["curl", "jq", "vim"]);
+
+    let cmd_vec =
+        super::build_command(var, &config, &DetectConfig::default(), &stdin_args).unwrap();
+    println!("{cmd_vec:?}");
+    assert!(cmd_vec.contains(&"apt-get".to_owned()));
+    assert!(cmd_vec.ends_with(&[
+        "first".to_owned(),
+        "curl".to_owned(),
+        "jq".to_owned(),
+        "vim".to_owned(),
+    ]));
+}
+
+#[test]
+fn test_detect_check() {
+    let varfull = sp_variant::build_variants();
+    let data = [("ID", "debian"), ("VERSION_ID", "11")]
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    let var = sp_variant::detect_from_os_release(varfull, &data, &DetectConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(super::detect_check_code(Ok(var)).unwrap(), 0);
+
+    assert_eq!(
+        super::detect_check_code(Err(VariantError::UnknownVariant)).unwrap(),
+        super::EXIT_UNSUPPORTED
+    );
+    super::detect_check_code(Err(VariantError::Internal("oof".to_owned()))).unwrap_err();
+}
+
+#[test]
+fn test_rewrite_keyring_path() {
+    let sources = "Types: deb deb-src
+URIs: https://repo.storpool.com/public/contrib/debian/
+Suites: bookworm
+Components: main
+Signed-By: /usr/share/keyrings/storpool-keyring.gpg
+";
+    let rewritten = String::from_utf8(
+        super::rewrite_field(
+            sources.as_bytes().to_vec(),
+            "Signed-By: ",
+            "/opt/keys/storpool-keyring.gpg",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    println!("{rewritten}");
+    assert_eq!(
+        rewritten,
+        sources.replace("/usr/share/keyrings/", "/opt/keys/")
+    );
+}
+
+#[test]
+fn test_repo_add_keyring_dir() {
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        keyring_dir: Some("/opt/keys".to_owned()),
+        ..repo_add_config(&tempd)
+    };
+
+    let (var, yum) = yum_repo("ALMA9");
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().extend([
+        (
+            "/repo/ALMA9/storpool-centos.repo".to_owned(),
+            b"[storpool-contrib]\ngpgkey=file:///etc/pki/rpm-gpg/RPM-GPG-KEY-StorPool\nenabled=1\n"
+                .to_vec(),
+        ),
+        (
+            "/repo/ALMA9/RPM-GPG-KEY-StorPool".to_owned(),
+            b"keyring".to_vec(),
+        ),
+    ]);
+    let runner = FakeRunner::default();
+    super::repo_add_yum(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/ALMA9",
+        yum,
+    )
+    .unwrap();
+    let files = fake.files.lock().unwrap();
+    assert_eq!(
+        files["/etc/yum.repos.d/storpool-centos.repo"],
+        b"[storpool-contrib]\ngpgkey=file:///opt/keys/RPM-GPG-KEY-StorPool\nenabled=1\n"
+    );
+    assert_eq!(files["/opt/keys/RPM-GPG-KEY-StorPool"], b"keyring");
+    assert!(!files.contains_key("/etc/pki/rpm-gpg/RPM-GPG-KEY-StorPool"));
+    drop(files);
+    let calls = runner.calls.borrow();
+    println!("{calls:?}");
+    if let Some(cmdvec) = sp_variant::keyring::key_import_command(var, "/opt/keys").unwrap() {
+        assert!(calls.contains(&cmdvec));
+    }
+
+    let (var, deb) = deb_repo("DEBIAN12");
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().extend([
+        (
+            "/repo/DEBIAN12/storpool.sources".to_owned(),
+            b"Types: deb\nSigned-By: /usr/share/keyrings/storpool-keyring.gpg\n".to_vec(),
+        ),
+        (
+            "/repo/DEBIAN12/storpool-keyring.gpg".to_owned(),
+            b"keyring".to_vec(),
+        ),
+    ]);
+    let runner = FakeRunner::default();
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+        deb,
+    )
+    .unwrap();
+    let files = fake.files.lock().unwrap();
+    assert_eq!(
+        files["/etc/apt/sources.list.d/storpool.sources"],
+        b"Types: deb\nSigned-By: /opt/keys/storpool-keyring.gpg\n"
+    );
+    assert_eq!(files["/opt/keys/storpool-keyring.gpg"], b"keyring");
+    assert!(!files.contains_key("/usr/share/keyrings/storpool-keyring.gpg"));
+}
+
+#[test]
+fn test_log_verbosity() {
+    let debug_meta = log::Metadata::builder().level(log::Level::Debug).build();
+    let info_meta = log::Metadata::builder().level(log::Level::Info).build();
+    let trace_meta = log::Metadata::builder().level(log::Level::Trace).build();
+
+    let quiet = StderrLogger::from_verbosity(0);
+    assert!(quiet.enabled(&info_meta));
+    assert!(!quiet.enabled(&debug_meta));
+    assert!(!quiet.enabled(&trace_meta));
+
+    let verbose = StderrLogger::from_verbosity(1);
+    assert!(verbose.enabled(&info_meta));
+    assert!(verbose.enabled(&debug_meta));
+    assert!(!verbose.enabled(&trace_meta));
+
+    let very_verbose = StderrLogger::from_verbosity(2);
+    assert!(very_verbose.enabled(&debug_meta));
+    assert!(very_verbose.enabled(&trace_meta));
+}
+
+#[test]
+fn test_repo_add_all_types() {
+    let fnames: HashSet<String> = RepoType::VALUES
+        .iter()
+        .map(|repotype| sp_variant::repo_filename("storpool.sources", repotype.extension()))
+        .collect();
+    assert_eq!(fnames.len(), RepoType::VALUES.len());
+
+    let (var, deb) = deb_repo("DEBIAN12");
+    // Only the final package database update is of interest here.
+    let mut deb = deb.clone();
+    deb.req_packages.clear();
+    let tempd = tempfile::tempdir().unwrap();
+    for offline in [true, false] {
+        let config = RepoAddConfig {
+            noop: false,
+            offline,
+            owner: Some(Owner::ROOT),
+            repotypes: RepoType::VALUES.to_vec(),
+            ..repo_add_config(&tempd)
+        };
+        let fake = FakeFs::default();
+        fake.files.lock().unwrap().extend(
+            fnames
+                .iter()
+                .chain(["storpool-keyring.gpg".to_owned()].iter())
+                .map(|fname| (format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec())),
+        );
+        let runner = FakeRunner::default();
+        super::repo_add_deb(
+            var,
+            &config,
+            &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+            "/repo/DEBIAN12",
+            &deb,
+        )
+        .unwrap();
+        let files = fake.files.lock().unwrap();
+        for fname in &fnames {
+            assert_eq!(
+                files[&format!("/etc/apt/sources.list.d/{fname}")],
+                fname.as_bytes()
+            );
+        }
+        assert_eq!(
+            files["/usr/share/keyrings/storpool-keyring.gpg"],
+            b"storpool-keyring.gpg"
+        );
+        let calls = runner.calls.borrow();
+        println!("offline {offline}: {calls:?}");
+        if offline {
+            assert!(calls.is_empty());
+        } else {
+            assert_eq!(*calls, [["apt-get".to_owned(), "update".to_owned()]]);
+        }
+    }
+
+    let (var, yum) = yum_repo("ALMA9");
+    let (tempd, vdir) = repo_add_prepare("ALMA9");
+    let config = RepoAddConfig {
+        repotypes: RepoType::VALUES.to_vec(),
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeRunner::default();
+    super::repo_add_yum(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        &vdir,
+        yum,
+    )
+    .unwrap();
+    let calls = fake.calls.borrow();
+    println!("{calls:?}");
+    let clean: Vec<&Vec<String>> = calls
+        .iter()
+        .filter(|cmd| cmd.iter().any(|word| word == "clean"))
+        .collect();
+    assert_eq!(
+        clean,
+        [&vec![
+            "yum".to_owned(),
+            "--disablerepo=*".to_owned(),
+            "--enablerepo=storpool-contrib".to_owned(),
+            "--enablerepo=storpool-staging".to_owned(),
+            "--enablerepo=storpool-infra".to_owned(),
+            "clean".to_owned(),
+            "metadata".to_owned(),
+        ]]
+    );
+}
+
+#[test]
+fn test_command_run_shell() {
+    assert_eq!(super::shell_quote("jq"), "jq");
+    assert_eq!(super::shell_quote(""), "''");
+    assert_eq!(super::shell_quote("a | b"), "'a | b'");
+    assert_eq!(super::shell_quote("it's"), "'it'\\''s'");
+
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    let config = CommandRunConfig {
+        category: "package".to_owned(),
+        name: "install".to_owned(),
+        noop: true,
+        args: vec!["curl".to_owned(), "x | y".to_owned()],
+        args_stdin: false,
+        shell: true,
+        dump_env: false,
+        repotype: RepoType::Contrib,
+        wrap: vec![],
+        ignore_failure: false,
+        cwd: None,
+    };
+    let cmd_vec = super::build_command(var, &config, &DetectConfig::default(), &[]).unwrap();
+    println!("{cmd_vec:?}");
+    assert_eq!(
+        cmd_vec,
+        [
+            "sh".to_owned(),
+            "-c".to_owned(),
+            "env DEBIAN_FRONTEND=noninteractive apt-get -q -y --no-install-recommends install -- \
+             curl 'x | y'"
+                .to_owned(),
+        ]
+    );
+
+    let fake = FakeRunner::default();
+    runner::run_command(&fake, &cmd_vec, "Command failed").unwrap();
+    assert_eq!(*fake.calls.borrow(), [cmd_vec]);
+
+    for (name, category, cmd_name) in [
+        ("DEBIAN12", "package", "install"),
+        ("DEBIAN12", "package", "list_all"),
+        ("DEBIAN12", "pkgfile", "install"),
+        ("ALMA9", "package", "list_all"),
+        ("ALMA9", "pkgfile", "install"),
+    ] {
+        let var = sp_variant::get_from(sp_variant::build_variants(), name).unwrap();
+        let config = CommandRunConfig {
+            category: category.to_owned(),
+            name: cmd_name.to_owned(),
+            noop: true,
+            args: vec!["curl".to_owned(), "x | y".to_owned()],
+            args_stdin: false,
+            shell: true,
+            dump_env: false,
+            repotype: RepoType::Contrib,
+            wrap: vec![],
+            ignore_failure: false,
+            cwd: None,
+        };
+        let cmd_vec = super::build_command(var, &config, &DetectConfig::default(), &[]).unwrap();
+        let expected: Vec<String> = var
+            .command(category, cmd_name)
+            .unwrap()
+            .iter()
+            .chain(config.args.iter())
+            .cloned()
+            .collect();
+        assert_eq!(
+            shell_words(&cmd_vec[2]),
+            expected,
+            "{name} {category}.{cmd_name}"
+        );
+    }
+}
+
+/// Let the shell split a command line into words, return them.
+fn shell_words(cmdstr: &str) -> Vec<String> {
+    let output = Command::new("sh")
+        .args(["-c", &format!("set -- {cmdstr}; printf '%s\\0' \"$@\"")])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .split_terminator('\0')
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+#[test]
+fn test_failure_code() {
+    let err = Err::<(), _>(VariantError::UnknownVariant)
+        .context("Could not detect the current build variant")
+        .unwrap_err();
+    assert_eq!(super::failure_code(&err), super::EXIT_UNSUPPORTED);
+
+    let err = fs::read("/nonexistent/storpool.sources")
+        .context("Could not read from /nonexistent/storpool.sources")
+        .unwrap_err();
+    assert_eq!(super::failure_code(&err), super::EXIT_FILESYSTEM);
+
+    let fake = FakeRunner::with_statuses(&[1 << 8]);
+    let err = runner::run_command(&fake, &["false".to_owned()], "Command failed").unwrap_err();
+    println!("{err:?}");
+    assert_eq!(super::failure_code(&err), super::EXIT_SUBPROCESS);
+
+    let err = Err::<(), _>(VariantError::BadVariant("NOPE".to_owned()))
+        .context("Invalid variant name")
+        .unwrap_err();
+    assert_eq!(super::failure_code(&err), super::EXIT_FAILURE);
+}
+
+#[test]
+fn test_detect_cache() {
+    let varfull = sp_variant::build_variants();
+    let tempd = tempfile::tempdir().unwrap();
+    let os_release = tempd.path().join("os-release");
+    fs::write(&os_release, "ID=debian\nVERSION_ID=12\n").unwrap();
+    let os_release = os_release.to_str().unwrap();
+    let cache_path = tempd.path().join("variant.json");
+    let detect = DetectOptions {
+        config: DetectConfig::default(),
+        cache: Some(cache_path.to_str().unwrap().to_owned()),
+    };
+    let actual = sp_variant::detect_from_with_config(varfull, &detect.config)
+        .unwrap()
+        .kind;
+    let other = if actual == VariantKind::ALMA9 {
+        VariantKind::DEBIAN12
+    } else {
+        VariantKind::ALMA9
+    };
+
+    // A cache file with a matching key is used as it is.
+    let files = cache::release_files(varfull, os_release);
+    assert_eq!(files[0], os_release);
+    assert!(files.contains(&"/etc/redhat-release"));
+    assert!(files.contains(&"/usr/lib/os-release"));
+    let key = cache::cache_key(&files, &detect.config, varfull).unwrap();
+    cache::store(detect.cache.as_ref().unwrap(), &key, other).unwrap();
+    let var = super::detect_cached(varfull, &detect, os_release).unwrap();
+    assert_eq!(var.kind, other);
+
+    // A stale cache file leads to detecting the variant anew and updating the cache.
+    fs::write(
+        &cache_path,
+        format!("{{\"key\":\"stale\",\"variant\":\"{other}\"}}"),
+    )
+    .unwrap();
+    let var = super::detect_cached(varfull, &detect, os_release).unwrap();
+    assert_eq!(var.kind, actual);
+    assert_eq!(
+        cache::load(detect.cache.as_ref().unwrap(), &key).unwrap(),
+        Some(actual)
+    );
+
+    // The key changes along with the contents of the os-release file.
+    fs::write(os_release, "ID=debian\nVERSION_ID=13\n").unwrap();
+    let new_key = cache::cache_key(&files, &detect.config, varfull).unwrap();
+    assert_ne!(new_key, key);
+
+    // ...and with the contents of the fallback release files.
+    let release = tempd.path().join("redhat-release");
+    let release = release.to_str().unwrap();
+    let files = [os_release, release];
+    let missing_key = cache::cache_key(&files, &detect.config, varfull).unwrap();
+    fs::write(release, "AlmaLinux release 9.4 (Seafoam Ocelot)\n").unwrap();
+    let present_key = cache::cache_key(&files, &detect.config, varfull).unwrap();
+    assert_ne!(present_key, missing_key);
+    fs::write(release, "AlmaLinux release 9.5 (Teal Serval)\n").unwrap();
+    assert_ne!(
+        cache::cache_key(&files, &detect.config, varfull).unwrap(),
+        present_key
+    );
+
+    // ...and with each of the detection options.
+    for changed in [
+        DetectConfig::default().with_id_like(true),
+        DetectConfig::default().with_dereference_symlinks(true),
+        DetectConfig::default().with_lenient(true),
+        DetectConfig::default().with_prefer_usr_lib(true),
+        DetectConfig::default().with_lsb_release(true),
+    ] {
+        assert_ne!(
+            cache::cache_key(&files, &changed, varfull).unwrap(),
+            cache::cache_key(&files, &detect.config, varfull).unwrap(),
+            "{changed:?}"
+        );
+    }
+
+    // ...and with the variant definitions, e.g. ones loaded from another file.
+    let mut modified = varfull.clone();
+    sp_variant::merge_commands_into(
+        &mut modified,
+        &HashMap::from([(
+            "misc".to_owned(),
+            HashMap::from([("cache".to_owned(), vec!["true".to_owned()])]),
+        )]),
+    );
+    assert_eq!(
+        cache::cache_key(&files, &detect.config, &varfull.clone()).unwrap(),
+        cache::cache_key(&files, &detect.config, varfull).unwrap()
+    );
+    assert_ne!(
+        cache::cache_key(&files, &detect.config, &modified).unwrap(),
+        cache::cache_key(&files, &detect.config, varfull).unwrap()
+    );
+
+    // The hash function itself does not depend on the Rust version.
+    assert_eq!(
+        cache::fnv1a(0xcbf2_9ce4_8422_2325, b""),
+        0xcbf2_9ce4_8422_2325
+    );
+    assert_eq!(
+        cache::fnv1a(0xcbf2_9ce4_8422_2325, b"a"),
+        0xaf63_dc4c_8601_ec8c
+    );
+    assert_eq!(
+        cache::fnv1a(0xcbf2_9ce4_8422_2325, b"foobar"),
+        0x8594_4171_f739_67e8
+    );
+}
+
+#[test]
+fn test_detect_override() {
+    let varfull = sp_variant::build_variants();
+    let detect = DetectOptions {
+        config: DetectConfig::default(),
+        cache: None,
+    };
+    let actual = sp_variant::detect_from_with_config(varfull, &detect.config)
+        .unwrap()
+        .kind;
+    let forced = if actual == VariantKind::DEBIAN11 {
+        VariantKind::ALMA9
+    } else {
+        VariantKind::DEBIAN11
+    };
+
+    env::set_var(super::ENV_OVERRIDE, forced.as_ref());
+    let var = super::detect_current(varfull, &detect).unwrap();
+    assert_eq!(var.kind, forced);
+
+    env::set_var(super::ENV_OVERRIDE, "NO-SUCH-VARIANT");
+    let err = super::detect_current(varfull, &detect).unwrap_err();
+    assert!(matches!(err, VariantError::BadVariant(_)));
+
+    env::remove_var(super::ENV_OVERRIDE);
+    let var = super::detect_current(varfull, &detect).unwrap();
+    assert_eq!(var.kind, actual);
+}
+
+#[test]
+fn test_repo_add_only_keyring() {
+    // Only create the keyring files, so that any attempt to copy
+    // the repository definitions will fail.
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        only_keyring: true,
+        ..repo_add_config(&tempd)
+    };
+
+    let (var, deb) = deb_repo("DEBIAN12");
+    let vdir = tempd.path().join("DEBIAN12");
+    fs::create_dir(&vdir).unwrap();
+    fs::write(vdir.join("storpool-keyring.gpg"), "keyring").unwrap();
+    let fake = FakeRunner::default();
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        vdir.to_str().unwrap(),
+        deb,
+    )
+    .unwrap();
+    assert!(fake.calls.borrow().is_empty());
+
+    let (var, yum) = yum_repo("ALMA9");
+    let vdir = tempd.path().join("ALMA9");
+    fs::create_dir(&vdir).unwrap();
+    fs::write(vdir.join("RPM-GPG-KEY-StorPool"), "keyring").unwrap();
+    let fake = FakeRunner::default();
+    super::repo_add_yum(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        vdir.to_str().unwrap(),
+        yum,
+    )
+    .unwrap();
+    let calls = fake.calls.borrow();
+    println!("{calls:?}");
+    let expected: Vec<Vec<String>> =
+        sp_variant::keyring::key_import_command(var, sp_variant::keyring::YUM_KEYRING_DIR)
+            .unwrap()
+            .into_iter()
+            .collect();
+    assert_eq!(*calls, expected);
+}
+
+#[test]
+fn test_command_list_filter() {
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    let all_lines = super::command_list_lines(
+        var,
+        &CommandListConfig {
+            category: None,
+            names_only: false,
+            show_all: false,
+        },
+    )
+    .unwrap();
+    assert!(all_lines.iter().any(|line| line.starts_with("pkgfile.")));
+
+    let lines = super::command_list_lines(
+        var,
+        &CommandListConfig {
+            category: Some("package".to_owned()),
+            names_only: false,
+            show_all: false,
+        },
+    )
+    .unwrap();
+    println!("{lines:?}");
+    assert!(!lines.is_empty());
+    assert!(lines.iter().all(|line| line.starts_with("package.")));
+    assert!(lines.contains(&format!(
+        "package.install: {cmd}",
+        cmd = var.command("package", "install").unwrap().join(" ")
+    )));
+
+    let names = super::command_list_lines(
+        var,
+        &CommandListConfig {
+            category: Some("package".to_owned()),
+            names_only: true,
+            show_all: false,
+        },
+    )
+    .unwrap();
+    println!("{names:?}");
+    assert!(names.contains(&"package.install".to_owned()));
+    assert!(names.iter().all(|line| !line.contains(' ')));
+    assert_eq!(names.len(), lines.len());
+
+    super::command_list_lines(
+        var,
+        &CommandListConfig {
+            category: Some("no-such-category".to_owned()),
+            names_only: false,
+            show_all: false,
+        },
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn test_command_list_show_all() {
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    assert!(var
+        .templated_commands
+        .contains(&"pkgfile.install".to_owned()));
+    let install = format!(
+        "pkgfile.install: {cmd}",
+        cmd = var.command("pkgfile", "install").unwrap().join(" ")
+    );
+
+    let brief = super::command_list_lines(
+        var,
+        &CommandListConfig {
+            category: Some("pkgfile".to_owned()),
+            names_only: false,
+            show_all: false,
+        },
+    )
+    .unwrap();
+    println!("{brief:?}");
+    assert!(brief.contains(&"pkgfile.install: ...".to_owned()));
+    assert!(!brief.contains(&install));
+
+    let full = super::command_list_lines(
+        var,
+        &CommandListConfig {
+            category: Some("pkgfile".to_owned()),
+            names_only: false,
+            show_all: true,
+        },
+    )
+    .unwrap();
+    println!("{full:?}");
+    assert!(full.contains(&install));
+    assert!(!full.contains(&"pkgfile.install: ...".to_owned()));
+}
+
+#[test]
+fn test_copy_file_owner() {
+    assert_eq!(
+        "1000:1001".parse::<Owner>().unwrap(),
+        Owner {
+            uid: 1000,
+            gid: 1001
+        }
+    );
+    "1000".parse::<Owner>().unwrap_err();
+    "root:root".parse::<Owner>().unwrap_err();
+
+    let custom = Owner { uid: 42, gid: 43 };
+    assert_eq!(
+        super::target_owner(Some(custom), Uid::from_raw(0)),
+        Some(custom)
+    );
+    assert_eq!(
+        super::target_owner(Some(custom), Uid::from_raw(1000)),
+        Some(custom)
+    );
+    assert_eq!(
+        super::target_owner(None, Uid::from_raw(0)),
+        Some(Owner::ROOT)
+    );
+    assert_eq!(super::target_owner(None, Uid::from_raw(1000)), None);
+
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+    let dstdir = tempd.path().join("keyrings");
+    fs::create_dir(&dstdir).unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner {
+            uid: Uid::effective().as_raw(),
+            gid: Gid::effective().as_raw(),
+        }),
+        ..repo_add_config(&tempd)
+    };
+    super::copy_file(
+        "storpool-keyring.gpg",
+        &vdir,
+        dstdir.to_str().unwrap(),
+        &config,
+        &RealFs,
+        &Manifest::default(),
+    )
+    .unwrap();
+    let meta = fs::metadata(dstdir.join("storpool-keyring.gpg")).unwrap();
+    assert_eq!(meta.uid(), Uid::effective().as_raw());
+    assert_eq!(meta.gid(), Gid::effective().as_raw());
+    assert_eq!(
+        fs::read(dstdir.join("storpool-keyring.gpg")).unwrap(),
+        b"storpool-keyring.gpg"
+    );
+}
+
+#[test]
+fn test_repo_add_no_key_import() {
+    let (var, yum) = yum_repo("ALMA9");
+    let (tempd, vdir) = repo_add_prepare("ALMA9");
+    let config = RepoAddConfig {
+        no_key_import: true,
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeRunner::default();
+    super::repo_add_yum(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        &vdir,
+        yum,
+    )
+    .unwrap();
+    let calls = fake.calls.borrow();
+    println!("{calls:?}");
+    assert!(!calls.iter().any(|cmd| cmd[0] == "rpmkeys"));
+    assert!(calls
+        .iter()
+        .any(|cmd| cmd[0] == "yum" && cmd.ends_with(&["clean".to_owned(), "metadata".to_owned()])));
+}
+
+#[test]
+fn test_repo_add_multiple_keyrings() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let mut deb = deb.clone();
+    deb.keyrings = Keyrings::new(vec![
+        "debian/repo/storpool-keyring.gpg".to_owned(),
+        "debian/repo/storpool-keyring-new.gpg".to_owned(),
+    ]);
+    let mut var = var.clone();
+    var.repo = Repo::Deb(deb.clone());
+
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+    fs::write(
+        Path::new(&vdir).join("storpool-keyring-new.gpg"),
+        "storpool-keyring-new.gpg",
+    )
+    .unwrap();
+    let dstdir = tempd.path().join("keyrings");
+    fs::create_dir(&dstdir).unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        only_keyring: true,
+        keyring_dir: Some(dstdir.to_str().unwrap().to_owned()),
+        owner: Some(Owner {
+            uid: Uid::effective().as_raw(),
+            gid: Gid::effective().as_raw(),
+        }),
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeRunner::default();
+    super::repo_add_deb(
+        &var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        &vdir,
+        &deb,
+    )
+    .unwrap();
+    assert!(fake.calls.borrow().is_empty());
+    for fname in ["storpool-keyring.gpg", "storpool-keyring-new.gpg"] {
+        assert_eq!(fs::read(dstdir.join(fname)).unwrap(), fname.as_bytes());
+    }
+
+    let signed_by = super::keyring_paths(
+        "",
+        "/opt/keys",
+        &["storpool-keyring.gpg", "storpool-keyring-new.gpg"],
+    );
+    assert_eq!(
+        signed_by,
+        "/opt/keys/storpool-keyring.gpg /opt/keys/storpool-keyring-new.gpg"
+    );
+}
+
+#[test]
+fn test_repo_add_repotype_keyrings() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let mut deb = deb.clone();
+    deb.keyrings = Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()])
+        .with_repotype(
+            RepoType::STAGING,
+            vec!["debian/repo/storpool-staging-keyring.gpg".to_owned()],
+        );
+    let mut var = var.clone();
+    var.repo = Repo::Deb(deb.clone());
+
+    let fake = FakeFs::default();
+    for fname in [
+        "storpool.sources",
+        "storpool-staging.sources",
+        "storpool-keyring.gpg",
+        "storpool-staging-keyring.gpg",
+    ] {
+        fake.files.lock().unwrap().insert(
+            format!("/repo/DEBIAN12/{fname}"),
+            format!("Signed-By: /usr/share/keyrings/{fname}\n").into_bytes(),
+        );
+    }
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        offline: true,
+        owner: Some(Owner::ROOT),
+        repotypes: vec![RepoType::Staging],
+        keyring_dir: Some("/opt/keys".to_owned()),
+        ..repo_add_config(&tempd)
+    };
+    super::repo_add_deb(
+        &var,
+        &config,
+        &RepoAddContext::new(&FakeRunner::default(), &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+        &deb,
+    )
+    .unwrap();
+    let files = fake.files.lock().unwrap();
+    assert!(files.contains_key("/opt/keys/storpool-staging-keyring.gpg"));
+    assert!(!files.contains_key("/opt/keys/storpool-keyring.gpg"));
+    assert_eq!(
+        files["/etc/apt/sources.list.d/storpool-staging.sources"],
+        b"Signed-By: /opt/keys/storpool-staging-keyring.gpg\n"
+    );
+    assert!(!files.contains_key("/etc/apt/sources.list.d/storpool.sources"));
+}
+
+#[test]
+fn test_command_run_dump_env() {
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    let config = CommandRunConfig {
+        category: "package".to_owned(),
+        name: "install".to_owned(),
+        noop: false,
+        args: vec!["curl".to_owned(), "jq".to_owned()],
+        args_stdin: false,
+        shell: false,
+        dump_env: true,
+        repotype: RepoType::Contrib,
+        wrap: vec![],
+        ignore_failure: false,
+        cwd: None,
+    };
+    let cmd_vec = super::build_command(var, &config, &DetectConfig::default(), &[]).unwrap();
+    let inv = super::command_invocation(&cmd_vec, None).unwrap();
+    println!("{inv}");
+    let command: Vec<String> = serde_json::from_value(inv["command"].clone()).unwrap();
+    assert_eq!(command, cmd_vec);
+    assert!(command.contains(&"apt-get".to_owned()));
+    assert!(command.ends_with(&["curl".to_owned(), "jq".to_owned()]));
+    assert_eq!(
+        inv["cwd"].as_str().unwrap(),
+        env::current_dir().unwrap().to_str().unwrap()
+    );
+    let env_vars: HashMap<String, String> = serde_json::from_value(inv["env"].clone()).unwrap();
+    assert_eq!(
+        env_vars,
+        HashMap::from([("DEBIAN_FRONTEND".to_owned(), "noninteractive".to_owned())])
+    );
+
+    // The variables set by an `env` wrapper are listed, too.
+    let wrapped = CommandRunConfig {
+        wrap: ["env", "LC_ALL=C", "DEBIAN_FRONTEND=readline"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect(),
+        ..config
+    };
+    let cmd_vec =
+        super::build_command(var, &wrapped, &DetectConfig::default(), &["x=y".to_owned()]).unwrap();
+    let inv = super::command_invocation(&cmd_vec, None).unwrap();
+    println!("{inv}");
+    let command: Vec<String> = serde_json::from_value(inv["command"].clone()).unwrap();
+    assert_eq!(command, cmd_vec);
+    let env_vars: HashMap<String, String> = serde_json::from_value(inv["env"].clone()).unwrap();
+    assert_eq!(
+        env_vars,
+        HashMap::from([
+            ("LC_ALL".to_owned(), "C".to_owned()),
+            ("DEBIAN_FRONTEND".to_owned(), "noninteractive".to_owned()),
+        ])
+    );
+}
+
+#[test]
+fn test_copy_file_replace() {
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+    let dstdir = tempd.path().join("sources");
+    fs::create_dir(&dstdir).unwrap();
+    let dst = dstdir.join("storpool.sources");
+    fs::write(&dst, "some much longer stale contents that must not remain").unwrap();
+    fs::set_permissions(&dst, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner {
+            uid: Uid::effective().as_raw(),
+            gid: Gid::effective().as_raw(),
+        }),
+        ..repo_add_config(&tempd)
+    };
+    super::copy_file(
+        "storpool.sources",
+        &vdir,
+        dstdir.to_str().unwrap(),
+        &config,
+        &RealFs,
+        &Manifest::default(),
+    )
+    .unwrap();
+    assert_eq!(fs::read(&dst).unwrap(), b"storpool.sources");
+    let meta = fs::metadata(&dst).unwrap();
+    assert_eq!(meta.mode() & 0o7777, 0o644);
+    assert_eq!(meta.uid(), Uid::effective().as_raw());
+    assert_eq!(meta.gid(), Gid::effective().as_raw());
+
+    let names: Vec<_> = fs::read_dir(&dstdir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["storpool.sources"]);
+}
+
+#[test]
+fn test_run_features() {
+    let varfull = sp_variant::build_variants();
+    let config = cli::parse_from(["storpool_variant", "features"]);
+    assert!(matches!(config.mode, Mode::Features));
+    super::run_config(varfull, config).unwrap();
+
+    let (major, minor) = sp_variant::get_format_version_from(varfull);
+    assert_eq!(
+        super::features_line(varfull),
+        format!(
+            "Features: format={major}.{minor} variant={version}",
+            version = sp_variant::get_program_version_from(varfull)
+        )
+    );
+}
+
+#[test]
+fn test_detect_json() {
+    let varfull = sp_variant::build_variants();
+    let var = sp_variant::get_from(varfull, "DEBIAN11").unwrap();
+    let detected = super::detect_json(varfull, var, Some("DEBIAN11:exact"));
+    println!("{detected}");
+    assert_eq!(detected["kind"], var.kind.to_string());
+    assert_eq!(detected["kind"], "DEBIAN11");
+
+    let (major, minor) = sp_variant::get_format_version_from(varfull);
+    assert_eq!(detected["format"]["version"]["major"], major);
+    assert_eq!(detected["format"]["version"]["minor"], minor);
+    assert_eq!(
+        detected["version"],
+        sp_variant::get_program_version_from(varfull)
+    );
+    assert_eq!(detected["arch"], std::env::consts::ARCH);
+    assert_eq!(
+        detected["init"],
+        sp_variant::detect_init_system().to_string()
+    );
+    let environment = sp_variant::detect_environment();
+    assert_eq!(detected["environment"]["wsl"], environment.wsl);
+    assert_eq!(detected["environment"]["container"], environment.container);
+    assert_eq!(detected["matched_rule"], "DEBIAN11:exact");
+    assert_eq!(detected.as_object().unwrap().len(), 7);
+
+    let overridden = super::detect_json(varfull, var, None);
+    assert!(overridden["matched_rule"].is_null());
+}
+
+#[test]
+fn test_repo_add_missing_keyring() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+    fs::remove_file(Path::new(&vdir).join("storpool-keyring.gpg")).unwrap();
+    let dstdir = tempd.path().join("keyrings");
+    fs::create_dir(&dstdir).unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        keyring_dir: Some(dstdir.to_str().unwrap().to_owned()),
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeRunner::default();
+    let err = super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&fake, &RealFs, &Manifest::default()),
+        &vdir,
+        deb,
+    )
+    .unwrap_err();
+    println!("{err:#}");
+    assert!(format!("{err:#}").contains("storpool-keyring.gpg"));
+    assert!(fake.calls.borrow().is_empty());
+    assert_eq!(fs::read_dir(&dstdir).unwrap().count(), 0);
+}
+
+#[test]
+fn test_show_compare() {
+    let varfull = sp_variant::build_variants();
+    let deb = sp_variant::get_from(varfull, "DEBIAN12").unwrap();
+    let yum = sp_variant::get_from(varfull, "ALMA9").unwrap();
+    let lines = super::compare_command_lines(deb, yum);
+    println!("{lines:#?}");
+    assert!(lines.contains(&"package.install: differs".to_owned()));
+    let pos = lines
+        .iter()
+        .position(|line| line == "package.install: differs")
+        .unwrap();
+    assert!(lines[pos + 1].starts_with("  DEBIAN12: env DEBIAN_FRONTEND=noninteractive apt-get"));
+    assert!(lines[pos + 2].starts_with("  ALMA9: dnf"));
+
+    assert!(super::compare_command_lines(deb, deb).is_empty());
+
+    let config = cli::parse_from(["storpool_variant", "show", "--compare", "DEBIAN12", "ALMA9"]);
+    match config.mode {
+        Mode::ShowCompare(compare) => {
+            assert_eq!(compare.first, "DEBIAN12");
+            assert_eq!(compare.second, "ALMA9");
+        }
+        other => panic!("Unexpected mode {other:?}"),
+    }
+}
+
+#[test]
+fn test_repo_add_manifest() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+    let config = repo_add_config(&tempd);
+    let manifest = Manifest::default();
+    let fake = FakeRunner::default();
+    let runner = RecordingRunner::new(&fake, &manifest, true);
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &RealFs, &manifest),
+        &vdir,
+        deb,
+    )
+    .unwrap();
+
+    let actions = manifest.actions();
+    println!("{actions:#?}");
+    assert!(actions.contains(&Action::Copy {
+        source: format!("{vdir}/storpool.sources"),
+        destination: "/etc/apt/sources.list.d/storpool.sources".to_owned(),
+        bytes: "storpool.sources".len(),
+    }));
+    assert!(actions.contains(&Action::Copy {
+        source: format!("{vdir}/storpool-keyring.gpg"),
+        destination: "/usr/share/keyrings/storpool-keyring.gpg".to_owned(),
+        bytes: "storpool-keyring.gpg".len(),
+    }));
+    assert_eq!(
+        actions.last(),
+        Some(&Action::Command {
+            argv: vec!["apt-get".to_owned(), "update".to_owned()],
+            exit_code: None,
+        })
+    );
+
+    let count = actions.len();
+    drop(actions);
+
+    let path = tempd.path().join("manifest.json");
+    manifest.write(path.to_str().unwrap()).unwrap();
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let written = written.as_array().unwrap();
+    assert_eq!(written.len(), count);
+    assert_eq!(written[0]["action"], "command");
+    assert!(written
+        .iter()
+        .any(|action| action["action"] == "copy" && action["bytes"] == "storpool.sources".len()));
+}
+
+/// Record the filesystem operations, serve the files from memory.
+#[derive(Debug, Default)]
+struct FakeFs {
+    files: Mutex<HashMap<String, Vec<u8>>>,
+    calls: Mutex<Vec<String>>,
+    /// Silently drop the last byte of each file written.
+    corrupt: bool,
+    /// Fail each write as if the program was interrupted.
+    interrupt: bool,
+}
+
+impl FsOps for FakeFs {
+    fn read(&self, path: &str) -> IoResult<Vec<u8>> {
+        self.calls.lock().unwrap().push(format!("read {path}"));
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| IoError::new(ErrorKind::NotFound, path))
+    }
+
+    /// Record the ownership along with the contents, since the real implementation
+    /// sets it before the file appears at its final path.
+    fn write(&self, path: &str, contents: &[u8], mode: u32, owner: Option<Owner>) -> IoResult<()> {
+        let owner = owner.map_or_else(
+            || "-".to_owned(),
+            |owner| format!("{uid}:{gid}", uid = owner.uid, gid = owner.gid),
+        );
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("write {path} {mode:o} {owner}"));
+        if self.interrupt {
+            return Err(IoError::new(ErrorKind::Interrupted, path));
+        }
+        let contents = if self.corrupt {
+            contents.split_last().map_or(&[][..], |(_, rest)| rest)
+        } else {
+            contents
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), contents.to_vec());
+        Ok(())
+    }
+
+    fn backup(&self, path: &str, backup: &str) -> IoResult<bool> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("backup {path} {backup}"));
+        let mut files = self.files.lock().unwrap();
+        match files.get(path).cloned() {
+            Some(contents) => {
+                files.insert(backup.to_owned(), contents);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[test]
+fn test_copy_file_fsops() {
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().insert(
+        "/repo/DEBIAN12/storpool.sources".to_owned(),
+        b"Signed-By: /usr/share/keyrings/storpool-keyring.gpg\n".to_vec(),
+    );
+    let (var, _) = deb_repo("DEBIAN12");
+    super::install_repo_file(
+        var,
+        RepoType::Contrib,
+        "/repo/DEBIAN12",
+        &config,
+        &fake,
+        &Manifest::default(),
+        |contents| super::rewrite_field(contents, "Signed-By: ", "/opt/keys/storpool-keyring.gpg"),
+    )
+    .unwrap();
+    assert_eq!(
+        *fake.calls.lock().unwrap(),
+        [
+            "read /repo/DEBIAN12/storpool.sources",
+            "write /etc/apt/sources.list.d/storpool.sources 644 0:0",
+        ]
+    );
+    assert_eq!(
+        fake.files.lock().unwrap()["/etc/apt/sources.list.d/storpool.sources"],
+        b"Signed-By: /opt/keys/storpool-keyring.gpg\n"
+    );
+
+    let fake = FakeFs::default();
+    super::copy_file(
+        "storpool.sources",
+        "/repo/DEBIAN12",
+        "/etc/apt/sources.list.d",
+        &config,
+        &fake,
+        &Manifest::default(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        *fake.calls.lock().unwrap(),
+        ["read /repo/DEBIAN12/storpool.sources"]
+    );
+}
+
+#[test]
+fn test_repo_add_inline_key() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let mut deb = deb.clone();
+    deb.inline_key = true;
+    deb.keyrings = Keyrings::new(vec!["debian/repo/storpool-keyring.asc".to_owned()]);
+    let mut var = var.clone();
+    var.repo = Repo::Deb(deb.clone());
+    let var = &var;
+
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        offline: true,
+        owner: Some(Owner::ROOT),
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().insert(
+        "/repo/DEBIAN12/storpool.sources".to_owned(),
+        b"Types: deb\nSigned-By: /usr/share/keyrings/storpool-keyring.gpg\nEnabled: yes\n".to_vec(),
+    );
+    fake.files.lock().unwrap().insert(
+        "/repo/DEBIAN12/storpool-keyring.asc".to_owned(),
+        b"-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmQINBGTest\n=abcd\n-----END PGP PUBLIC KEY BLOCK-----\n"
+            .to_vec(),
+    );
+    let runner = FakeRunner::default();
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+        &deb,
+    )
+    .unwrap();
+    assert!(runner.calls.borrow().is_empty());
+
+    let files = fake.files.lock().unwrap();
+    let sources =
+        String::from_utf8(files["/etc/apt/sources.list.d/storpool.sources"].clone()).unwrap();
+    println!("{sources}");
+    assert_eq!(
+        sources,
+        "Types: deb
+Signed-By:
+ -----BEGIN PGP PUBLIC KEY BLOCK-----
+ .
+ mQINBGTest
+ =abcd
+ -----END PGP PUBLIC KEY BLOCK-----
+Enabled: yes
+"
+    );
+    assert!(!files
+        .keys()
+        .any(|path| path.starts_with("/usr/share/keyrings/")));
+    drop(files);
+
+    fake.files.lock().unwrap().insert(
+        "/repo/DEBIAN12/storpool-keyring.asc".to_owned(),
+        b"\x99\x02\x0d\x04binary".to_vec(),
+    );
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+        &deb,
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn test_show_current_json() {
+    let varfull = sp_variant::build_variants();
+    let var = sp_variant::get_from(varfull, "DEBIAN12").unwrap();
+    let single = super::show_variant_json(varfull, var);
+    let current = super::show_current_json(varfull, var, Some("DEBIAN12:exact"));
+    println!("{current}");
+    assert_eq!(
+        current["init"],
+        sp_variant::detect_init_system().to_string()
+    );
+    let environment = sp_variant::detect_environment();
+    assert_eq!(current["environment"]["wsl"], environment.wsl);
+    assert_eq!(current["environment"]["container"], environment.container);
+    assert_eq!(current["matched_rule"], "DEBIAN12:exact");
+
+    // Everything else is the same as for the variant shown by name.
+    let mut common = current.as_object().unwrap().clone();
+    for key in ["environment", "init", "matched_rule"] {
+        common.remove(key);
+    }
+    assert_eq!(serde_json::Value::Object(common), single);
+
+    let overridden = super::show_current_json(varfull, var, None);
+    assert!(overridden["matched_rule"].is_null());
+}
+
+#[test]
+fn test_show_field() {
+    let varfull = sp_variant::build_variants();
+    let var = sp_variant::get_from(varfull, "ALMA9").unwrap();
+    let single = super::show_variant_json(varfull, var);
+    let variant = &single["variant"];
+
+    let name = super::extract_field(variant, "name").unwrap();
+    assert_eq!(super::field_text(name).unwrap(), "ALMA9");
+    let repo = super::extract_field(variant, "supported.repo").unwrap();
+    assert_eq!(
+        super::field_text(repo).unwrap(),
+        var.supported.repo.to_string()
+    );
+
+    let install = super::extract_field(variant, "commands.package.install").unwrap();
+    let install_cmd: Vec<String> = serde_json::from_value(install.clone()).unwrap();
+    assert_eq!(install_cmd, var.command("package", "install").unwrap());
+    assert_eq!(
+        super::field_text(install).unwrap(),
+        serde_json::to_string(&install_cmd).unwrap()
+    );
+    let first = super::extract_field(variant, "commands.package.install.0").unwrap();
+    assert_eq!(super::field_text(first).unwrap(), "dnf");
+
+    for bad in [
+        "nonexistent",
+        "commands.package.nonexistent",
+        "kind.sub",
+        "commands..install",
+    ] {
+        let err = super::extract_field(variant, bad).unwrap_err();
+        println!("{bad}: {err}");
+    }
+}
+
+#[test]
+fn test_repo_add_parallel() {
+    let (var, deb) = deb_repo("DEBIAN12");
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        repotypes: RepoType::VALUES.to_vec(),
+        parallel: true,
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeFs::default();
+    for fname in [
+        "storpool.sources",
+        "storpool-staging.sources",
+        "storpool-infra.sources",
+        "storpool-keyring.gpg",
+    ] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec());
+    }
+    let runner = FakeRunner::default();
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+        deb,
+    )
+    .unwrap();
+
+    let files = fake.files.lock().unwrap();
+    for (path, contents) in [
+        (
+            "/etc/apt/sources.list.d/storpool.sources",
+            "storpool.sources",
+        ),
+        (
+            "/etc/apt/sources.list.d/storpool-staging.sources",
+            "storpool-staging.sources",
+        ),
+        (
+            "/etc/apt/sources.list.d/storpool-infra.sources",
+            "storpool-infra.sources",
+        ),
+        (
+            "/usr/share/keyrings/storpool-keyring.gpg",
+            "storpool-keyring.gpg",
+        ),
+    ] {
+        assert_eq!(files[path], contents.as_bytes(), "{path}");
+    }
+    drop(files);
+    assert_eq!(
+        runner.calls.borrow().last().unwrap(),
+        &["apt-get".to_owned(), "update".to_owned()]
+    );
+}
+
+#[test]
+fn test_run_copy_jobs_errors() {
+    let jobs: Vec<super::CopyJob<'_>> = vec![
+        Box::new(|| Err(anyhow::anyhow!("first failure"))),
+        Box::new(|| Ok(())),
+        Box::new(|| Err(anyhow::anyhow!("second failure"))),
+    ];
+    let err = super::run_copy_jobs(jobs, true).unwrap_err();
+    let msg = format!("{err:#}");
+    println!("{msg}");
+    assert!(msg.contains("Could not copy 2 files"));
+    assert!(msg.contains("first failure"));
+    assert!(msg.contains("second failure"));
+
+    let jobs: Vec<super::CopyJob<'_>> = vec![
+        Box::new(|| Err(anyhow::anyhow!("first failure"))),
+        Box::new(|| panic!("Should not be reached in sequential mode")),
+    ];
+    let err = super::run_copy_jobs(jobs, false).unwrap_err();
+    assert_eq!(err.to_string(), "first failure");
+}
+
+#[test]
+fn test_variants_file() {
+    let varfull = sp_variant::build_variants();
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("variants.json");
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, super::serialize_variants(varfull, false).unwrap()).unwrap();
+
+    let config = cli::parse_from(["storpool_variant", "--variants-file", path_str, "features"]);
+    assert_eq!(config.variants_file.as_deref(), Some(path_str));
+    let loaded = super::load_variants_file(path_str).unwrap();
+    assert!(sp_variant::check_format_version_from(&loaded));
+    assert_eq!(
+        sp_variant::get_from(&loaded, "DEBIAN12").unwrap(),
+        sp_variant::get_from(varfull, "DEBIAN12").unwrap()
+    );
+
+    let mut data: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let minor = data["format"]["version"]["minor"].as_u64().unwrap();
+    data["format"]["version"]["minor"] = (minor + 1).into();
+    fs::write(&path, data.to_string()).unwrap();
+    let newer = super::load_variants_file(path_str).unwrap();
+    assert!(!sp_variant::check_format_version_from(&newer));
+
+    fs::write(&path, "{}").unwrap();
+    super::load_variants_file(path_str).unwrap_err();
+}
+
+#[test]
+fn test_repo_add_mirror() {
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        offline: true,
+        owner: Some(Owner::ROOT),
+        mirror: Some("http://mirror.example.com/storpool/".to_owned()),
+        ..repo_add_config(&tempd)
+    };
+
+    let (var, deb) = deb_repo("DEBIAN12");
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().extend([
+        (
+            "/repo/DEBIAN12/storpool.sources".to_owned(),
+            b"Types: deb\nURIs: https://repo.storpool.com/public/contrib/debian/\nSigned-By: /usr/share/keyrings/storpool-keyring.gpg\n".to_vec(),
+        ),
+        (
+            "/repo/DEBIAN12/storpool-keyring.gpg".to_owned(),
+            b"keyring".to_vec(),
+        ),
+    ]);
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&FakeRunner::default(), &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+        deb,
+    )
+    .unwrap();
+    let sources = String::from_utf8(
+        fake.files.lock().unwrap()["/etc/apt/sources.list.d/storpool.sources"].clone(),
+    )
+    .unwrap();
+    println!("{sources}");
+    assert_eq!(
+        sources,
+        "Types: deb
+URIs: http://mirror.example.com/storpool/public/contrib/debian/
+Signed-By: /usr/share/keyrings/storpool-keyring.gpg
+"
+    );
+    assert!(!sources.contains("repo.storpool.com"));
+
+    let (var, yum) = yum_repo("ALMA9");
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().extend([
+        (
+            "/repo/ALMA9/storpool-centos.repo".to_owned(),
+            b"[storpool-contrib]\nbaseurl=https://repo.storpool.com/public/contrib/centos/$releasever/$basearch/\ngpgkey=file:///etc/pki/rpm-gpg/RPM-GPG-KEY-StorPool\n".to_vec(),
+        ),
+        (
+            "/repo/ALMA9/RPM-GPG-KEY-StorPool".to_owned(),
+            b"keyring".to_vec(),
+        ),
+    ]);
+    super::repo_add_yum(
+        var,
+        &RepoAddConfig {
+            no_key_import: true,
+            ..config
+        },
+        &RepoAddContext::new(&FakeRunner::default(), &fake, &Manifest::default()),
+        "/repo/ALMA9",
+        yum,
+    )
+    .unwrap();
+    let yumdef = String::from_utf8(
+        fake.files.lock().unwrap()["/etc/yum.repos.d/storpool-centos.repo"].clone(),
+    )
+    .unwrap();
+    println!("{yumdef}");
+    assert_eq!(
+        yumdef,
+        "[storpool-contrib]
+baseurl=http://mirror.example.com/storpool/public/contrib/centos/$releasever/$basearch/
+gpgkey=file:///etc/pki/rpm-gpg/RPM-GPG-KEY-StorPool
+"
+    );
+    assert!(!yumdef.contains("repo.storpool.com"));
+}
+
+#[test]
+fn test_serialize_variants_stable() {
+    let varfull = sp_variant::build_variants();
+    let first = super::serialize_variants(varfull, false).unwrap();
+    assert_eq!(super::serialize_variants(varfull, false).unwrap(), first);
+
+    // A freshly loaded copy has its own hash maps with a different iteration order.
+    let loaded: VariantDefTop = serde_json::from_str(&first).unwrap();
+    assert_eq!(super::serialize_variants(&loaded, false).unwrap(), first);
+    assert_eq!(
+        super::serialize_variants(&loaded, true).unwrap(),
+        super::serialize_variants(varfull, true).unwrap()
+    );
+}
+
+#[test]
+fn test_repo_add_privileges() {
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        ..repo_add_config(&tempd)
+    };
+    let user = Uid::from_raw(1000);
+    let err = super::check_privileges(&config, user).unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("must be run as root"));
+    super::check_privileges(&config, Uid::from_raw(0)).unwrap();
+    super::check_privileges(&repo_add_config(&tempd), user).unwrap();
+    super::check_privileges(
+        &RepoAddConfig {
+            noop: false,
+            owner: Some(Owner { uid: 42, gid: 43 }),
+            ..repo_add_config(&tempd)
+        },
+        user,
+    )
+    .unwrap();
+
+    if Uid::effective().is_root() {
+        println!("Running as root, not checking the repo add failure");
+        return;
+    }
+    // Create the directories for all the variants in case the detection is overridden.
+    let varfull = sp_variant::build_variants();
+    for var in sp_variant::get_all_variants_in_order_from(varfull) {
+        fs::create_dir(tempd.path().join(var.kind.as_ref())).unwrap();
+    }
+    let detect = DetectOptions {
+        config: DetectConfig::default(),
+        cache: None,
+    };
+    let err = super::cmd_repo_add(varfull, &detect, &config).unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("must be run as root"));
+}
+
+#[test]
+fn test_list_variants() {
+    let config = cli::parse_from(["storpool_variant", "list-variants", "--json"]);
+    assert!(matches!(
+        config.mode,
+        Mode::ListVariants(ListVariantsConfig { json: true })
+    ));
+
+    let varfull = sp_variant::build_variants();
+    let lines = super::list_variants_lines(varfull).unwrap();
+    println!("{lines:#?}");
+    assert_eq!(lines.len(), cardinality::<VariantKind>());
+    let debian12 = sp_variant::get_from(varfull, "DEBIAN12").unwrap();
+    assert!(lines.iter().any(|line| line.starts_with("DEBIAN12 ")
+        && line.ends_with(&format!("  deb  {descr}", descr = debian12.descr))));
+
+    let listed = super::list_variants_json(varfull).unwrap();
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), cardinality::<VariantKind>());
+    let alma9 = listed.iter().find(|var| var["name"] == "ALMA9").unwrap();
+    assert_eq!(alma9["repo"], "yum");
+    assert_eq!(
+        alma9["descr"],
+        sp_variant::get_from(varfull, "ALMA9").unwrap().descr
+    );
+}
+
+#[test]
+fn test_command_templates() {
+    let varfull = sp_variant::build_variants();
+    let debian11 = sp_variant::get_from(varfull, "DEBIAN11").unwrap();
+    let tempd = tempfile::tempdir().unwrap();
+    let os_release = tempd.path().join("os-release");
+    fs::write(&os_release, "ID=debian\nVERSION_ID=\"11\"\n").unwrap();
+    let os_release = || Ok(os_release.clone());
+
+    let config = CommandRunConfig {
+        category: "package".to_owned(),
+        name: "install".to_owned(),
+        noop: true,
+        args: vec![],
+        args_stdin: false,
+        shell: false,
+        dump_env: false,
+        repotype: RepoType::Staging,
+        wrap: vec![],
+        ignore_failure: false,
+        cwd: None,
+    };
+    let lookup = |name: &str| super::template_value(debian11, &config, &os_release, name);
+    assert_eq!(
+        super::expand_word("${CODENAME}", &lookup).unwrap(),
+        "bullseye"
+    );
+    assert_eq!(
+        super::expand_word("dists/${CODENAME}-${REPO_TYPE}/v${VERSION_ID}", &lookup).unwrap(),
+        "dists/bullseye-staging/v11"
+    );
+    for untouched in [
+        "${Package}\\t${db:Status-Abbrev}\\n",
+        "$packages",
+        "${",
+        "${CODENAME",
+    ] {
+        assert_eq!(super::expand_word(untouched, &lookup).unwrap(), untouched);
+    }
+    let err = super::expand_word("${NO_SUCH_THING}", &lookup).unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("${NO_SUCH_THING}"));
+
+    let alma9 = sp_variant::get_from(varfull, "ALMA9").unwrap();
+    super::template_value(alma9, &config, &os_release, "CODENAME").unwrap_err();
+
+    // The dpkg-query format fields must survive the templating pass.
+    let config = CommandRunConfig {
+        category: "package".to_owned(),
+        name: "list_all".to_owned(),
+        ..config
+    };
+    assert_eq!(
+        super::build_command(debian11, &config, &DetectConfig::default(), &[]).unwrap(),
+        debian11.command("package", "list_all").unwrap()
+    );
+}
+
+#[test]
+fn test_repo_add_package_manager() {
+    let tempd = tempfile::tempdir().unwrap();
+    let bindir = tempd.path().join("bin");
+    fs::create_dir(&bindir).unwrap();
+    let path = env::join_paths([tempd.path().join("nonexistent"), bindir.clone()]).unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        ..repo_add_config(&tempd)
+    };
+    let (debian12, _) = deb_repo("DEBIAN12");
+    let (alma9, _) = yum_repo("ALMA9");
+
+    for var in [debian12, alma9] {
+        let err = super::check_package_manager(var, &config, Some(&path)).unwrap_err();
+        println!("{err:#}");
+        assert!(err
+            .to_string()
+            .contains(if var == debian12 { "apt-get" } else { "yum" }));
+    }
+    super::check_package_manager(debian12, &config, None).unwrap_err();
+
+    // A non-executable file does not count.
+    fs::write(bindir.join("apt-get"), "").unwrap();
+    super::check_package_manager(debian12, &config, Some(&path)).unwrap_err();
+    fs::set_permissions(bindir.join("apt-get"), fs::Permissions::from_mode(0o755)).unwrap();
+    super::check_package_manager(debian12, &config, Some(&path)).unwrap();
+    super::check_package_manager(alma9, &config, Some(&path)).unwrap_err();
+
+    // No need to look for it if it will not be run.
+    for skip in [
+        repo_add_config(&tempd),
+        RepoAddConfig {
+            noop: false,
+            offline: true,
+            ..repo_add_config(&tempd)
+        },
+        RepoAddConfig {
+            noop: false,
+            only_keyring: true,
+            ..repo_add_config(&tempd)
+        },
+    ] {
+        super::check_package_manager(alma9, &skip, Some(&path)).unwrap();
+    }
+}
+
+#[test]
+fn test_copy_file_verify_checksums() {
+    assert_eq!(
+        super::sha256_hex(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        verify_checksums: true,
+        ..repo_add_config(&tempd)
+    };
+    let copy = |fake: &FakeFs| {
+        fake.files.lock().unwrap().insert(
+            "/repo/DEBIAN12/storpool-keyring.gpg".to_owned(),
+            b"keyring".to_vec(),
+        );
+        super::copy_file(
+            "storpool-keyring.gpg",
+            "/repo/DEBIAN12",
+            "/usr/share/keyrings",
+            &config,
+            fake,
+            &Manifest::default(),
+        )
+    };
+
+    let fake = FakeFs::default();
+    copy(&fake).unwrap();
+    assert_eq!(
+        *fake.calls.lock().unwrap(),
+        [
+            "read /repo/DEBIAN12/storpool-keyring.gpg",
+            "write /usr/share/keyrings/storpool-keyring.gpg 644 0:0",
+            "read /usr/share/keyrings/storpool-keyring.gpg",
+        ]
+    );
+
+    let fake = FakeFs {
+        corrupt: true,
+        ..FakeFs::default()
+    };
+    let err = copy(&fake).unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("Checksum mismatch"));
+    assert!(err.to_string().contains(&super::sha256_hex(b"keyring")));
+    assert!(err.to_string().contains(&super::sha256_hex(b"keyrin")));
+}
+
+#[test]
+fn test_command_run_wrap() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "command",
+        "run",
+        "--wrap",
+        "nice -n 19 'ionice' -c\\ 3",
+        "package.install",
+        "curl",
+    ]);
+    let config = match config.mode {
+        Mode::CommandRun(config) => config,
+        other => panic!("Unexpected mode {other:?}"),
+    };
+    assert_eq!(config.wrap, ["nice", "-n", "19", "ionice", "-c 3"]);
+
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    let command = var.command("package", "install").unwrap();
+    let cmd_vec = super::build_command(var, &config, &DetectConfig::default(), &[]).unwrap();
+    println!("{cmd_vec:?}");
+    let (wrapper, rest) = cmd_vec.split_at(config.wrap.len());
+    assert_eq!(wrapper, config.wrap);
+    let (cmd, args) = rest.split_at(command.len());
+    assert_eq!(cmd, command);
+    assert_eq!(args, ["curl"]);
+
+    let shell_config = CommandRunConfig {
+        shell: true,
+        ..config
+    };
+    let cmd_vec = super::build_command(var, &shell_config, &DetectConfig::default(), &[]).unwrap();
+    assert_eq!(
+        cmd_vec[..7],
+        ["nice", "-n", "19", "ionice", "-c 3", "sh", "-c"]
+    );
+
+    for invalid in ["", "   ", "nice 'oops"] {
+        cli::Cli::try_parse_from([
+            "storpool_variant",
+            "command",
+            "run",
+            "--wrap",
+            invalid,
+            "package.install",
+        ])
+        .unwrap_err();
+    }
+}
+
+#[test]
+fn test_copy_file_backup() {
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        backup: true,
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeFs::default();
+    fake.files.lock().unwrap().extend([
+        (
+            "/repo/DEBIAN12/storpool-keyring.gpg".to_owned(),
+            b"new keyring".to_vec(),
+        ),
+        (
+            "/usr/share/keyrings/storpool-keyring.gpg".to_owned(),
+            b"old keyring".to_vec(),
+        ),
+    ]);
+    let manifest = Manifest::default();
+    let copy = |config: &RepoAddConfig| {
+        super::copy_file(
+            "storpool-keyring.gpg",
+            "/repo/DEBIAN12",
+            "/usr/share/keyrings",
+            config,
+            &fake,
+            &manifest,
+        )
+        .unwrap();
+    };
+
+    // No backups in no-operation mode.
+    copy(&RepoAddConfig {
+        noop: true,
+        backup: true,
+        ..repo_add_config(&tempd)
+    });
+    assert!(!fake
+        .files
+        .lock()
+        .unwrap()
+        .contains_key("/usr/share/keyrings/storpool-keyring.gpg.sp-bak"));
+
+    copy(&config);
+    let files = fake.files.lock().unwrap();
+    assert_eq!(
+        files["/usr/share/keyrings/storpool-keyring.gpg.sp-bak"],
+        b"old keyring"
+    );
+    assert_eq!(
+        files["/usr/share/keyrings/storpool-keyring.gpg"],
+        b"new keyring"
+    );
+    drop(files);
+    assert!(manifest.actions().contains(&Action::Backup {
+        path: "/usr/share/keyrings/storpool-keyring.gpg".to_owned(),
+        backup: "/usr/share/keyrings/storpool-keyring.gpg.sp-bak".to_owned(),
+    }));
+
+    // The real implementation keeps the old file's contents even though
+    // the new one is renamed over it.
+    let (tempd, vdir) = repo_add_prepare("DEBIAN12");
+    let dstdir = tempd.path().join("keyrings");
+    fs::create_dir(&dstdir).unwrap();
+    fs::write(dstdir.join("storpool-keyring.gpg"), "old keyring").unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: None,
+        backup: true,
+        ..repo_add_config(&tempd)
+    };
+    for _ in 0..2 {
+        super::copy_file(
+            "storpool-keyring.gpg",
+            &vdir,
+            dstdir.to_str().unwrap(),
+            &config,
+            &RealFs,
+            &Manifest::default(),
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        fs::read(dstdir.join("storpool-keyring.gpg")).unwrap(),
+        b"storpool-keyring.gpg"
+    );
+    assert_eq!(
+        fs::read(dstdir.join("storpool-keyring.gpg.sp-bak")).unwrap(),
+        b"storpool-keyring.gpg"
+    );
+}
+
+#[test]
+fn test_repo_add_dump_commands() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "repo",
+        "add",
+        "--dump-commands",
+        "-d",
+        "/x",
+    ]);
+    match config.mode {
+        Mode::RepoAdd(ref add) => assert!(add.noop && add.dump_commands),
+        ref other => panic!("Unexpected mode: {other:?}"),
+    }
+
+    let (var, yum) = yum_repo("ALMA9");
+    let (tempd, vdir) = repo_add_prepare("ALMA9");
+    let config = RepoAddConfig {
+        dump_commands: true,
+        repotypes: vec![RepoType::Contrib, RepoType::Staging],
+        ..repo_add_config(&tempd)
+    };
+    let manifest = Manifest::default();
+    let runner = RecordingRunner::new(&SkipRunner, &manifest, true);
+    super::repo_add_yum(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &RealFs, &manifest),
+        &vdir,
+        yum,
+    )
+    .unwrap();
+
+    let lines: Vec<String> = super::RepoAddReport::from_actions(&manifest.actions())
+        .commands_run
+        .iter()
+        .map(|argv| super::shell_command_line(argv))
+        .collect();
+    println!("{lines:#?}");
+    let mut expected = vec![
+        "yum '--disablerepo=storpool-*' install -q -y ca-certificates".to_owned(),
+        "yum '--disablerepo=*' --enablerepo=storpool-contrib --enablerepo=storpool-staging clean metadata"
+            .to_owned(),
+    ];
+    // The keys are only imported if the rpmkeys or the rpm and gpg tools are installed.
+    if let Some(cmdvec) =
+        sp_variant::keyring::key_import_command(var, sp_variant::keyring::YUM_KEYRING_DIR).unwrap()
+    {
+        expected.insert(1, cmdvec.join(" "));
+    }
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn test_repo_requirements() {
+    let config = cli::parse_from(["storpool_variant", "repo", "requirements"]);
+    assert!(matches!(config.mode, Mode::RepoRequirements));
+}
+
+#[test]
+fn test_copy_file_interrupted() {
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: None,
+        ..repo_add_config(&tempd)
+    };
+    let fake = FakeFs {
+        interrupt: true,
+        ..FakeFs::default()
+    };
+    fake.files.lock().unwrap().insert(
+        "/repo/DEBIAN12/storpool.sources".to_owned(),
+        b"Types: deb".to_vec(),
+    );
+    let manifest = Manifest::default();
+    let err = super::copy_file(
+        "storpool.sources",
+        "/repo/DEBIAN12",
+        "/etc/apt/sources.list.d",
+        &config,
+        &fake,
+        &manifest,
+    )
+    .unwrap_err();
+    println!("{err:?}");
+    assert!(!fake
+        .files
+        .lock()
+        .unwrap()
+        .contains_key("/etc/apt/sources.list.d/storpool.sources"));
+    assert!(manifest.actions().is_empty());
+
+    // The real implementation does not leave the temporary file behind if
+    // it cannot be renamed over the destination, e.g. a non-empty directory.
+    let dstdir = tempd.path().join("sources.list.d");
+    fs::create_dir_all(dstdir.join("storpool.sources/subdir")).unwrap();
+    RealFs
+        .write(
+            dstdir.join("storpool.sources").to_str().unwrap(),
+            b"Types: deb",
+            0o644,
+            None,
+        )
+        .unwrap_err();
+    let left: Vec<_> = fs::read_dir(&dstdir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(left, ["storpool.sources"]);
+}
+
+#[test]
+fn test_show_output() {
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("all.json");
+    let path_str = path.to_str().unwrap();
+    let config = cli::parse_from(["storpool_variant", "show", "all", "--output", path_str]);
+    let show = match config.mode {
+        Mode::Show(show) => show,
+        other => panic!("Unexpected mode: {other:?}"),
+    };
+    assert_eq!(show.output.as_deref(), Some(path_str));
+    super::cmd_show(sp_variant::build_variants(), &config.detect, &show).unwrap();
+
+    let loaded: VariantDefTop = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+        sp_variant::get_all_variants_in_order_from(&loaded).count(),
+        cardinality::<VariantKind>()
+    );
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o644
+    );
+}
+
+#[test]
+fn test_detect_all_matches_cli() {
+    let config = cli::parse_from(["storpool_variant", "detect", "--all-matches"]);
+    match config.mode {
+        Mode::Detect(ref detect) => assert!(detect.all_matches && !detect.explain),
+        ref other => panic!("Unexpected mode {other:?}"),
+    }
+    for conflicting in ["--check", "--explain", "--json"] {
+        cli::Cli::try_parse_from(["storpool_variant", "detect", "--all-matches", conflicting])
+            .unwrap_err();
+    }
+}
+
+#[test]
+fn test_command_run_ignore_failure() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "command",
+        "run",
+        "--ignore-failure",
+        "package.install",
+        "curl",
+    ]);
+    match config.mode {
+        Mode::CommandRun(ref run) => assert!(run.ignore_failure),
+        ref other => panic!("Unexpected mode {other:?}"),
+    }
+
+    let cmd_vec = vec!["false".to_owned()];
+    let fake = FakeRunner::with_statuses(&[1 << 8]);
+    let err = super::run_distro_command(&fake, &cmd_vec, false).unwrap_err();
+    println!("{err:#}");
+    assert_eq!(super::failure_code(&err), super::EXIT_SUBPROCESS);
+
+    let fake = FakeRunner::with_statuses(&[1 << 8]);
+    super::run_distro_command(&fake, &cmd_vec, true).unwrap();
+    assert_eq!(fake.calls.borrow().len(), 1);
+
+    // A command killed by a signal is still a failure.
+    let fake = FakeRunner::with_statuses(&[9]);
+    super::run_distro_command(&fake, &cmd_vec, true).unwrap_err();
+}
+
+#[test]
+fn test_command_batch() {
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("batch.txt");
+    let path_str = path.to_str().unwrap();
+    let config = cli::parse_from(["storpool_variant", "command", "batch", "-N", path_str]);
+    let config = match config.mode {
+        Mode::CommandBatch(batch) => batch,
+        other => panic!("Unexpected mode {other:?}"),
+    };
+    assert!(config.noop && !config.keep_going);
+
+    let var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12").unwrap();
+    let contents = "package.install curl 'x y'\n\n  # a comment\npackage.remove jq\n";
+    let fake = FakeRunner::default();
+    super::run_batch(var, &config, &DetectConfig::default(), contents, &fake).unwrap();
+    let calls = fake.calls.borrow();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[0].ends_with(&["curl".to_owned(), "x y".to_owned()]));
+    assert!(calls[1].ends_with(&["jq".to_owned()]));
+
+    // Stop at the first failure unless told to keep going.
+    let fake = FakeRunner::with_statuses(&[1 << 8]);
+    super::run_batch(var, &config, &DetectConfig::default(), contents, &fake).unwrap_err();
+    assert_eq!(fake.calls.borrow().len(), 1);
+
+    let keep_going = CommandBatchConfig {
+        keep_going: true,
+        ..config
+    };
+    let fake = FakeRunner::with_statuses(&[1 << 8]);
+    let err =
+        super::run_batch(var, &keep_going, &DetectConfig::default(), contents, &fake).unwrap_err();
+    println!("{err:#}");
+    assert_eq!(err.to_string(), "1 of 2 commands failed");
+    assert_eq!(fake.calls.borrow().len(), 2);
+
+    // Nothing is run if any of the lines is invalid.
+    let fake = FakeRunner::default();
+    let err = super::run_batch(
+        var,
+        &keep_going,
+        &DetectConfig::default(),
+        "package.install curl\nnope\n",
+        &fake,
+    )
+    .unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().ends_with("line 2"));
+    assert!(fake.calls.borrow().is_empty());
+}
+
+#[test]
+fn test_validate() {
+    let varfull = sp_variant::build_variants();
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("variants.json");
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, super::serialize_variants(varfull, false).unwrap()).unwrap();
+
+    let config = cli::parse_from(["storpool_variant", "validate", "--variants-file", path_str]);
+    assert!(matches!(config.mode, Mode::Validate));
+    assert_eq!(config.variants_file.as_deref(), Some(path_str));
+    let loaded = super::load_variants_file(path_str).unwrap();
+    super::cmd_validate(&loaded, Some(path_str)).unwrap();
+    super::cmd_validate(&loaded, None).unwrap_err();
+
+    // Drop a variant from the detection order and bump the format version.
+    let mut data: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    data["order"].as_array_mut().unwrap().pop();
+    data["format"]["version"]["major"] = 99.into();
+    fs::write(&path, data.to_string()).unwrap();
+    let loaded = super::load_variants_file(path_str).unwrap();
+    let problems = super::validate_problems(&loaded);
+    println!("{problems:#?}");
+    assert_eq!(problems.len(), 2);
+    assert!(problems[1].starts_with("Unsupported format version 99."));
+    let err = super::cmd_validate(&loaded, Some(path_str)).unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("2 problem(s)"));
+}
+
+#[test]
+fn test_show_compact() {
+    let varfull = sp_variant::build_variants();
+    let show_config = |args: &[&str]| {
+        let config = cli::parse_from(["storpool_variant", "show"].iter().chain(args));
+        match config.mode {
+            Mode::Show(show) => show,
+            other => panic!("Unexpected mode: {other:?}"),
+        }
+    };
+    let show_text = |args: &[&str], stdout_is_tty: bool| {
+        let config = cli::parse_from(["storpool_variant", "show"].iter().chain(args));
+        let show = show_config(args);
+        let pretty = super::show_pretty(&show, stdout_is_tty);
+        super::show_text(varfull, &config.detect, &show, pretty).unwrap()
+    };
+
+    for name in ["DEBIAN12", "all"] {
+        // By default, pretty-print only if the output goes to a terminal.
+        let pretty = show_text(&[name], true);
+        assert!(pretty.lines().count() > 1, "{name}");
+        let compact = show_text(&[name], false);
+        assert_eq!(compact.lines().count(), 1, "{name}");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap()
+        );
+
+        // The options override the default either way.
+        for stdout_is_tty in [false, true] {
+            assert_eq!(show_text(&["--pretty", name], stdout_is_tty), pretty);
+            assert_eq!(show_text(&["--compact", name], stdout_is_tty), compact);
+        }
+    }
+
+    // The output written to a file is not pretty-printed unless requested.
+    assert!(!super::show_pretty(
+        &show_config(&["-o", "/x", "all"]),
+        true
+    ));
+    assert!(super::show_pretty(
+        &show_config(&["--pretty", "-o", "/x", "all"]),
+        false
+    ));
+
+    for args in [
+        &["--pretty", "--compact", "DEBIAN12"][..],
+        &["--field", "name", "--compact", "DEBIAN12"][..],
+    ] {
+        cli::Cli::try_parse_from(["storpool_variant", "show"].iter().chain(args)).unwrap_err();
+    }
+}
+
+#[cfg(feature = "tarball")]
+#[test]
+fn test_repo_add_tarball() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::archive::{self, ArchiveFs};
+
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("repo.tar.gz");
+    let path_str = path.to_str().unwrap();
+    let mut builder = tar::Builder::new(GzEncoder::new(
+        fs::File::create(&path).unwrap(),
+        Compression::default(),
+    ));
+    for (fname, contents) in [
+        ("./DEBIAN12/storpool.sources", &b"Types: deb\n"[..]),
+        ("./DEBIAN12/storpool-keyring.gpg", &b"keyring"[..]),
+        ("./ALMA9/storpool-centos.repo", &b"[storpool]\n"[..]),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len().try_into().unwrap());
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, fname, contents).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+
+    assert!(archive::is_archive(path_str));
+    assert!(!archive::is_archive(tempd.path().to_str().unwrap()));
+    let fake = FakeFs::default();
+    let loaded = ArchiveFs::load(path_str, &fake).unwrap();
+    assert!(loaded.has_dir("DEBIAN12"));
+    assert!(!loaded.has_dir("DEBIAN11"));
+
+    let config = RepoAddConfig {
+        noop: false,
+        offline: true,
+        owner: Some(Owner::ROOT),
+        repodir: path_str.to_owned(),
+        ..repo_add_config(&tempd)
+    };
+    let (var, deb) = deb_repo("DEBIAN12");
+    super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&FakeRunner::default(), &loaded, &Manifest::default()),
+        &format!("{path_str}/DEBIAN12"),
+        deb,
+    )
+    .unwrap();
+    let files = fake.files.lock().unwrap();
+    assert_eq!(
+        files["/etc/apt/sources.list.d/storpool.sources"],
+        b"Types: deb\n"
+    );
+    assert_eq!(
+        files["/usr/share/keyrings/storpool-keyring.gpg"],
+        b"keyring"
+    );
+    assert_eq!(files.len(), 2);
+    drop(files);
+
+    // A file missing from the archive is reported before anything is written.
+    let config = RepoAddConfig {
+        repotypes: vec![RepoType::Staging],
+        ..config
+    };
+    let fake = FakeFs::default();
+    let loaded = ArchiveFs::load(path_str, &fake).unwrap();
+    let err = super::repo_add_deb(
+        var,
+        &config,
+        &RepoAddContext::new(&FakeRunner::default(), &loaded, &Manifest::default()),
+        &format!("{path_str}/DEBIAN12"),
+        deb,
+    )
+    .unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("storpool-staging.sources"));
+    assert!(fake.files.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_command_which() {
+    let config = cli::parse_from(["storpool_variant", "command", "which", "test.greet"]);
+    let config = match config.mode {
+        Mode::CommandWhich(which) => which,
+        other => panic!("Unexpected mode {other:?}"),
+    };
+    assert_eq!(config.command.category, "test");
+    assert_eq!(config.command.name, "greet");
+
+    let tempd = tempfile::tempdir().unwrap();
+    let bindir = tempd.path().join("bin");
+    fs::create_dir(&bindir).unwrap();
+    let echo = bindir.join("echo");
+    fs::write(&echo, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&echo, fs::Permissions::from_mode(0o755)).unwrap();
+    let emptydir = tempd.path().join("empty");
+    fs::create_dir(&emptydir).unwrap();
+    let search = env::join_paths([&emptydir, &bindir]).unwrap();
+
+    let mut var = sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12")
+        .unwrap()
+        .clone();
+    var.commands.insert(
+        "test".to_owned(),
+        HashMap::from([("greet".to_owned(), vec!["echo".to_owned(), "hi".to_owned()])]),
+    );
+    assert_eq!(
+        super::resolve_command(&var, &config.command, Some(&search)).unwrap(),
+        echo
+    );
+
+    // Not found in the search path, or no search path at all.
+    let err =
+        super::resolve_command(&var, &config.command, Some(emptydir.as_os_str())).unwrap_err();
+    println!("{err:#}");
+    assert!(err.to_string().contains("echo"));
+    super::resolve_command(&var, &config.command, None).unwrap_err();
+
+    let unknown: cli::CommandId = "test.nonexistent".parse().unwrap();
+    super::resolve_command(&var, &unknown, Some(&search)).unwrap_err();
+}
+
+#[test]
+fn test_repo_add_if_changed() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "repo",
+        "add",
+        "--if-changed",
+        "-d",
+        "/repo",
+    ]);
+    match config.mode {
+        Mode::RepoAdd(ref add) => assert!(add.if_changed),
+        ref other => panic!("Unexpected mode {other:?}"),
+    }
+
+    let (var, _) = deb_repo("DEBIAN12");
+    let fake = FakeFs::default();
+    for fname in ["storpool.sources", "storpool-keyring.gpg"] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec());
+    }
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        if_changed: true,
+        ..repo_add_config(&tempd)
+    };
+    let run = || {
+        let runner = FakeRunner::default();
+        fake.calls.lock().unwrap().clear();
+        super::repo_add(
+            var,
+            &config,
+            &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+            "/repo/DEBIAN12",
+        )
+        .unwrap();
+        let writes = fake
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.starts_with("write "))
+            .count();
+        let commands = runner.calls.borrow().clone();
+        (writes, commands)
+    };
+
+    let (writes, commands) = run();
+    assert_eq!(writes, 2);
+    assert!(commands.contains(&vec!["apt-get".to_owned(), "update".to_owned()]));
+
+    // Nothing changed, so nothing is written and the package database is not updated.
+    let (writes, commands) = run();
+    assert_eq!(writes, 0);
+    assert!(commands.is_empty(), "{commands:?}");
+
+    // Only the modified file is written, and the package database is updated.
+    fake.files.lock().unwrap().insert(
+        "/repo/DEBIAN12/storpool.sources".to_owned(),
+        b"modified".to_vec(),
+    );
+    let (writes, commands) = run();
+    assert_eq!(writes, 1);
+    assert!(commands.contains(&vec!["apt-get".to_owned(), "update".to_owned()]));
+    assert_eq!(
+        fake.files.lock().unwrap()["/etc/apt/sources.list.d/storpool.sources"],
+        b"modified"
+    );
+}
+
+#[test]
+fn test_repo_add_proxy() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "repo",
+        "add",
+        "--http-proxy",
+        "http://proxy:3128",
+        "--https-proxy",
+        "http://sproxy:3128",
+        "-d",
+        "/repo",
+    ]);
+    match config.mode {
+        Mode::RepoAdd(ref add) => {
+            assert_eq!(add.http_proxy.as_deref(), Some("http://proxy:3128"));
+            assert_eq!(add.https_proxy.as_deref(), Some("http://sproxy:3128"));
+        }
+        ref other => panic!("Unexpected mode {other:?}"),
+    }
+
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        https_proxy: Some("http://proxy:3128".to_owned()),
+        ..repo_add_config(&tempd)
+    };
+
+    let (var, _) = deb_repo("DEBIAN12");
+    let fake = FakeFs::default();
+    for fname in ["storpool.sources", "storpool-keyring.gpg"] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec());
+    }
+    let runner = FakeRunner::default();
+    super::repo_add(
+        var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+    )
+    .unwrap();
+    let commands = runner.calls.borrow().clone();
+    println!("{commands:?}");
+    assert!(commands.contains(&vec![
+        "env".to_owned(),
+        "https_proxy=http://proxy:3128".to_owned(),
+        "apt-get".to_owned(),
+        "update".to_owned(),
+    ]));
+    assert!(!commands.iter().any(|cmd| cmd[0] == "apt-get"));
+    assert!(commands
+        .iter()
+        .all(|cmd| cmd[1..].iter().all(|word| word != "env")));
+
+    // Yum is also told about the proxy via its own option.
+    let (var, _) = yum_repo("ALMA9");
+    let fake = FakeFs::default();
+    for fname in ["storpool-centos.repo", "RPM-GPG-KEY-StorPool"] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/ALMA9/{fname}"), fname.as_bytes().to_vec());
+    }
+    let runner = FakeRunner::default();
+    super::repo_add(
+        var,
+        &RepoAddConfig {
+            http_proxy: Some("http://plain:8080".to_owned()),
+            ..config
+        },
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/ALMA9",
+    )
+    .unwrap();
+    let commands = runner.calls.borrow().clone();
+    println!("{commands:?}");
+    let install = commands
+        .iter()
+        .find(|cmd| cmd.contains(&"install".to_owned()))
+        .unwrap();
+    assert_eq!(
+        install[..4],
+        [
+            "env",
+            "http_proxy=http://plain:8080",
+            "https_proxy=http://proxy:3128",
+            "yum"
+        ]
+    );
+    assert_eq!(install[4], "--setopt=proxy=http://proxy:3128");
+}
+
+#[test]
+fn test_inventory() {
+    let config = cli::parse_from(["storpool_variant", "inventory"]);
+    assert!(matches!(config.mode, Mode::Inventory));
+
+    let varfull = sp_variant::build_variants();
+    let var = sp_variant::get_from(varfull, "ALMA9").unwrap();
+    let inventory = super::inventory_json(varfull, var, Some("ALMA9:exact"));
+    println!("{inventory}");
+    let keys: Vec<&str> = inventory
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "arch",
+            "environment",
+            "features",
+            "format",
+            "init",
+            "kind",
+            "matched_rule",
+            "version"
+        ]
+    );
+    assert_eq!(inventory["kind"], "ALMA9");
+    assert_eq!(
+        inventory["features"]["variant"],
+        sp_variant::get_program_version_from(varfull)
+    );
+    assert_eq!(
+        super::features_line(varfull),
+        format!(
+            "Features: format={format} variant={variant}",
+            format = inventory["features"]["format"].as_str().unwrap(),
+            variant = inventory["features"]["variant"].as_str().unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_commands_file() {
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("commands.json");
+    let path_str = path.to_str().unwrap();
+    fs::write(
+        &path,
+        r#"{"site": {"hello": ["echo", "hello"]}, "package": {"install": ["site-install", "-y"]}}"#,
+    )
+    .unwrap();
+
+    let config = cli::parse_from([
+        "storpool_variant",
+        "--commands-file",
+        path_str,
+        "command",
+        "run",
+        "site.hello",
+        "world",
+    ]);
+    assert_eq!(config.commands_file.as_deref(), Some(path_str));
+    let config = match config.mode {
+        Mode::CommandRun(config) => config,
+        other => panic!("Unexpected mode {other:?}"),
+    };
+
+    let mut varfull = sp_variant::build_variants().clone();
+    sp_variant::merge_commands_into(&mut varfull, &super::load_commands_file(path_str).unwrap());
+    let var = sp_variant::get_from(&varfull, "DEBIAN12").unwrap();
+    assert_eq!(
+        var.command("package", "install").unwrap(),
+        ["site-install", "-y"]
+    );
+    assert_eq!(
+        var.command("package", "remove").unwrap(),
+        sp_variant::get_from(sp_variant::build_variants(), "DEBIAN12")
+            .unwrap()
+            .command("package", "remove")
+            .unwrap()
+    );
+
+    let fake = FakeRunner::default();
+    let cmd_vec = super::build_command(var, &config, &DetectConfig::default(), &[]).unwrap();
+    super::run_distro_command(&fake, &cmd_vec, false).unwrap();
+    assert_eq!(*fake.calls.borrow(), [["echo", "hello", "world"]]);
+
+    for invalid in [
+        "[]",
+        r#"{"site": ["echo"]}"#,
+        r#"{"site": {"hello": []}}"#,
+        r#"{"site": {"hello": [""]}}"#,
+        r#"{"site.x": {"hello": ["echo"]}}"#,
+        r#"{"site": {"": ["echo"]}}"#,
+    ] {
+        fs::write(&path, invalid).unwrap();
+        super::load_commands_file(path_str).unwrap_err();
+    }
+}
+
+#[test]
+fn test_repo_add_assume_no() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "repo",
+        "add",
+        "--assume-no",
+        "-d",
+        "/repo",
+    ]);
+    let config = match config.mode {
+        Mode::RepoAdd(config) => config,
+        other => panic!("Unexpected mode {other:?}"),
+    };
+    assert!(config.noop);
+    assert!(!config.assume_yes);
+    cli::Cli::try_parse_from([
+        "storpool_variant",
+        "repo",
+        "add",
+        "--assume-yes",
+        "--assume-no",
+    ])
+    .unwrap_err();
+
+    let (var, _) = deb_repo("DEBIAN12");
+    let fake = FakeFs::default();
+    for fname in ["storpool.sources", "storpool-keyring.gpg"] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec());
+    }
+    let runner = FakeRunner::default();
+    super::repo_add(
+        var,
+        &RepoAddConfig {
+            owner: Some(Owner::ROOT),
+            ..config.clone()
+        },
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+    )
+    .unwrap();
+    let calls = fake.calls.lock().unwrap().clone();
+    println!("{calls:?}");
+    assert!(!calls.iter().any(|call| call.starts_with("write ")));
+
+    // The summary shown before asking for confirmation lists the planned changes.
+    let changes = super::describe_actions(
+        &super::planned_actions(
+            var,
+            &RepoAddConfig {
+                noop: false,
+                ..config
+            },
+            &fake,
+            "/repo/DEBIAN12",
+        )
+        .unwrap(),
+    );
+    println!("{changes:#?}");
+    assert!(changes.contains(&"install /etc/apt/sources.list.d/storpool.sources".to_owned()));
+    assert!(changes.contains(&"run apt-get update".to_owned()));
+    for (answer, expected) in [
+        ("y\n", true),
+        ("Yes\n", true),
+        ("\n", false),
+        ("nope\n", false),
+    ] {
+        let mut output = Vec::new();
+        assert_eq!(
+            super::confirm_changes(&changes, answer.as_bytes(), &mut output).unwrap(),
+            expected
+        );
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("  run apt-get update\nProceed? [y/N] "));
+    }
+}
+
+#[test]
+fn test_repo_add_post_add() {
+    let (var, _) = deb_repo("DEBIAN12");
+    assert!(var.post_add.is_empty());
+    let mut var = var.clone();
+    var.post_add = vec![vec![
+        "apt-get".to_owned(),
+        "install".to_owned(),
+        "apt-transport-https".to_owned(),
+    ]];
+
+    let fake = FakeFs::default();
+    for fname in ["storpool.sources", "storpool-keyring.gpg"] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec());
+    }
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner::ROOT),
+        ..repo_add_config(&tempd)
+    };
+    let runner = FakeRunner::default();
+    super::repo_add(
+        &var,
+        &config,
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+    )
+    .unwrap();
+    let calls = runner.calls.borrow().clone();
+    println!("{calls:?}");
+    assert_eq!(calls.last(), var.post_add.last());
+    assert_eq!(
+        calls.iter().filter(|cmd| **cmd == var.post_add[0]).count(),
+        1
+    );
+
+    // The post-add commands need network access.
+    let runner = FakeRunner::default();
+    super::repo_add(
+        &var,
+        &RepoAddConfig {
+            offline: true,
+            ..config
+        },
+        &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+        "/repo/DEBIAN12",
+    )
+    .unwrap();
+    assert!(runner.calls.borrow().is_empty());
+}
+
+#[test]
+fn test_repo_add_report() {
+    let (var, _) = deb_repo("DEBIAN12");
+    let fake = FakeFs::default();
+    for fname in [
+        "storpool.sources",
+        "storpool-staging.sources",
+        "storpool-keyring.gpg",
+    ] {
+        fake.files
+            .lock()
+            .unwrap()
+            .insert(format!("/repo/DEBIAN12/{fname}"), fname.as_bytes().to_vec());
+    }
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        owner: Some(Owner::ROOT),
+        repotypes: vec![RepoType::Contrib, RepoType::Staging],
+        ..repo_add_config(&tempd)
+    };
+    let report = super::repo_add_report(var, &config, &fake, "/repo/DEBIAN12").unwrap();
+    println!("{report:#?}");
+    assert_eq!(
+        report.files_written,
+        [
+            "/etc/apt/sources.list.d/storpool.sources",
+            "/etc/apt/sources.list.d/storpool-staging.sources",
+            "/usr/share/keyrings/storpool-keyring.gpg",
+        ]
+        .map(PathBuf::from)
+    );
+    assert_eq!(
+        report.commands_run.last().unwrap(),
+        &["apt-get".to_owned(), "update".to_owned()]
+    );
+    assert!(!fake
+        .calls
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|call| call.starts_with("write ")));
+}
+
+#[test]
+fn test_repo_add_no_update() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "repo",
+        "add",
+        "--no-update",
+        "-d",
+        "/repo",
+    ]);
+    match config.mode {
+        Mode::RepoAdd(ref add) => assert!(add.no_update && !add.offline),
+        ref other => panic!("Unexpected mode {other:?}"),
+    }
+
+    let tempd = tempfile::tempdir().unwrap();
+    let config = RepoAddConfig {
+        noop: false,
+        no_update: true,
+        owner: Some(Owner::ROOT),
+        ..repo_add_config(&tempd)
+    };
+    for (name, files) in [
+        ("DEBIAN12", ["storpool.sources", "storpool-keyring.gpg"]),
+        ("ALMA9", ["storpool-centos.repo", "RPM-GPG-KEY-StorPool"]),
+    ] {
+        let var = sp_variant::get_from(sp_variant::build_variants(), name).unwrap();
+        let fake = FakeFs::default();
+        for fname in files {
+            fake.files
+                .lock()
+                .unwrap()
+                .insert(format!("/repo/{name}/{fname}"), fname.as_bytes().to_vec());
+        }
+        let runner = FakeRunner::default();
+        super::repo_add(
+            var,
+            &config,
+            &RepoAddContext::new(&runner, &fake, &Manifest::default()),
+            &format!("/repo/{name}"),
+        )
+        .unwrap();
+        let writes = fake
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.starts_with("write "))
+            .count();
+        assert_eq!(writes, 2);
+
+        let commands = runner.calls.borrow().clone();
+        println!("{commands:?}");
+        assert!(!commands
+            .iter()
+            .any(|cmd| cmd.contains(&"metadata".to_owned())));
+        // The package database is only updated before installing the required packages.
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|cmd| **cmd == ["apt-get", "update"])
+                .count(),
+            usize::from(name == "DEBIAN12")
+        );
+    }
+}
+
+#[test]
+fn test_show_diff_embedded() {
+    let config = cli::parse_from([
+        "storpool_variant",
+        "--variants-file",
+        "variants.json",
+        "show",
+        "--diff-embedded",
+    ]);
+    assert!(matches!(config.mode, Mode::ShowDiffEmbedded));
+    cli::Cli::try_parse_from(["storpool_variant", "show", "--diff-embedded", "ALMA9"]).unwrap_err();
+
+    let varfull = sp_variant::build_variants();
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("variants.json");
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, super::serialize_variants(varfull, false).unwrap()).unwrap();
+    let loaded = super::load_variants_file(path_str).unwrap();
+    assert!(super::diff_embedded_lines(varfull, &loaded)
+        .unwrap()
+        .is_empty());
+
+    // The file defines a variant that the older embedded data does not.
+    let mut older: serde_json::Value = serde_json::to_value(varfull).unwrap();
+    older["variants"]
+        .as_object_mut()
+        .unwrap()
+        .remove("DEBIAN13")
+        .unwrap();
+    older["variants"]["ALMA9"]["commands"]["package"]["install"] = serde_json::json!(["yum"]);
+    older["variants"]["ALMA9"]["file_ext"] = "drpm".into();
+    let older: VariantDefTop = serde_json::from_value(older).unwrap();
+    let lines = super::diff_embedded_lines(&older, &loaded).unwrap();
+    println!("{lines:#?}");
+    let install = sp_variant::get_from(varfull, "ALMA9")
+        .unwrap()
+        .command("package", "install")
+        .unwrap()
+        .join(" ");
+    assert_eq!(
+        lines,
+        [
+            "ALMA9: changed".to_owned(),
+            "  package.install: differs".to_owned(),
+            "    embedded: yum".to_owned(),
+            format!("    file: {install}"),
+            "  file_ext: differs".to_owned(),
+            "DEBIAN13: added".to_owned(),
+        ]
+    );
+
+    let lines = super::diff_embedded_lines(&loaded, &older).unwrap();
+    assert!(lines.contains(&"DEBIAN13: removed".to_owned()));
+}
+
+/// Operate on the real filesystem, but refuse to read whole files into memory.
+#[derive(Debug)]
+struct StreamingFs;
+
+impl FsOps for StreamingFs {
+    fn read(&self, path: &str) -> IoResult<Vec<u8>> {
+        panic!("Unexpected read of {path}");
+    }
+
+    fn write(&self, path: &str, contents: &[u8], mode: u32, owner: Option<Owner>) -> IoResult<()> {
+        RealFs.write(path, contents, mode, owner)
+    }
+
+    fn copy(&self, src: &str, dst: &str, mode: u32, owner: Option<Owner>) -> IoResult<u64> {
+        RealFs.copy(src, dst, mode, owner)
+    }
+
+    fn size(&self, path: &str) -> IoResult<u64> {
+        RealFs.size(path)
+    }
+
+    fn backup(&self, path: &str, backup: &str) -> IoResult<bool> {
+        RealFs.backup(path, backup)
+    }
+}
+
+#[test]
+fn test_copy_file_large() {
+    let tempd = tempfile::tempdir().unwrap();
+    let srcdir = tempd.path().join("src");
+    let dstdir = tempd.path().join("dst");
+    fs::create_dir(&srcdir).unwrap();
+    fs::create_dir(&dstdir).unwrap();
+    let contents: Vec<u8> = (0..5_u32 * 1024 * 1024 + 17)
+        .map(|idx| u8::try_from(idx % 251).unwrap())
+        .collect();
+    fs::write(srcdir.join("bundle.bin"), &contents).unwrap();
+
+    let noop_manifest = Manifest::default();
+    super::copy_file(
+        "bundle.bin",
+        srcdir.to_str().unwrap(),
+        dstdir.to_str().unwrap(),
+        &repo_add_config(&tempd),
+        &StreamingFs,
+        &noop_manifest,
+    )
+    .unwrap();
+    assert!(!dstdir.join("bundle.bin").exists());
+    assert!(matches!(
+        noop_manifest.actions()[..],
+        [Action::Copy { bytes, .. }] if bytes == contents.len()
+    ));
+
+    let config = RepoAddConfig {
+        noop: false,
+        owner: Some(Owner {
+            uid: Uid::effective().as_raw(),
+            gid: Gid::effective().as_raw(),
+        }),
+        ..repo_add_config(&tempd)
+    };
+    let manifest = Manifest::default();
+    super::copy_file(
+        "bundle.bin",
+        srcdir.to_str().unwrap(),
+        dstdir.to_str().unwrap(),
+        &config,
+        &StreamingFs,
+        &manifest,
+    )
+    .unwrap();
+    assert!(matches!(
+        manifest.actions()[..],
+        [Action::Copy { bytes, .. }] if bytes == contents.len()
+    ));
+    assert!(fs::read(dstdir.join("bundle.bin")).unwrap() == contents);
+    let meta = fs::metadata(dstdir.join("bundle.bin")).unwrap();
+    assert_eq!(meta.mode() & 0o7777, 0o644);
+}
+
+#[test]
+fn test_command_run_cwd() {
+    let tempd = tempfile::tempdir().unwrap();
+    let dir = tempd.path().to_str().unwrap();
+    let config = cli::parse_from([
+        "storpool_variant",
+        "command",
+        "run",
+        "--cwd",
+        dir,
+        "package.install",
+        "curl",
+    ]);
+    match config.mode {
+        Mode::CommandRun(ref run) => assert_eq!(run.cwd.as_deref(), Some(dir)),
+        ref other => panic!("Unexpected mode: {other:?}"),
+    }
+
+    let cmdvec = ["sh", "-c", "pwd > pwd.txt"].map(str::to_owned);
+    let status = ProcessRunner {
+        cwd: Some(tempd.path()),
+    }
+    .run(&cmdvec)
+    .unwrap();
+    assert!(status.success());
+    let output = fs::read_to_string(tempd.path().join("pwd.txt")).unwrap();
+    assert_eq!(
+        Path::new(output.trim_end()).canonicalize().unwrap(),
+        tempd.path().canonicalize().unwrap()
+    );
+
+    let missing = tempd.path().join("no-such-dir");
+    let config = cli::parse_from([
+        "storpool_variant",
+        "command",
+        "run",
+        "-N",
+        "--cwd",
+        missing.to_str().unwrap(),
+        "package.install",
+    ]);
+    let err = super::run_config(sp_variant::build_variants(), config).unwrap_err();
+    println!("{err:#}");
+    assert!(format!("{err:#}").contains("no-such-dir working directory does not exist"));
+}
+
+#[test]
+fn test_interrupt_handler_restored() {
+    let tempd = tempfile::tempdir().unwrap();
+    let path = tempd.path().join("written.txt");
+    RealFs
+        .write(path.to_str().unwrap(), b"contents\n", 0o644, None)
+        .unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"contents\n");
+
+    // Other tests may be writing files at the same time; wait for them.
+    for _ in 0..100 {
+        let (active, installed) = fsops::interrupt_handler_status();
+        if active == 0 {
+            assert!(!installed);
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("The temporary files are still being written");
+}
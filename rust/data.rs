@@ -7,25 +7,32 @@
 //! The data is autogenerated from the supported StorPool build variants.
 
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::FromStr;
 
 use enum_iterator::Sequence;
 use once_cell::sync::Lazy;
-use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "json")]
+use schemars::JsonSchema;
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
-    Builder, DebRepo, Detect, Repo, Supported, Variant, VariantDefTop, VariantError, VariantFormat,
-    VariantFormatVersion, YumRepo,
+    Builder, DebRepo, Detect, Keyrings, Repo, Supported, Variant, VariantDefTop, VariantError,
+    VariantFormat, VariantFormatVersion, YumRepo,
 };
 
 /// The supported StorPool build variants (OS distribution, version, etc).
-#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Serialize, Deserialize, Sequence)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Sequence)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub enum VariantKind {
     /// AlmaLinux 8.x
     ALMA8,
     /// AlmaLinux 9.x
     ALMA9,
+    /// Amazon Linux 2
+    AMZN2,
     /// CentOS 7.x
     CENTOS7,
     /// CentOS 8.x
@@ -40,6 +47,8 @@ pub enum VariantKind {
     DEBIAN12,
     /// Debian 13.x (trixie/unstable)
     DEBIAN13,
+    /// Fedora 38
+    FEDORA38,
     /// Oracle Linux 7.x
     ORACLE7,
     /// Oracle Linux 8.x
@@ -65,6 +74,7 @@ pub enum VariantKind {
 impl VariantKind {
     const ALMA8_NAME: &'static str = "ALMA8";
     const ALMA9_NAME: &'static str = "ALMA9";
+    const AMZN2_NAME: &'static str = "AMZN2";
     const CENTOS7_NAME: &'static str = "CENTOS7";
     const CENTOS8_NAME: &'static str = "CENTOS8";
     const CENTOS9_NAME: &'static str = "CENTOS9";
@@ -72,6 +82,7 @@ impl VariantKind {
     const DEBIAN11_NAME: &'static str = "DEBIAN11";
     const DEBIAN12_NAME: &'static str = "DEBIAN12";
     const DEBIAN13_NAME: &'static str = "DEBIAN13";
+    const FEDORA38_NAME: &'static str = "FEDORA38";
     const ORACLE7_NAME: &'static str = "ORACLE7";
     const ORACLE8_NAME: &'static str = "ORACLE8";
     const ORACLE9_NAME: &'static str = "ORACLE9";
@@ -90,6 +101,7 @@ impl AsRef<str> for VariantKind {
         match *self {
             Self::ALMA8 => Self::ALMA8_NAME,
             Self::ALMA9 => Self::ALMA9_NAME,
+            Self::AMZN2 => Self::AMZN2_NAME,
             Self::CENTOS7 => Self::CENTOS7_NAME,
             Self::CENTOS8 => Self::CENTOS8_NAME,
             Self::CENTOS9 => Self::CENTOS9_NAME,
@@ -97,6 +109,7 @@ impl AsRef<str> for VariantKind {
             Self::DEBIAN11 => Self::DEBIAN11_NAME,
             Self::DEBIAN12 => Self::DEBIAN12_NAME,
             Self::DEBIAN13 => Self::DEBIAN13_NAME,
+            Self::FEDORA38 => Self::FEDORA38_NAME,
             Self::ORACLE7 => Self::ORACLE7_NAME,
             Self::ORACLE8 => Self::ORACLE8_NAME,
             Self::ORACLE9 => Self::ORACLE9_NAME,
@@ -119,6 +132,7 @@ impl FromStr for VariantKind {
         match value {
             Self::ALMA8_NAME => Ok(Self::ALMA8),
             Self::ALMA9_NAME => Ok(Self::ALMA9),
+            Self::AMZN2_NAME => Ok(Self::AMZN2),
             Self::CENTOS7_NAME => Ok(Self::CENTOS7),
             Self::CENTOS8_NAME => Ok(Self::CENTOS8),
             Self::CENTOS9_NAME => Ok(Self::CENTOS9),
@@ -126,6 +140,7 @@ impl FromStr for VariantKind {
             Self::DEBIAN11_NAME => Ok(Self::DEBIAN11),
             Self::DEBIAN12_NAME => Ok(Self::DEBIAN12),
             Self::DEBIAN13_NAME => Ok(Self::DEBIAN13),
+            Self::FEDORA38_NAME => Ok(Self::FEDORA38),
             Self::ORACLE7_NAME => Ok(Self::ORACLE7),
             Self::ORACLE8_NAME => Ok(Self::ORACLE8),
             Self::ORACLE9_NAME => Ok(Self::ORACLE9),
@@ -141,6 +156,34 @@ impl FromStr for VariantKind {
     }
 }
 
+impl Display for VariantKind {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_ref())
+    }
+}
+
+impl Serialize for VariantKind {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_ref())
+    }
+}
+
+impl<'de> Deserialize<'de> for VariantKind {
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(DeError::custom)
+    }
+}
+
 /// Return the definition of the StorPool variants.
 #[allow(clippy::manual_string_new)]
 #[allow(clippy::panic)]
@@ -152,10 +195,12 @@ pub fn get_variants() -> &'static VariantDefTop {
             format: VariantFormat {
                 version: VariantFormatVersion {
                     major: 1,
-                    minor: 4,
+                    minor: 5,
                 },
             },
             order: vec![
+                    VariantKind::AMZN2,
+                    VariantKind::FEDORA38,
                     VariantKind::ROCKY8,
                     VariantKind::ROCKY9,
                     VariantKind::RHEL8,
@@ -306,12 +351,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -467,12 +516,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.9".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -500,6 +553,168 @@ fi
                                 },
                             },
                     ),
+                    (
+                            VariantKind::AMZN2,
+                            Variant {
+                                kind: VariantKind::AMZN2,
+                                descr: "Amazon Linux 2".to_owned(),
+                                family: "redhat".to_owned(),
+                                parent: "CENTOS7".to_owned(),
+                                detect: Detect {
+                                    filename: "/etc/system-release".to_owned(),
+                                    #[allow(clippy::needless_raw_strings)]
+                                    regex: r"^ Amazon \s+ Linux \s+ release \s+ 2 \s".to_owned(),
+                                    os_id: "amzn".to_owned(),
+                                    #[allow(clippy::needless_raw_strings)]
+                                    os_version_regex: r"^2$".to_owned(),
+                                },
+                                supported: Supported {
+                                    repo: true,
+                                },
+                                commands: HashMap::from(
+                                    [
+                                        (
+                                            "package".to_owned(),
+                                            HashMap::from(
+                                                [
+                                                    (
+                                                        "install".to_owned(),
+                                                        vec![
+                                                            "yum".to_owned(),
+                                                            "--disablerepo=*".to_owned(),
+                                                            "--enablerepo=amzn2-core".to_owned(),
+                                                            "--enablerepo=storpool-contrib".to_owned(),
+                                                            "install".to_owned(),
+                                                            "-q".to_owned(),
+                                                            "-y".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "list_all".to_owned(),
+                                                        vec![
+                                                            "rpm".to_owned(),
+                                                            "-qa".to_owned(),
+                                                            "--qf".to_owned(),
+                                                            "%{Name}\\t%{EVR}\\t%{Arch}\\tii\\n".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "purge".to_owned(),
+                                                        vec![
+                                                            "yum".to_owned(),
+                                                            "remove".to_owned(),
+                                                            "-q".to_owned(),
+                                                            "-y".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "remove".to_owned(),
+                                                        vec![
+                                                            "yum".to_owned(),
+                                                            "remove".to_owned(),
+                                                            "-q".to_owned(),
+                                                            "-y".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "remove_impl".to_owned(),
+                                                        vec![
+                                                            "rpm".to_owned(),
+                                                            "-e".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "update_db".to_owned(),
+                                                        vec![
+                                                            "true".to_owned(),
+                                                        ],
+                                                    ),
+                                                ]
+                                            ),
+                                        ),
+                                        (
+                                            "pkgfile".to_owned(),
+                                            HashMap::from(
+                                                [
+                                                    (
+                                                        "dep_query".to_owned(),
+                                                        vec![
+                                                            "sh".to_owned(),
+                                                            "-c".to_owned(),
+                                                            "rpm -qpR -- \"$pkg\"".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "install".to_owned(),
+                                                        vec![
+                                                            "sh".to_owned(),
+                                                            "-c".to_owned(),
+                                                            "
+unset to_install to_reinstall
+for f in $packages; do
+    package=\"$(rpm -qp \"$f\")\"
+    if rpm -q -- \"$package\"; then
+        to_reinstall=\"$to_reinstall ./$f\"
+    else
+        to_install=\"$to_install ./$f\"
+    fi
+done
+
+if [ -n \"$to_install\" ]; then
+    yum install -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install
+fi
+if [ -n \"$to_reinstall\" ]; then
+    yum reinstall -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall
+fi
+".to_owned(),
+                                                        ],
+                                                    ),
+                                                ]
+                                            ),
+                                        ),
+                                    ]
+                                ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
+                                min_sys_python: "3.6".to_owned(),
+                                repo:
+                                    Repo::Yum(YumRepo {
+                                        yumdef: "redhat/repo/storpool-amzn.repo".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
+                                    }),
+                                    post_add: Vec::new(),
+                                    package: HashMap::from(
+                                    [
+                                        ("KMOD".to_owned(), "kmod".to_owned()),
+                                        ("LIBCGROUP".to_owned(), "libcgroup-tools".to_owned()),
+                                        ("LIBUDEV".to_owned(), "systemd-libs".to_owned()),
+                                        ("OPENSSL".to_owned(), "openssl-libs".to_owned()),
+                                        ("PERL_AUTODIE".to_owned(), "perl-autodie".to_owned()),
+                                        ("PERL_FILE_PATH".to_owned(), "perl-File-Path".to_owned()),
+                                        ("PERL_LWP_PROTO_HTTPS".to_owned(), "perl-LWP-Protocol-https".to_owned()),
+                                        ("PERL_SYS_SYSLOG".to_owned(), "perl-Sys-Syslog".to_owned()),
+                                        ("PROCPS".to_owned(), "procps-ng".to_owned()),
+                                        ("PYTHON_SIMPLEJSON".to_owned(), "python2-simplejson".to_owned()),
+                                        ("UDEV".to_owned(), "systemd".to_owned()),
+                                    ]
+                                ),
+                                systemd_lib: "usr/lib/systemd/system".to_owned(),
+                                file_ext: "rpm".to_owned(),
+                                initramfs_flavor: "mkinitrd".to_owned(),
+                                builder: Builder {
+                                    alias: "amzn2".to_owned(),
+                                    base_image: "amazonlinux:2".to_owned(),
+                                    branch: "".to_owned(),
+                                    kernel_package: "kernel".to_owned(),
+                                    utf8_locale: "en_US.utf8".to_owned(),
+                                },
+                            },
+                    ),
                     (
                             VariantKind::CENTOS7,
                             Variant {
@@ -599,6 +814,8 @@ fi
                                                     (
                                                         "install".to_owned(),
                                                         vec![
+                                                            "sh".to_owned(),
+                                                            "-c".to_owned(),
                                                             "
 unset to_install to_reinstall
 for f in $packages; do
@@ -624,12 +841,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -785,12 +1006,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -946,12 +1171,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.9".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -1101,17 +1330,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.7".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "buster".to_owned(),
                                         vendor: "debian".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python".to_owned()),
@@ -1257,17 +1492,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.9".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "bullseye".to_owned(),
                                         vendor: "debian".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python3".to_owned()),
@@ -1413,17 +1654,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.11".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "bookworm".to_owned(),
                                         vendor: "debian".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python3".to_owned()),
@@ -1569,17 +1816,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.11".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "unstable".to_owned(),
                                         vendor: "debian".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python3".to_owned()),
@@ -1603,6 +1856,170 @@ fi
                                 },
                             },
                     ),
+                    (
+                            VariantKind::FEDORA38,
+                            Variant {
+                                kind: VariantKind::FEDORA38,
+                                descr: "Fedora 38".to_owned(),
+                                family: "redhat".to_owned(),
+                                parent: "ALMA9".to_owned(),
+                                detect: Detect {
+                                    filename: "/etc/fedora-release".to_owned(),
+                                    #[allow(clippy::needless_raw_strings)]
+                                    regex: r"^ Fedora \s+ release \s+ 38 \s".to_owned(),
+                                    os_id: "fedora".to_owned(),
+                                    #[allow(clippy::needless_raw_strings)]
+                                    os_version_regex: r"^38$".to_owned(),
+                                },
+                                supported: Supported {
+                                    repo: false,
+                                },
+                                commands: HashMap::from(
+                                    [
+                                        (
+                                            "package".to_owned(),
+                                            HashMap::from(
+                                                [
+                                                    (
+                                                        "install".to_owned(),
+                                                        vec![
+                                                            "dnf".to_owned(),
+                                                            "--disablerepo=*".to_owned(),
+                                                            "--enablerepo=fedora".to_owned(),
+                                                            "--enablerepo=updates".to_owned(),
+                                                            "--enablerepo=storpool-contrib".to_owned(),
+                                                            "install".to_owned(),
+                                                            "-q".to_owned(),
+                                                            "-y".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "list_all".to_owned(),
+                                                        vec![
+                                                            "rpm".to_owned(),
+                                                            "-qa".to_owned(),
+                                                            "--qf".to_owned(),
+                                                            "%{Name}\\t%{EVR}\\t%{Arch}\\tii\\n".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "purge".to_owned(),
+                                                        vec![
+                                                            "dnf".to_owned(),
+                                                            "remove".to_owned(),
+                                                            "-q".to_owned(),
+                                                            "-y".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "remove".to_owned(),
+                                                        vec![
+                                                            "dnf".to_owned(),
+                                                            "remove".to_owned(),
+                                                            "-q".to_owned(),
+                                                            "-y".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "remove_impl".to_owned(),
+                                                        vec![
+                                                            "rpm".to_owned(),
+                                                            "-e".to_owned(),
+                                                            "--".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "update_db".to_owned(),
+                                                        vec![
+                                                            "true".to_owned(),
+                                                        ],
+                                                    ),
+                                                ]
+                                            ),
+                                        ),
+                                        (
+                                            "pkgfile".to_owned(),
+                                            HashMap::from(
+                                                [
+                                                    (
+                                                        "dep_query".to_owned(),
+                                                        vec![
+                                                            "sh".to_owned(),
+                                                            "-c".to_owned(),
+                                                            "rpm -qpR -- \"$pkg\"".to_owned(),
+                                                        ],
+                                                    ),
+                                                    (
+                                                        "install".to_owned(),
+                                                        vec![
+                                                            "sh".to_owned(),
+                                                            "-c".to_owned(),
+                                                            "
+unset to_install to_reinstall
+for f in $packages; do
+    package=\"$(rpm -qp \"$f\")\"
+    if rpm -q -- \"$package\"; then
+        to_reinstall=\"$to_reinstall ./$f\"
+    else
+        to_install=\"$to_install ./$f\"
+    fi
+done
+
+if [ -n \"$to_install\" ]; then
+    dnf install -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install
+fi
+if [ -n \"$to_reinstall\" ]; then
+    dnf reinstall -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall
+fi
+".to_owned(),
+                                                        ],
+                                                    ),
+                                                ]
+                                            ),
+                                        ),
+                                    ]
+                                ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
+                                min_sys_python: "3.11".to_owned(),
+                                repo:
+                                    Repo::Yum(YumRepo {
+                                        yumdef: "redhat/repo/storpool-fedora.repo".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
+                                    }),
+                                    post_add: Vec::new(),
+                                    package: HashMap::from(
+                                    [
+                                        ("KMOD".to_owned(), "kmod".to_owned()),
+                                        ("LIBCGROUP".to_owned(), "bash".to_owned()),
+                                        ("LIBUDEV".to_owned(), "systemd-libs".to_owned()),
+                                        ("OPENSSL".to_owned(), "openssl-libs".to_owned()),
+                                        ("PERL_AUTODIE".to_owned(), "perl-autodie".to_owned()),
+                                        ("PERL_FILE_PATH".to_owned(), "perl-File-Path".to_owned()),
+                                        ("PERL_LWP_PROTO_HTTPS".to_owned(), "perl-LWP-Protocol-https".to_owned()),
+                                        ("PERL_SYS_SYSLOG".to_owned(), "perl-Sys-Syslog".to_owned()),
+                                        ("PROCPS".to_owned(), "procps-ng".to_owned()),
+                                        ("PYTHON_SIMPLEJSON".to_owned(), "bash".to_owned()),
+                                        ("UDEV".to_owned(), "systemd".to_owned()),
+                                    ]
+                                ),
+                                systemd_lib: "usr/lib/systemd/system".to_owned(),
+                                file_ext: "rpm".to_owned(),
+                                initramfs_flavor: "mkinitrd".to_owned(),
+                                builder: Builder {
+                                    alias: "fedora38".to_owned(),
+                                    base_image: "fedora:38".to_owned(),
+                                    branch: "".to_owned(),
+                                    kernel_package: "kernel-core".to_owned(),
+                                    utf8_locale: "C.UTF-8".to_owned(),
+                                },
+                            },
+                    ),
                     (
                             VariantKind::ORACLE7,
                             Variant {
@@ -1702,6 +2119,8 @@ fi
                                                     (
                                                         "install".to_owned(),
                                                         vec![
+                                                            "sh".to_owned(),
+                                                            "-c".to_owned(),
                                                             "
 unset to_install to_reinstall
 for f in $packages; do
@@ -1727,12 +2146,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -1888,12 +2311,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -2049,12 +2476,16 @@ fi
                                     ),
                                 ]
                             ),
+                            templated_commands: vec![
+                                "pkgfile.install".to_owned(),
+                            ],
                             min_sys_python: "3.9".to_owned(),
                             repo:
                             Repo::Yum(YumRepo {
                                 yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                             }),
+                            post_add: Vec::new(),
                             package: HashMap::from(
                                 [
                                     ("KMOD".to_owned(), "kmod".to_owned()),
@@ -2210,12 +2641,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -2371,12 +2806,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -2532,12 +2971,16 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.9".to_owned(),
                                 repo:
                                     Repo::Yum(YumRepo {
                                         yumdef: "redhat/repo/storpool-centos.repo".to_owned(),
-                                        keyring: "redhat/repo/RPM-GPG-KEY-StorPool".to_owned(),
+                                        keyrings: Keyrings::new(vec!["redhat/repo/RPM-GPG-KEY-StorPool".to_owned()]),
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("KMOD".to_owned(), "kmod".to_owned()),
@@ -2683,17 +3126,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.6".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "bionic".to_owned(),
                                         vendor: "ubuntu".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python".to_owned()),
@@ -2835,17 +3284,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.8".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "focal".to_owned(),
                                         vendor: "ubuntu".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python3".to_owned()),
@@ -2987,17 +3442,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.10".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "jammy".to_owned(),
                                         vendor: "ubuntu".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python3".to_owned()),
@@ -3139,17 +3600,23 @@ fi
                                         ),
                                     ]
                                 ),
+                                templated_commands: vec![
+                                    "pkgfile.install".to_owned(),
+                                ],
                                 min_sys_python: "3.12".to_owned(),
                                 repo:
                                     Repo::Deb(DebRepo {
                                         codename: "noble".to_owned(),
                                         vendor: "ubuntu".to_owned(),
                                         sources: "debian/repo/storpool.sources".to_owned(),
-                                        keyring: "debian/repo/storpool-keyring.gpg".to_owned(),
+                                        keyrings: Keyrings::new(vec!["debian/repo/storpool-keyring.gpg".to_owned()]),
                                         req_packages: vec![
                                             "ca-certificates".to_owned(),
                                         ],
+                                        arch_suffixes: HashMap::new(),
+                                        inline_key: false,
                                     }),
+                                    post_add: Vec::new(),
                                     package: HashMap::from(
                                     [
                                         ("BINDINGS_PYTHON".to_owned(), "python3".to_owned()),
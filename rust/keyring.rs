@@ -0,0 +1,282 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Install the keys used to verify the StorPool package repositories.
+//!
+//! This is the key handling part of `storpool_variant repo add --only-keyring`,
+//! usable without the rest of the repository setup.
+
+use std::error::Error as StdError;
+use std::path::Path;
+
+use log::{debug, warn};
+use thiserror::Error;
+
+use crate::{Keyrings, Repo, Variant};
+
+/// The directory that the Apt keyring files are installed into by default.
+pub const APT_KEYRING_DIR: &str = "/usr/share/keyrings";
+
+/// The directory that the Yum keyring files are installed into by default.
+pub const YUM_KEYRING_DIR: &str = "/etc/pki/rpm-gpg";
+
+/// The first line of an ASCII-armored OpenPGP public key block.
+pub const ARMORED_KEY_HEADER: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----";
+
+/// The tool used to import the keys into the RPM database, if present.
+const RPMKEYS_PATH: &str = "/usr/bin/rpmkeys";
+
+/// The tool used to import the keys if `rpmkeys` is not present.
+const RPM_PATH: &str = "/usr/bin/rpm";
+
+/// The tool that `rpm --import` needs to parse the keys.
+const GPG_PATH: &str = "/usr/bin/gpg";
+
+/// An error reported by the caller-supplied installer.
+pub type InstallerError = Box<dyn StdError + Send + Sync>;
+
+/// An error that occurred while installing the keyring files.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum KeyringError {
+    /// A keyring path in the variant definition has no filename component.
+    #[error("Could not obtain a filename from the '{0}' keyring path")]
+    BadPath(String),
+
+    /// A keyring file could not be installed.
+    #[error("Could not install {0} into {1}")]
+    Install(String, String, #[source] InstallerError),
+
+    /// The keys could not be imported into the package manager's database.
+    #[error("Could not import the keys from {0}")]
+    Import(String, #[source] InstallerError),
+}
+
+/// The operations performed on the system while installing the keyring files.
+pub trait KeyringInstaller {
+    /// Copy the `fname` file from the `srcdir` directory into the `dstdir` one.
+    ///
+    /// # Errors
+    /// Any error that prevented the file from being installed.
+    fn install_file(&self, srcdir: &str, fname: &str, dstdir: &str) -> Result<(), InstallerError>;
+
+    /// Run the command that imports the installed keys into the package manager's database.
+    ///
+    /// # Errors
+    /// Any error that prevented the command from being run or from succeeding.
+    fn import_keys(&self, cmdvec: &[String]) -> Result<(), InstallerError>;
+}
+
+/// Get the keyring definitions of the variant's repository.
+const fn repo_keyrings(var: &Variant) -> &Keyrings {
+    match var.repo {
+        Repo::Deb(ref deb) => &deb.keyrings,
+        Repo::Yum(ref yum) => &yum.keyrings,
+    }
+}
+
+/// Get the filename component of each keyring path.
+fn paths_to_filenames<'path, I>(paths: I) -> Result<Vec<&'path str>, KeyringError>
+where
+    I: IntoIterator<Item = &'path str>,
+{
+    paths
+        .into_iter()
+        .map(|path| match path.rsplit('/').next() {
+            Some(fname) if !fname.is_empty() => Ok(fname),
+            _ => Err(KeyringError::BadPath(path.to_owned())),
+        })
+        .collect()
+}
+
+/// Get the filenames of the keyring files that the variant's repository uses
+/// for any of the repository types.
+///
+/// # Errors
+/// [`KeyringError::BadPath`] if a keyring path ends with a slash.
+#[inline]
+pub fn keyring_filenames(var: &Variant) -> Result<Vec<&str>, KeyringError> {
+    paths_to_filenames(repo_keyrings(var).all())
+}
+
+/// Get the filenames of the keyring files that the variant's repository uses
+/// for the specified repository type, e.g. "staging".
+///
+/// # Errors
+/// [`KeyringError::BadPath`] if a keyring path ends with a slash.
+#[inline]
+pub fn keyring_filenames_for<'var>(
+    var: &'var Variant,
+    repotype: &str,
+) -> Result<Vec<&'var str>, KeyringError> {
+    paths_to_filenames(
+        repo_keyrings(var)
+            .for_repotype(repotype)
+            .iter()
+            .map(String::as_str),
+    )
+}
+
+/// Get the directory that the variant's keyring files are installed into by default.
+#[inline]
+#[must_use]
+pub const fn default_keyring_dir(var: &Variant) -> &'static str {
+    match var.repo {
+        Repo::Deb(_) => APT_KEYRING_DIR,
+        Repo::Yum(_) => YUM_KEYRING_DIR,
+    }
+}
+
+/// Format the keys as a multiline deb822 field value.
+///
+/// Each line is indented by a single space and empty lines are replaced by
+/// a single dot, as described in the sources.list(5) manual page.
+#[inline]
+#[must_use]
+pub fn signed_by_inline(keys: &[String]) -> String {
+    keys.iter()
+        .flat_map(|key| key.lines())
+        .map(|line| {
+            if line.trim().is_empty() {
+                "\n .".to_owned()
+            } else {
+                format!("\n {line}")
+            }
+        })
+        .collect()
+}
+
+/// Build the command that imports the installed keys, if the variant needs one.
+///
+/// Only Yum repositories need the keys imported, using the `rpmkeys` tool or,
+/// if it is not installed, `rpm --import` if `gpg` is available; otherwise,
+/// `yum` will try to import them on first use.
+///
+/// # Errors
+/// [`KeyringError::BadPath`] if a keyring path ends with a slash.
+#[inline]
+pub fn key_import_command(
+    var: &Variant,
+    keyring_dir: &str,
+) -> Result<Option<Vec<String>>, KeyringError> {
+    Ok(key_import_command_with(
+        var,
+        keyring_dir,
+        &keyring_filenames(var)?,
+    ))
+}
+
+/// Build the command that imports the specified installed keyring files, if needed.
+///
+/// Same as [`key_import_command()`], but only for some of the keyring files,
+/// e.g. the ones used by the selected repository types.
+#[inline]
+#[must_use]
+pub fn key_import_command_with(
+    var: &Variant,
+    keyring_dir: &str,
+    keyring_fnames: &[&str],
+) -> Option<Vec<String>> {
+    key_import_command_using(var, keyring_dir, keyring_fnames, |path| {
+        Path::new(path).exists()
+    })
+}
+
+/// Choose the tool that imports the keys into the RPM database.
+///
+/// The `tool_exists` function is passed the full path to each candidate tool.
+pub(crate) fn key_import_tool<F>(tool_exists: F) -> Option<&'static str>
+where
+    F: Fn(&str) -> bool,
+{
+    if tool_exists(RPMKEYS_PATH) {
+        Some("rpmkeys")
+    } else if tool_exists(RPM_PATH) && tool_exists(GPG_PATH) {
+        debug!("No {RPMKEYS_PATH}, falling back to `rpm --import`");
+        Some("rpm")
+    } else {
+        None
+    }
+}
+
+/// Build the command that imports the specified installed keyring files, if needed.
+///
+/// Same as [`key_import_command_with()`], but use the `tool_exists` function
+/// instead of examining the filesystem to choose the tool to run.
+pub(crate) fn key_import_command_using<F>(
+    var: &Variant,
+    keyring_dir: &str,
+    keyring_fnames: &[&str],
+    tool_exists: F,
+) -> Option<Vec<String>>
+where
+    F: Fn(&str) -> bool,
+{
+    if !matches!(var.repo, Repo::Yum(_)) {
+        return None;
+    }
+    let tool = match key_import_tool(tool_exists) {
+        Some(tool) => tool,
+        None => {
+            warn!(
+                "Neither {RPMKEYS_PATH} nor {RPM_PATH} and {GPG_PATH} found, \
+                 not importing the StorPool OpenPGP keys"
+            );
+            return None;
+        }
+    };
+    Some(
+        [tool.to_owned(), "--import".to_owned()]
+            .into_iter()
+            .chain(
+                keyring_fnames
+                    .iter()
+                    .map(|fname| format!("{keyring_dir}/{fname}")),
+            )
+            .collect(),
+    )
+}
+
+/// Install the variant's keyring files and, for Yum, import the keys.
+///
+/// The files are copied from the variant-specific subdirectory of `repodir`
+/// (e.g. `repodir/DEBIAN12/`) into `dest_override` or into the default
+/// directory for the variant's package manager.
+/// If the variant's repository embeds the keys into the sources list files,
+/// there is nothing to install.
+///
+/// # Errors
+/// [`KeyringError::Install`] or [`KeyringError::Import`] if the installer fails.
+#[inline]
+pub fn install_keyring(
+    var: &Variant,
+    repodir: &str,
+    dest_override: Option<&str>,
+    runner: &dyn KeyringInstaller,
+) -> Result<(), KeyringError> {
+    if let Repo::Deb(ref deb) = var.repo {
+        if deb.inline_key {
+            debug!("The keys are embedded in the sources list files");
+            return Ok(());
+        }
+    }
+
+    let vdir = format!("{repodir}/{kind}", kind = var.kind.as_ref());
+    let keyring_dir = dest_override.unwrap_or_else(|| default_keyring_dir(var));
+    for fname in keyring_filenames(var)? {
+        runner
+            .install_file(&vdir, fname, keyring_dir)
+            .map_err(|err| KeyringError::Install(fname.to_owned(), keyring_dir.to_owned(), err))?;
+    }
+
+    match key_import_command(var, keyring_dir)? {
+        Some(cmdvec) => runner
+            .import_keys(&cmdvec)
+            .map_err(|err| KeyringError::Import(keyring_dir.to_owned(), err)),
+        None => {
+            debug!("No need to import the keys from {keyring_dir}");
+            Ok(())
+        }
+    }
+}
@@ -9,11 +9,23 @@
 #![allow(clippy::pub_use)]
 
 use std::clone::Clone;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::fs;
-use std::io::{Error as IoError, ErrorKind};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
+use log::{debug, info, warn};
+use once_cell::sync::OnceCell;
 use regex::RegexBuilder;
+#[cfg(feature = "json")]
+use schemars::JsonSchema;
+use serde::{Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -21,14 +33,22 @@ use yai::YAIError;
 
 mod data;
 
+pub mod keyring;
 pub mod yai;
 
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
 #[cfg(test)]
-pub mod tests;
+mod tests;
 
 pub use data::VariantKind;
 
 /// An error that occurred while determining the Linux variant.
+///
+/// The underlying I/O or os-release parse error, if any, is available as
+/// the error's source. The type is `Send` and `Sync`, so it may be boxed or
+/// wrapped into an `anyhow::Error`.
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum VariantError {
@@ -36,10 +56,22 @@ pub enum VariantError {
     #[error("Unknown variant '{0}'")]
     BadVariant(String),
 
+    /// An invalid repository type name was specified.
+    #[error("Unknown repository type '{0}'")]
+    BadRepoType(String),
+
     /// A file to be examined could not be read.
     #[error("Checking for {0}: could not read {1}")]
     FileRead(String, String, #[source] IoError),
 
+    /// A repository file could not be read.
+    #[error("Could not read the {0} repository file")]
+    RepoFile(String, #[source] IoError),
+
+    /// A repository file is not in the expected format.
+    #[error("Invalid repository file {0}: {1}")]
+    BadRepoFile(String, String),
+
     /// Unexpected error parsing the /etc/os-release file.
     #[error("Could not parse the /etc/os-release file")]
     OsRelease(#[source] YAIError),
@@ -48,13 +80,24 @@ pub enum VariantError {
     #[error("Could not detect the current host's build variant")]
     UnknownVariant,
 
+    /// The variant does not define the requested command.
+    #[error("No '{1}' command defined for the {0} variant")]
+    UnknownCommand(String, String),
+
+    /// Reading the os-release file did not complete in time.
+    #[error("Timed out after {1:?} reading {0}")]
+    DetectionTimeout(String, Duration),
+
     /// Something went really, really wrong.
     #[error("Internal sp-variant error: {0}")]
     Internal(String),
 }
 
 /// The version of the variant definition format data.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+///
+/// Versions are ordered by the major number first, then by the minor one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct VariantFormatVersion {
     /// The version major number.
@@ -63,14 +106,35 @@ pub struct VariantFormatVersion {
     pub minor: u32,
 }
 
+impl VariantFormatVersion {
+    /// Build a format version object from its components.
+    #[inline]
+    #[must_use]
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Can code that understands this format version handle data in the `required` one?
+    ///
+    /// The major versions must be the same, and this minor version must not be
+    /// older than the required one.
+    #[inline]
+    #[must_use]
+    pub const fn is_compatible_with(&self, required: Self) -> bool {
+        self.major == required.major && self.minor >= required.minor
+    }
+}
+
 /// The internal format of the variant definition format data.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct VariantFormat {
     /// The version of the metadata format.
     pub version: VariantFormatVersion,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 struct VariantFormatTop {
     format: VariantFormat,
@@ -78,6 +142,7 @@ struct VariantFormatTop {
 
 /// Check whether this host is running this particular OS variant.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct Detect {
     /// The name of the file to read.
@@ -92,14 +157,165 @@ pub struct Detect {
 
 /// The aspects of the StorPool operation supported for this build variant.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct Supported {
     /// Is there a StorPool third-party packages repository?
     pub repo: bool,
 }
 
+/// One or more keyring files, as specified in the variant data.
+///
+/// Older variant data lists a single keyring file as a string.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
+#[serde(untagged)]
+enum KeyringList {
+    /// A single keyring file.
+    Single(String),
+    /// Several keyring files, e.g. both the old and the new one during a key rotation.
+    Multiple(Vec<String>),
+}
+
+impl From<KeyringList> for Vec<String> {
+    #[inline]
+    fn from(list: KeyringList) -> Self {
+        match list {
+            KeyringList::Single(single) => vec![single],
+            KeyringList::Multiple(multiple) => multiple,
+        }
+    }
+}
+
+impl From<&[String]> for KeyringList {
+    /// Use a string for a single keyring file for compatibility with older consumers.
+    #[inline]
+    fn from(keyrings: &[String]) -> Self {
+        match *keyrings {
+            [ref single] => Self::Single(single.clone()),
+            _ => Self::Multiple(keyrings.to_vec()),
+        }
+    }
+}
+
+/// The keyring files for a repository, as specified in the variant data.
+///
+/// Older variant data lists the same keyring files for all the repository types;
+/// newer data may specify a map keyed by the repository type name (e.g. "staging"),
+/// with a "default" entry for the types not listed explicitly.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
+#[serde(untagged)]
+enum KeyringSpec {
+    /// The same keyring files for all the repository types.
+    All(KeyringList),
+    /// Different keyring files for some repository types.
+    ByRepoType(BTreeMap<String, KeyringList>),
+}
+
+/// The keyring files for a repository, possibly different for some repository types.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Keyrings {
+    /// The keyring files for the repository types not listed in `by_repotype`.
+    pub default: Vec<String>,
+    /// The keyring files for specific repository types, keyed by the type name.
+    pub by_repotype: HashMap<String, Vec<String>>,
+}
+
+impl Keyrings {
+    /// The name of the map entry for the repository types not listed explicitly.
+    pub const DEFAULT: &'static str = "default";
+
+    /// Use the same keyring files for all the repository types.
+    #[inline]
+    #[must_use]
+    pub fn new(default: Vec<String>) -> Self {
+        Self {
+            default,
+            by_repotype: HashMap::new(),
+        }
+    }
+
+    /// Use different keyring files for the specified repository type.
+    #[inline]
+    #[must_use]
+    pub fn with_repotype(mut self, repotype: &str, keyrings: Vec<String>) -> Self {
+        self.by_repotype.insert(repotype.to_owned(), keyrings);
+        self
+    }
+
+    /// Get the keyring files for the specified repository type, e.g. "staging".
+    #[inline]
+    #[must_use]
+    pub fn for_repotype(&self, repotype: &str) -> &[String] {
+        self.by_repotype.get(repotype).unwrap_or(&self.default)
+    }
+
+    /// Get the keyring files for all the repository types, each one listed once.
+    ///
+    /// The default ones come first, then the type-specific ones sorted by type name.
+    #[inline]
+    #[must_use]
+    pub fn all(&self) -> Vec<&str> {
+        let mut types: Vec<&String> = self.by_repotype.keys().collect();
+        types.sort();
+        let mut res: Vec<&str> = Vec::new();
+        for path in self
+            .default
+            .iter()
+            .chain(types.into_iter().flat_map(|name| &self.by_repotype[name]))
+        {
+            if !res.contains(&path.as_str()) {
+                res.push(path);
+            }
+        }
+        res
+    }
+}
+
+impl serde::Serialize for Keyrings {
+    /// Use the older format unless there are type-specific keyring files.
+    #[inline]
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        if self.by_repotype.is_empty() {
+            KeyringList::from(self.default.as_slice()).serialize(ser)
+        } else {
+            self.by_repotype
+                .iter()
+                .map(|(name, keyrings)| (name.as_str(), KeyringList::from(keyrings.as_slice())))
+                .chain([(Self::DEFAULT, KeyringList::from(self.default.as_slice()))])
+                .collect::<BTreeMap<_, _>>()
+                .serialize(ser)
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Keyrings {
+    /// Accept a single keyring file, a list of them, or a map keyed by repository type.
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        match KeyringSpec::deserialize(de)? {
+            KeyringSpec::All(list) => Ok(Self::new(list.into())),
+            KeyringSpec::ByRepoType(mut map) => {
+                let default = map.remove(Self::DEFAULT).ok_or_else(|| {
+                    serde::de::Error::custom("no \"default\" entry in the keyring map")
+                })?;
+                Ok(Self {
+                    default: default.into(),
+                    by_repotype: map
+                        .into_iter()
+                        .map(|(name, list)| (name, list.into()))
+                        .collect(),
+                })
+            }
+        }
+    }
+}
+
 /// Debian package repository data.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct DebRepo {
     /// The distribution codename (e.g. "buster").
@@ -108,24 +324,52 @@ pub struct DebRepo {
     pub vendor: String,
     /// The APT sources list file to copy to /etc/apt/sources.list.d/.
     pub sources: String,
-    /// The GnuPG keyring file to copy to /usr/share/keyrings/.
-    pub keyring: String,
+    /// The GnuPG keyring files to copy to /usr/share/keyrings/.
+    #[serde(rename = "keyring")]
+    #[cfg_attr(feature = "json", schemars(with = "KeyringSpec"))]
+    pub keyrings: Keyrings,
     /// OS packages that need to be installed before `apt-get update` is run.
     pub req_packages: Vec<String>,
+    /// The suffixes to add to the sources list filename on some host architectures,
+    /// keyed by the value returned by [`get_host_arch()`] (e.g. "arm" -> "-armhf").
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub arch_suffixes: HashMap<String, String>,
+    /// Embed the ASCII-armored keys into the sources list file's "Signed-By" field
+    /// instead of installing the keyring files.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inline_key: bool,
+}
+
+impl DebRepo {
+    /// Build the name of the sources list file to install for the specified
+    /// repository type and host architecture.
+    ///
+    /// The architecture-specific suffix, if any, is added after the repository
+    /// type extension: `storpool.sources` becomes `storpool-staging-armhf.sources`.
+    #[inline]
+    #[must_use]
+    pub fn sources_filename(&self, repotype_extension: &str, arch: &str) -> String {
+        let arch_suffix = self.arch_suffixes.get(arch).map_or("", String::as_str);
+        repo_filename(&self.sources, &format!("{repotype_extension}{arch_suffix}"))
+    }
 }
 
 /// Yum/DNF package repository data.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct YumRepo {
     /// The *.repo file to copy to /etc/yum.repos.d/.
     pub yumdef: String,
-    /// The keyring file to copy to /etc/pki/rpm-gpg/.
-    pub keyring: String,
+    /// The keyring files to copy to /etc/pki/rpm-gpg/.
+    #[serde(rename = "keyring")]
+    #[cfg_attr(feature = "json", schemars(with = "KeyringSpec"))]
+    pub keyrings: Keyrings,
 }
 
 /// OS package repository data.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[serde(untagged)]
 #[non_exhaustive]
 pub enum Repo {
@@ -135,8 +379,117 @@ pub enum Repo {
     Yum(YumRepo),
 }
 
+/// The type of a StorPool package repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RepoType {
+    /// The packages built for the StorPool customers.
+    Contrib,
+    /// The packages that are still being tested.
+    Staging,
+    /// The packages used by the StorPool infrastructure.
+    Infra,
+}
+
+impl RepoType {
+    /// The name of the default repository type.
+    pub const CONTRIB: &'static str = "contrib";
+    /// The name of the staging repository type.
+    pub const STAGING: &'static str = "staging";
+    /// The name of the infrastructure repository type.
+    pub const INFRA: &'static str = "infra";
+
+    /// The filename extension of the default repository type.
+    pub const EXT_CONTRIB: &'static str = "";
+    /// The filename extension of the staging repository type.
+    pub const EXT_STAGING: &'static str = "-staging";
+    /// The filename extension of the infrastructure repository type.
+    pub const EXT_INFRA: &'static str = "-infra";
+
+    /// All the repository types, in the order that they are added in.
+    pub const VALUES: [Self; 3] = [Self::Contrib, Self::Staging, Self::Infra];
+
+    /// The name of the repository type, e.g. "staging".
+    #[inline]
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match *self {
+            Self::Contrib => Self::CONTRIB,
+            Self::Staging => Self::STAGING,
+            Self::Infra => Self::INFRA,
+        }
+    }
+
+    /// The extension added to the repository files' names, e.g. "-staging".
+    #[inline]
+    #[must_use]
+    pub const fn extension(&self) -> &'static str {
+        match *self {
+            Self::Contrib => Self::EXT_CONTRIB,
+            Self::Staging => Self::EXT_STAGING,
+            Self::Infra => Self::EXT_INFRA,
+        }
+    }
+}
+
+impl AsRef<str> for RepoType {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.name()
+    }
+}
+
+impl FromStr for RepoType {
+    type Err = VariantError;
+
+    #[inline]
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::VALUES
+            .into_iter()
+            .find(|repotype| repotype.name() == value)
+            .ok_or_else(|| VariantError::BadRepoType(value.to_owned()))
+    }
+}
+
+/// The package manager used to install the OS packages on a build variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PackageManager {
+    /// Debian, Ubuntu: `apt-get`.
+    Apt,
+    /// Red Hat-like distributions: `yum`.
+    Yum,
+}
+
+impl PackageManager {
+    /// The name of the package manager's command-line tool, e.g. "apt-get".
+    #[inline]
+    #[must_use]
+    pub const fn tool(&self) -> &'static str {
+        match *self {
+            Self::Apt => "apt-get",
+            Self::Yum => "yum",
+        }
+    }
+}
+
+impl AsRef<str> for PackageManager {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.tool()
+    }
+}
+
+/// Get all the supported repository types, in the order that they are added in.
+#[inline]
+#[must_use]
+pub const fn repo_types() -> &'static [RepoType] {
+    &RepoType::VALUES
+}
+
 /// StorPool builder data.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct Builder {
     /// The builder name.
@@ -153,6 +506,7 @@ pub struct Builder {
 
 /// A single StorPool build variant with all its options.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 #[non_exhaustive]
 pub struct Variant {
     /// Which variant is that?
@@ -170,10 +524,16 @@ pub struct Variant {
     pub supported: Supported,
     /// The OS commands to execute for particular purposes.
     pub commands: HashMap<String, HashMap<String, Vec<String>>>,
+    /// The commands ("category.name") that are templates containing shell variable references.
+    #[serde(default)]
+    pub templated_commands: Vec<String>,
     /// The minimum Python version that we can depend on.
     pub min_sys_python: String,
     /// The StorPool repository files to install.
     pub repo: Repo,
+    /// The commands to run after the StorPool repository files have been installed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_add: Vec<Vec<String>>,
     /// The names of the packages to be used for this variant.
     pub package: HashMap<String, String>,
     /// The name of the directory to install systemd unit files to.
@@ -186,8 +546,199 @@ pub struct Variant {
     pub builder: Builder,
 }
 
+impl Variant {
+    /// Get the distribution-specific command with the specified category and name.
+    ///
+    /// # Errors
+    /// [`VariantError::UnknownCommand`] if there is no such command.
+    #[inline]
+    pub fn command(&self, category: &str, name: &str) -> Result<&[String], VariantError> {
+        self.commands
+            .get(category)
+            .and_then(|cmap| cmap.get(name))
+            .map(Vec::as_slice)
+            .ok_or_else(|| {
+                VariantError::UnknownCommand(
+                    self.kind.as_ref().to_owned(),
+                    format!("{category}.{name}"),
+                )
+            })
+    }
+
+    /// Get the package manager that the StorPool repository is added for.
+    ///
+    /// This is determined by the type of the repository: Apt for Debian-like
+    /// variants, Yum for Red Hat-like ones, even if they also provide `dnf`.
+    #[inline]
+    #[must_use]
+    pub const fn package_manager(&self) -> PackageManager {
+        match self.repo {
+            Repo::Deb(_) => PackageManager::Apt,
+            Repo::Yum(_) => PackageManager::Yum,
+        }
+    }
+
+    /// Get the OS packages that must be installed before adding the StorPool repository.
+    ///
+    /// For Apt repositories, these are listed in the variant data; for Yum ones,
+    /// the CA certificates are always installed.
+    #[inline]
+    #[must_use]
+    pub fn required_packages(&self) -> Vec<&str> {
+        match self.repo {
+            Repo::Deb(ref deb) => deb.req_packages.iter().map(String::as_str).collect(),
+            Repo::Yum(_) => YUM_REQ_PACKAGES.to_vec(),
+        }
+    }
+
+    /// Build the full command to install the specified OS packages.
+    ///
+    /// # Errors
+    /// [`VariantError::UnknownCommand`] if there is no "package.install" command.
+    #[inline]
+    pub fn package_install_command(
+        &self,
+        packages: &[String],
+    ) -> Result<Vec<String>, VariantError> {
+        self.command_with_args("package", "install", packages)
+    }
+
+    /// Build the full command to remove the specified OS packages.
+    ///
+    /// # Errors
+    /// [`VariantError::UnknownCommand`] if there is no "package.remove" command.
+    #[inline]
+    pub fn package_remove_command(&self, packages: &[String]) -> Result<Vec<String>, VariantError> {
+        self.command_with_args("package", "remove", packages)
+    }
+
+    /// Render the StorPool repository files that `storpool_variant repo add` installs.
+    ///
+    /// The files are read from the variant-specific subdirectory of `repodir`
+    /// (e.g. `repodir/DEBIAN12/`) and returned as pairs of the path that each
+    /// one would be installed as and its contents; nothing is written.
+    /// Only the keyring files for the repository type are included.
+    /// If the Apt repository embeds the keys, they are placed into the sources
+    /// list file's "Signed-By" field and no keyring files are returned.
+    ///
+    /// # Errors
+    /// - [`VariantError::RepoFile`] if a file could not be read
+    /// - [`VariantError::BadRepoFile`] if a sources list file is not valid UTF-8 or
+    ///   a key to embed is not ASCII-armored
+    /// - [`VariantError::Internal`] if a keyring path has no filename component
+    #[allow(clippy::missing_inline_in_public_items)]
+    pub fn render_repo_files(
+        &self,
+        repotype_extension: &str,
+        repodir: &str,
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>, VariantError> {
+        let vdir = format!("{repodir}/{kind}", kind = self.kind.as_ref());
+        let read = |path: &str| fs::read(path);
+        let mut res = vec![self.render_repo_file_with(repotype_extension, &vdir, read)?];
+        if matches!(self.repo, Repo::Deb(ref deb) if deb.inline_key) {
+            return Ok(res);
+        }
+
+        let keyring_dir = keyring::default_keyring_dir(self);
+        for fname in keyring::keyring_filenames_for(self, repotype_name(repotype_extension))
+            .map_err(|err| VariantError::Internal(err.to_string()))?
+        {
+            let path = format!("{vdir}/{fname}");
+            let contents = fs::read(&path).map_err(|err| VariantError::RepoFile(path, err))?;
+            res.push((Path::new(keyring_dir).join(fname), contents));
+        }
+        Ok(res)
+    }
+
+    /// Render the StorPool repository definition file for a repository type:
+    /// the Apt sources list file, with any embedded keys, or the Yum one.
+    ///
+    /// The `read` function is passed the full path to each file within `vdir`,
+    /// the variant-specific directory, e.g. so that the files may be read from
+    /// an archive instead. Returns the path that the file would be installed as
+    /// and its contents.
+    ///
+    /// # Errors
+    /// Same as for [`Variant::render_repo_files()`].
+    #[allow(clippy::missing_inline_in_public_items)]
+    pub fn render_repo_file_with<F>(
+        &self,
+        repotype_extension: &str,
+        vdir: &str,
+        read: F,
+    ) -> Result<(PathBuf, Vec<u8>), VariantError>
+    where
+        F: Fn(&str) -> IoResult<Vec<u8>>,
+    {
+        let read_file = |fname: &str| {
+            let path = format!("{vdir}/{fname}");
+            read(&path).map_err(|err| VariantError::RepoFile(path, err))
+        };
+        match self.repo {
+            Repo::Deb(ref deb) => {
+                let fname = deb.sources_filename(repotype_extension, get_host_arch());
+                let contents = read_file(&fname)?;
+                if !deb.inline_key {
+                    return Ok((Path::new(APT_SOURCES_DIR).join(fname), contents));
+                }
+                let keys = keyring::keyring_filenames_for(self, repotype_name(repotype_extension))
+                    .map_err(|err| VariantError::Internal(err.to_string()))?
+                    .into_iter()
+                    .map(|key_fname| {
+                        String::from_utf8(read_file(key_fname)?)
+                            .ok()
+                            .filter(|key| key.trim_start().starts_with(keyring::ARMORED_KEY_HEADER))
+                            .ok_or_else(|| {
+                                VariantError::BadRepoFile(
+                                    format!("{vdir}/{key_fname}"),
+                                    "not an ASCII-armored OpenPGP key".to_owned(),
+                                )
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let text = String::from_utf8(contents).map_err(|_| {
+                    VariantError::BadRepoFile(
+                        format!("{vdir}/{fname}"),
+                        "not valid UTF-8".to_owned(),
+                    )
+                })?;
+                let contents =
+                    replace_field(&text, "Signed-By:", &keyring::signed_by_inline(&keys));
+                Ok((
+                    Path::new(APT_SOURCES_DIR).join(fname),
+                    contents.into_bytes(),
+                ))
+            }
+            Repo::Yum(ref yum) => {
+                let fname = repo_filename(&yum.yumdef, repotype_extension);
+                let contents = read_file(&fname)?;
+                Ok((Path::new(YUM_REPOS_DIR).join(fname), contents))
+            }
+        }
+    }
+
+    /// Append the specified arguments to a distribution-specific command.
+    fn command_with_args(
+        &self,
+        category: &str,
+        name: &str,
+        args: &[String],
+    ) -> Result<Vec<String>, VariantError> {
+        Ok(self
+            .command(category, name)?
+            .iter()
+            .chain(args.iter())
+            .cloned()
+            .collect())
+    }
+}
+
+/// The packages installed before adding a Yum repository.
+const YUM_REQ_PACKAGES: [&str; 1] = ["ca-certificates"];
+
 /// The internal variant format data: all build variants, some more info.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "json", derive(JsonSchema))]
 pub struct VariantDefTop {
     format: VariantFormat,
     order: Vec<VariantKind>,
@@ -195,6 +746,210 @@ pub struct VariantDefTop {
     version: String,
 }
 
+/// Options that control the detection of the current host's variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DetectConfig {
+    /// Fall back to the distributions listed in the os-release "ID_LIKE" field.
+    pub id_like: bool,
+    /// Resolve the os-release file's symlinks and, if it is missing or
+    /// a dangling symlink, try the next one in [`OS_RELEASE_CANDIDATES`].
+    pub dereference_symlinks: bool,
+    /// If the os-release "VERSION_ID" field does not match any variant,
+    /// try matching only its major version number.
+    pub lenient: bool,
+    /// Read the vendor-provided `/usr/lib/os-release` file before
+    /// the administrator-provided `/etc/os-release` one.
+    pub prefer_usr_lib: bool,
+    /// Give up if reading the os-release file takes longer than this,
+    /// e.g. if `/etc` is on a hung network filesystem.
+    pub timeout: Option<Duration>,
+    /// If the os-release file has an "ID" field, but no usable "VERSION_ID" one,
+    /// run `lsb_release -sr` to obtain the version; slow, so disabled by default.
+    pub lsb_release: bool,
+}
+
+impl DetectConfig {
+    /// Enable or disable the "ID_LIKE" fallback.
+    #[inline]
+    #[must_use]
+    pub const fn with_id_like(self, id_like: bool) -> Self {
+        Self { id_like, ..self }
+    }
+
+    /// Enable or disable resolving the os-release file's symlinks.
+    #[inline]
+    #[must_use]
+    pub const fn with_dereference_symlinks(self, dereference_symlinks: bool) -> Self {
+        Self {
+            dereference_symlinks,
+            ..self
+        }
+    }
+
+    /// Enable or disable the major version fallback.
+    #[inline]
+    #[must_use]
+    pub const fn with_lenient(self, lenient: bool) -> Self {
+        Self { lenient, ..self }
+    }
+
+    /// Enable or disable reading `/usr/lib/os-release` first.
+    #[inline]
+    #[must_use]
+    pub const fn with_prefer_usr_lib(self, prefer_usr_lib: bool) -> Self {
+        Self {
+            prefer_usr_lib,
+            ..self
+        }
+    }
+
+    /// Set or remove the time limit for reading the os-release file.
+    #[inline]
+    #[must_use]
+    pub const fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Enable or disable the `lsb_release` version fallback.
+    #[inline]
+    #[must_use]
+    pub const fn with_lsb_release(self, lsb_release: bool) -> Self {
+        Self {
+            lsb_release,
+            ..self
+        }
+    }
+}
+
+/// The os-release files to look for, in order, as described in the os-release(5) manual page.
+///
+/// The administrator-provided `/etc/os-release` file is read first by default;
+/// see [`DetectConfig::prefer_usr_lib`] for reversing the order.
+pub const OS_RELEASE_CANDIDATES: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+/// How certain the detection of the current host's variant is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Confidence {
+    /// The os-release "ID" and "VERSION_ID" fields matched the variant definition.
+    Exact,
+    /// A line in a distribution-specific release file matched the variant's version pattern.
+    VersionRange,
+    /// The variant was only found using the os-release "ID_LIKE" field.
+    Fallback,
+    /// Only the major number of the os-release "VERSION_ID" field matched.
+    Lenient,
+    /// The vendor and version in the os-release "CPE_NAME" field matched.
+    Cpe,
+}
+
+impl AsRef<str> for Confidence {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        match *self {
+            Self::Exact => "exact",
+            Self::VersionRange => "version-range",
+            Self::Fallback => "fallback",
+            Self::Lenient => "lenient",
+            Self::Cpe => "cpe",
+        }
+    }
+}
+
+impl Display for Confidence {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_ref())
+    }
+}
+
+/// The parts of a Common Platform Enumeration name used to identify a distribution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CpeName {
+    /// The vendor of the product, e.g. "rocky" or "redhat".
+    pub vendor: String,
+    /// The name of the product, e.g. "rocky" or "enterprise_linux".
+    pub product: String,
+    /// The version of the product, e.g. "8".
+    pub version: String,
+}
+
+impl CpeName {
+    /// Parse a CPE name in either the 2.2 URI format (`cpe:/o:rocky:rocky:8:GA`) or
+    /// the 2.3 formatted string one (`cpe:2.3:o:rocky:rocky:8:*:*:*:*:*:*:*`).
+    ///
+    /// Only operating system names with a vendor, a product, and a version are
+    /// recognized.
+    #[inline]
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        let rest = value
+            .strip_prefix("cpe:/")
+            .or_else(|| value.strip_prefix("cpe:2.3:"))?;
+        let mut fields = rest.split(':');
+        if fields.next()? != "o" {
+            return None;
+        }
+        let mut next_field = || {
+            fields
+                .next()
+                .filter(|field| !field.is_empty() && *field != "*" && *field != "-")
+                .map(str::to_lowercase)
+        };
+        Some(Self {
+            vendor: next_field()?,
+            product: next_field()?,
+            version: next_field()?,
+        })
+    }
+}
+
+/// The os-release "ID" values of the distributions identified by a CPE name vendor.
+const CPE_VENDORS: [(&str, &str); 5] = [
+    ("almalinux", "almalinux"),
+    ("centos", "centos"),
+    ("oracle", "ol"),
+    ("redhat", "rhel"),
+    ("rocky", "rocky"),
+];
+
+/// The detected variant and the way it was detected.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct DetectionResult<'defs> {
+    /// The detected build variant.
+    pub variant: &'defs Variant,
+    /// How certain the detection is.
+    pub confidence: Confidence,
+}
+
+impl<'defs> DetectionResult<'defs> {
+    /// Record a successfully detected variant.
+    #[inline]
+    #[must_use]
+    pub const fn new(variant: &'defs Variant, confidence: Confidence) -> Self {
+        Self {
+            variant,
+            confidence,
+        }
+    }
+
+    /// Identify the variant definition and the detection rule that matched,
+    /// e.g. "DEBIAN12:exact" or "ALMA8:version-range", so that field reports
+    /// can be traced back to a specific data entry.
+    #[inline]
+    #[must_use]
+    pub fn matched_rule(&self) -> String {
+        format!(
+            "{kind}:{confidence}",
+            kind = self.variant.kind.as_ref(),
+            confidence = self.confidence
+        )
+    }
+}
+
 /// Get the list of StorPool variants from the internal `data` module.
 #[inline]
 #[must_use]
@@ -211,6 +966,21 @@ pub fn detect() -> Result<Variant, VariantError> {
     detect_from(build_variants()).cloned()
 }
 
+/// Detect the variant that this host is currently running, only doing the work once.
+///
+/// The result of a successful detection is remembered and returned by all
+/// subsequent calls; a failed detection will be retried on the next call.
+///
+/// # Errors
+/// Propagates any errors from [`detect_from()`].
+#[inline]
+pub fn current() -> Result<&'static Variant, VariantError> {
+    static CURRENT: OnceCell<&'static Variant> = OnceCell::new();
+    CURRENT
+        .get_or_try_init(|| detect_from(build_variants()))
+        .copied()
+}
+
 /// Detect the current host's variant from the supplied data.
 ///
 /// # Errors
@@ -218,83 +988,510 @@ pub fn detect() -> Result<Variant, VariantError> {
 /// an underlying error condition:
 /// - any `os-release` parse errors from [`crate::yai::parse()`] other than "file not found"
 /// - I/O errors from reading the distribution-specific version files (e.g. `/etc/redhat-release`)
-#[allow(clippy::missing_inline_in_public_items)]
+#[inline]
 pub fn detect_from(variants: &VariantDefTop) -> Result<&Variant, VariantError> {
-    match yai::parse("/etc/os-release") {
-        Ok(data) => {
-            if let Some(os_id) = data.get("ID") {
-                if let Some(version_id) = data.get("VERSION_ID") {
-                    for kind in &variants.order {
-                        let var = &variants.variants.get(kind).ok_or_else(|| {
-                            VariantError::Internal(format!(
-                                "Internal error: unknown variant {kind} in the order",
-                                kind = kind.as_ref()
-                            ))
-                        })?;
-                        if var.detect.os_id != *os_id {
-                            continue;
-                        }
-                        let re_ver = RegexBuilder::new(&var.detect.os_version_regex)
-                            .ignore_whitespace(true)
-                            .build()
-                            .map_err(|err| {
-                                VariantError::Internal(format!(
-                                    "Internal error: {kind}: could not parse '{regex}': {err}",
-                                    kind = kind.as_ref(),
-                                    regex = var.detect.regex
-                                ))
-                            })?;
-                        if re_ver.is_match(version_id) {
-                            return Ok(var);
-                        }
-                    }
-                }
+    detect_from_with_config(variants, &DetectConfig::default())
+}
+
+/// Detect the current host's variant from the supplied data using the specified options.
+///
+/// # Errors
+/// Same as for [`detect_from()`].
+#[inline]
+pub fn detect_from_with_config<'defs>(
+    variants: &'defs VariantDefTop,
+    config: &DetectConfig,
+) -> Result<&'defs Variant, VariantError> {
+    detect_result_from(variants, config).map(|res| res.variant)
+}
+
+/// Detect the current host's variant, also report how certain the detection is.
+///
+/// # Errors
+/// Same as for [`detect_from()`].
+#[allow(clippy::missing_inline_in_public_items)]
+pub fn detect_result_from<'defs>(
+    variants: &'defs VariantDefTop,
+    config: &DetectConfig,
+) -> Result<DetectionResult<'defs>, VariantError> {
+    if let Some((os_release, data)) = read_os_release(config)? {
+        if let Some(res) = detect_result_from_os_release(variants, &data, config)? {
+            debug!(
+                "Detected {kind} using {path}, confidence: {confidence}",
+                kind = res.variant.kind.as_ref(),
+                path = os_release.display(),
+                confidence = res.confidence
+            );
+            return Ok(res);
+        }
+        // Fall through to the PRETTY_NAME processing.
+    }
+
+    for var in ordered_variants(variants)? {
+        if release_file_matches(var)? {
+            debug!(
+                "Detected {kind} using {fname}",
+                kind = var.kind.as_ref(),
+                fname = var.detect.filename
+            );
+            return Ok(DetectionResult::new(var, Confidence::VersionRange));
+        }
+    }
+    Err(VariantError::UnknownVariant)
+}
+
+/// Find all the variants whose detection rules match the current host.
+///
+/// The results are in order of preference, so the first one is the variant
+/// that [`detect_result_from()`] would return. More than one result usually
+/// means that the variant definitions overlap.
+///
+/// # Errors
+/// Same as for [`detect_from()`]; an empty list is not an error.
+#[allow(clippy::missing_inline_in_public_items)]
+pub fn detect_all_from<'defs>(
+    variants: &'defs VariantDefTop,
+    config: &DetectConfig,
+) -> Result<Vec<DetectionResult<'defs>>, VariantError> {
+    let mut res = match read_os_release(config)? {
+        Some((_, data)) => detect_all_from_os_release(variants, &data, config)?,
+        None => Vec::new(),
+    };
+    for var in ordered_variants(variants)? {
+        if release_file_matches(var)? {
+            res.push(DetectionResult::new(var, Confidence::VersionRange));
+        }
+    }
+    Ok(res)
+}
+
+/// The path to an os-release file and the fields parsed from it.
+type OsReleaseData = (PathBuf, HashMap<String, String>);
+
+/// Read the os-release file, if there is one.
+///
+/// If [`DetectConfig::lsb_release`] is set, fill in a missing "VERSION_ID" field.
+fn read_os_release(config: &DetectConfig) -> Result<Option<OsReleaseData>, VariantError> {
+    Ok(
+        read_os_release_from(&OS_RELEASE_CANDIDATES, config)?.map(|(os_release, mut data)| {
+            if config.lsb_release {
+                fill_version_id_from_lsb_release(&mut data, run_lsb_release);
             }
-            // Fall through to the PRETTY_NAME processing.
+            (os_release, data)
+        }),
+    )
+}
+
+/// Run `lsb_release -sr`, return its standard output.
+fn run_lsb_release() -> IoResult<String> {
+    let output = Command::new("lsb_release").arg("-sr").output()?;
+    if !output.status.success() {
+        return Err(IoError::new(
+            ErrorKind::Other,
+            format!("lsb_release -sr failed: {status}", status = output.status),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+}
+
+/// Fill in a missing or non-numeric os-release "VERSION_ID" field using
+/// the output of `lsb_release -sr`, but only if the "ID" field is set.
+///
+/// The `run` function runs `lsb_release -sr` and returns its standard output;
+/// a "not found" error means that the tool is not installed.
+/// Any errors are logged and the data is left unchanged.
+///
+/// Returns true if the "VERSION_ID" field was set.
+pub(crate) fn fill_version_id_from_lsb_release<F>(
+    data: &mut HashMap<String, String>,
+    run: F,
+) -> bool
+where
+    F: FnOnce() -> IoResult<String>,
+{
+    if !data.contains_key("ID")
+        || data
+            .get("VERSION_ID")
+            .map_or(false, |version_id| is_numeric_version(version_id))
+    {
+        return false;
+    }
+    info!("No usable os-release VERSION_ID field, running `lsb_release -sr` as a last resort");
+    match run() {
+        Ok(output) => {
+            let version_id = output.trim();
+            if is_numeric_version(version_id) {
+                debug!("Using VERSION_ID {version_id} reported by lsb_release");
+                data.insert("VERSION_ID".to_owned(), version_id.to_owned());
+                true
+            } else {
+                warn!("Unexpected lsb_release version output: {output:?}");
+                false
+            }
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            debug!("The lsb_release tool is not installed");
+            false
+        }
+        Err(err) => {
+            warn!("Could not run lsb_release: {err}");
+            false
         }
-        Err(YAIError::FileRead(io_err)) if io_err.kind() == ErrorKind::NotFound => (),
-        Err(err) => return Err(VariantError::OsRelease(err)),
     }
+}
 
-    for kind in &variants.order {
-        let var = &variants.variants.get(kind).ok_or_else(|| {
+/// Choose the first of the `/etc/os-release` and `/usr/lib/os-release` files, or
+/// the other way around if [`DetectConfig::prefer_usr_lib`] is set.
+///
+/// Unless [`DetectConfig::dereference_symlinks`] is set, only the first one is tried.
+fn choose_os_release<P: AsRef<Path>>(
+    candidates: &[P],
+    config: &DetectConfig,
+) -> Result<Option<PathBuf>, VariantError> {
+    let mut candidates: Vec<&Path> = candidates.iter().map(AsRef::as_ref).collect();
+    if config.prefer_usr_lib {
+        candidates.reverse();
+    }
+    if config.dereference_symlinks {
+        resolve_os_release(&candidates)
+    } else {
+        Ok(candidates.first().map(|path| path.to_path_buf()))
+    }
+}
+
+/// Get the path to the os-release file that the detection reads.
+///
+/// # Errors
+/// [`VariantError::FileRead`] if [`DetectConfig::dereference_symlinks`] is set and
+/// a candidate path could not be examined.
+#[inline]
+pub fn os_release_path(config: &DetectConfig) -> Result<Option<PathBuf>, VariantError> {
+    choose_os_release(&OS_RELEASE_CANDIDATES, config)
+}
+
+/// Read the os-release file chosen by [`choose_os_release`].
+///
+/// If [`DetectConfig::timeout`] is set, the file is read in a separate thread.
+fn read_os_release_from<P: AsRef<Path>>(
+    candidates: &[P],
+    config: &DetectConfig,
+) -> Result<Option<OsReleaseData>, VariantError> {
+    match choose_os_release(candidates, config)? {
+        Some(os_release) => match config.timeout {
+            Some(timeout) => {
+                let path = os_release.clone();
+                read_with_timeout(&os_release, timeout, move || fs::read_to_string(path))?
+                    .map_err(YAIError::FileRead)
+                    .and_then(|contents| yai::parse_str(&contents))
+            }
+            None => yai::parse(&os_release),
+        }
+        .map_or_else(
+            |err| match err {
+                YAIError::FileRead(io_err) if io_err.kind() == ErrorKind::NotFound => Ok(None),
+                err => Err(VariantError::OsRelease(err)),
+            },
+            |data| Ok(Some((os_release, data))),
+        ),
+        None => Ok(None),
+    }
+}
+
+/// Run a read operation in a separate thread, give up if it does not complete in time.
+///
+/// There is no way to interrupt a blocked read, so on a timeout the thread is
+/// left running in the background and its result is discarded.
+fn read_with_timeout<F>(
+    path: &Path,
+    timeout: Duration,
+    read: F,
+) -> Result<IoResult<String>, VariantError>
+where
+    F: FnOnce() -> IoResult<String> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("os-release-read".to_owned())
+        .spawn(move || {
+            // The receiver is gone if the read timed out.
+            tx.send(read()).ok();
+        })
+        .map_err(|err| VariantError::Internal(format!("Could not start a thread: {err}")))?;
+    match rx.recv_timeout(timeout) {
+        Ok(res) => Ok(res),
+        Err(RecvTimeoutError::Timeout) => Err(VariantError::DetectionTimeout(
+            path.display().to_string(),
+            timeout,
+        )),
+        Err(RecvTimeoutError::Disconnected) => Err(VariantError::Internal(format!(
+            "The thread reading {path} exited unexpectedly",
+            path = path.display()
+        ))),
+    }
+}
+
+/// Check whether a line in the variant's distribution-specific release file
+/// matches its version pattern.
+fn release_file_matches(var: &Variant) -> Result<bool, VariantError> {
+    let re_line = RegexBuilder::new(&var.detect.regex)
+        .ignore_whitespace(true)
+        .build()
+        .map_err(|err| {
             VariantError::Internal(format!(
-                "Internal error: unknown variant {kind} in the order",
-                kind = kind.as_ref()
+                "Internal error: {kind}: could not parse '{regex}': {err}",
+                kind = var.kind.as_ref(),
+                regex = var.detect.regex
             ))
         })?;
-        let re_line = RegexBuilder::new(&var.detect.regex)
-            .ignore_whitespace(true)
-            .build()
-            .map_err(|err| {
-                VariantError::Internal(format!(
-                    "Internal error: {kind}: could not parse '{regex}': {err}",
-                    kind = kind.as_ref(),
-                    regex = var.detect.regex
+    match fs::read(&var.detect.filename) {
+        Ok(file_bytes) => Ok(String::from_utf8(file_bytes).map_or(false, |contents| {
+            contents.lines().any(|line| re_line.is_match(line))
+        })),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(VariantError::FileRead(
+            var.kind.as_ref().to_owned(),
+            var.detect.filename.clone(),
+            err,
+        )),
+    }
+}
+
+/// Find the first os-release file that exists, following any symlinks.
+///
+/// A missing file or a dangling symlink is skipped, so that e.g. a broken
+/// `/etc/os-release` link does not hide `/usr/lib/os-release`.
+/// The resolved path of the file that will be read is logged.
+///
+/// # Errors
+/// [`VariantError::FileRead`] if a candidate path could not be examined for
+/// a reason other than it not existing.
+#[inline]
+pub fn resolve_os_release<P: AsRef<Path>>(
+    candidates: &[P],
+) -> Result<Option<PathBuf>, VariantError> {
+    for candidate in candidates {
+        let candidate = candidate.as_ref();
+        match fs::canonicalize(candidate) {
+            Ok(path) => {
+                info!(
+                    "Reading the os-release data from {path} ({candidate})",
+                    path = path.display(),
+                    candidate = candidate.display()
+                );
+                return Ok(Some(path));
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => debug!(
+                "No {candidate} file or a dangling symlink, trying the next one",
+                candidate = candidate.display()
+            ),
+            Err(err) => {
+                return Err(VariantError::FileRead(
+                    "os-release".to_owned(),
+                    candidate.display().to_string(),
+                    err,
                 ))
-            })?;
-        match fs::read(&var.detect.filename) {
-            Ok(file_bytes) => {
-                if let Ok(contents) = String::from_utf8(file_bytes) {
-                    {
-                        if contents.lines().any(|line| re_line.is_match(line)) {
-                            return Ok(var);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Detect a variant using the fields parsed from an os-release file.
+///
+/// The "ID" and "VERSION_ID" fields are matched against the variant
+/// definitions first. If that fails and [`DetectConfig::id_like`] is set,
+/// the distributions listed in the "ID_LIKE" field are examined in turn:
+/// for e.g. "ubuntu", the "UBUNTU_CODENAME" field is matched against
+/// the codenames of the Ubuntu variants. If there is no such field,
+/// the "VERSION_CODENAME" one is used instead, e.g. Raspbian only sets
+/// "ID_LIKE=debian" and "VERSION_CODENAME=bullseye".
+///
+/// The "VERSION_ID" field is only considered if it consists of
+/// dot-separated ASCII decimal numbers, e.g. "8" or "8.9"; a value like
+/// "8.x" never matches a variant, so the detection falls back to the
+/// "ID_LIKE" field (if enabled) or fails.
+///
+/// If [`DetectConfig::lenient`] is set and a "VERSION_ID" value like "7.10"
+/// does not match any variant, only its major number ("7") is tried next,
+/// e.g. for a point release that is newer than the variant definitions.
+/// A value without a dot is never shortened, so a new major release of
+/// a distribution is still not detected as an older one.
+///
+/// # Errors
+/// An internal error if the variants data is inconsistent.
+#[inline]
+pub fn detect_from_os_release<'defs>(
+    variants: &'defs VariantDefTop,
+    data: &HashMap<String, String>,
+    config: &DetectConfig,
+) -> Result<Option<&'defs Variant>, VariantError> {
+    detect_result_from_os_release(variants, data, config).map(|res| res.map(|res| res.variant))
+}
+
+/// Detect a variant using the fields parsed from an os-release file, also report
+/// how certain the detection is.
+///
+/// A match on the "ID" and "VERSION_ID" fields is reported as [`Confidence::Exact`],
+/// a match on the major version number only as [`Confidence::Lenient`],
+/// a match using the "CPE_NAME" field as [`Confidence::Cpe`],
+/// a match using the "ID_LIKE" field as [`Confidence::Fallback`].
+///
+/// # Errors
+/// Same as for [`detect_from_os_release()`].
+#[allow(clippy::missing_inline_in_public_items)]
+pub fn detect_result_from_os_release<'defs>(
+    variants: &'defs VariantDefTop,
+    data: &HashMap<String, String>,
+    config: &DetectConfig,
+) -> Result<Option<DetectionResult<'defs>>, VariantError> {
+    let res = detect_all_from_os_release(variants, data, config)?
+        .into_iter()
+        .next();
+    if let Some(DetectionResult {
+        variant: var,
+        confidence: Confidence::Lenient,
+    }) = res
+    {
+        let version_id = data.get("VERSION_ID").map_or("", String::as_str);
+        warn!(
+            "Unknown {os_id} version {version_id}, using the {kind} build variant for version {major}",
+            os_id = data.get("ID").map_or("", String::as_str),
+            kind = var.kind.as_ref(),
+            major = version_id
+                .split_once('.')
+                .map_or(version_id, |(major, _)| major)
+        );
+    }
+    Ok(res)
+}
+
+/// Find all the variants that match the fields parsed from an os-release file.
+///
+/// The results are in order of preference: all the [`Confidence::Exact`] matches
+/// first, then the [`Confidence::Lenient`] ones (if enabled), then
+/// the [`Confidence::Cpe`] ones, and then the [`Confidence::Fallback`] ones
+/// (if enabled).
+///
+/// The vendor in the "CPE_NAME" field (e.g. `cpe:/o:rocky:rocky:8:GA`) tells
+/// the RHEL clones apart even if the "ID" field names a derivative distribution
+/// or the "ID_LIKE" field lists several of them.
+///
+/// # Errors
+/// Same as for [`detect_from_os_release()`].
+#[allow(clippy::missing_inline_in_public_items)]
+pub fn detect_all_from_os_release<'defs>(
+    variants: &'defs VariantDefTop,
+    data: &HashMap<String, String>,
+    config: &DetectConfig,
+) -> Result<Vec<DetectionResult<'defs>>, VariantError> {
+    let mut res = Vec::new();
+    if let Some(os_id) = data.get("ID") {
+        if let Some(version_id) = data
+            .get("VERSION_ID")
+            .filter(|version_id| is_numeric_version(version_id))
+        {
+            let candidates: Vec<&Variant> = ordered_variants(variants)?
+                .into_iter()
+                .filter(|var| var.detect.os_id == *os_id)
+                .collect();
+            for var in &candidates {
+                if version_matches(var, version_id)? {
+                    res.push(DetectionResult::new(var, Confidence::Exact));
+                }
+            }
+
+            if config.lenient {
+                if let Some((major, _)) = version_id.split_once('.') {
+                    // Prefer the newest variant, the last one in the detection order.
+                    for var in candidates.iter().rev() {
+                        if version_matches(var, major)? {
+                            res.push(DetectionResult::new(var, Confidence::Lenient));
                         }
                     }
                 }
             }
-            Err(err) => {
-                if err.kind() != ErrorKind::NotFound {
-                    return Err(VariantError::FileRead(
-                        var.kind.as_ref().to_owned(),
-                        var.detect.filename.clone(),
-                        err,
-                    ));
+        }
+    }
+
+    if let Some(cpe) = data.get("CPE_NAME").and_then(|value| CpeName::parse(value)) {
+        if let Some(&(_, os_id)) = CPE_VENDORS
+            .iter()
+            .find(|&&(vendor, _)| vendor == cpe.vendor)
+        {
+            if is_numeric_version(&cpe.version) {
+                for var in ordered_variants(variants)? {
+                    if var.detect.os_id == os_id && version_matches(var, &cpe.version)? {
+                        res.push(DetectionResult::new(var, Confidence::Cpe));
+                    }
                 }
             }
-        };
+        }
     }
-    Err(VariantError::UnknownVariant)
+
+    if config.id_like {
+        if let Some(id_like) = data.get("ID_LIKE") {
+            for base in id_like.split_whitespace() {
+                let codename = match data
+                    .get(&format!("{base}_CODENAME").to_uppercase())
+                    .or_else(|| data.get("VERSION_CODENAME"))
+                {
+                    Some(codename) => codename,
+                    None => continue,
+                };
+                for var in ordered_variants(variants)? {
+                    if var.detect.os_id != base {
+                        continue;
+                    }
+                    if let Repo::Deb(ref deb) = var.repo {
+                        if deb.codename == *codename {
+                            res.push(DetectionResult::new(var, Confidence::Fallback));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Check whether an os-release "VERSION_ID" value matches the variant's version pattern.
+fn version_matches(var: &Variant, version_id: &str) -> Result<bool, VariantError> {
+    let re_ver = RegexBuilder::new(&var.detect.os_version_regex)
+        .ignore_whitespace(true)
+        .build()
+        .map_err(|err| {
+            VariantError::Internal(format!(
+                "Internal error: {kind}: could not parse '{regex}': {err}",
+                kind = var.kind.as_ref(),
+                regex = var.detect.os_version_regex
+            ))
+        })?;
+    Ok(re_ver.is_match(version_id))
+}
+
+/// Check whether a version string consists of dot-separated decimal numbers.
+///
+/// Only ASCII digits are accepted, regardless of the current locale.
+fn is_numeric_version(version: &str) -> bool {
+    version
+        .split('.')
+        .all(|comp| !comp.is_empty() && comp.bytes().all(|chr| chr.is_ascii_digit()))
+}
+
+/// Get the variants in the detection order, make sure all of them are defined.
+fn ordered_variants(variants: &VariantDefTop) -> Result<Vec<&Variant>, VariantError> {
+    variants
+        .order
+        .iter()
+        .map(|kind| {
+            variants.variants.get(kind).ok_or_else(|| {
+                VariantError::Internal(format!(
+                    "Internal error: unknown variant {kind} in the order",
+                    kind = kind.as_ref()
+                ))
+            })
+        })
+        .collect()
 }
 
 /// Get the variant with the specified name from the supplied data.
@@ -330,6 +1527,56 @@ pub fn get_by_alias_from<'defs>(
         .ok_or_else(|| VariantError::Internal(format!("No variant with the {alias} alias")))
 }
 
+/// The directory that the Apt sources list files are installed into.
+pub const APT_SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+
+/// The directory that the Yum repository definition files are installed into.
+pub const YUM_REPOS_DIR: &str = "/etc/yum.repos.d";
+
+/// Replace the value of a field in the text of a repository definition file.
+///
+/// The value of each line starting with `prefix` is replaced; the rest of
+/// the lines are left unchanged.
+#[inline]
+#[must_use]
+pub fn replace_field(text: &str, prefix: &str, value: &str) -> String {
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.starts_with(prefix) {
+                let eol = if line.ends_with('\n') { "\n" } else { "" };
+                format!("{prefix}{value}{eol}")
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect()
+}
+
+/// Get the name of a repository type (e.g. "staging") from its filename extension
+/// (e.g. "-staging"); the default "contrib" repository type has no extension.
+fn repotype_name(repotype_extension: &str) -> &str {
+    repo_types()
+        .iter()
+        .find(|repotype| repotype.extension() == repotype_extension)
+        .map_or(RepoType::CONTRIB, RepoType::name)
+}
+
+/// Build the name of a repository file to install for the specified repository type.
+///
+/// Any leading directory components are removed from the original path and
+/// the extension (e.g. "-staging") is inserted right before the last
+/// filename extension, or appended if there is none:
+/// `debian/repo/storpool.sources` becomes `storpool-staging.sources`.
+#[inline]
+#[must_use]
+pub fn repo_filename(original: &str, repotype_extension: &str) -> String {
+    let filename = original.rsplit('/').next().unwrap_or(original);
+    match filename.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => format!("{base}{repotype_extension}.{ext}"),
+        _ => format!("{filename}{repotype_extension}"),
+    }
+}
+
 /// Get information about all variants.
 #[inline]
 #[must_use]
@@ -344,6 +1591,30 @@ pub const fn get_all_variants_from(variants: &VariantDefTop) -> &HashMap<Variant
     &variants.variants
 }
 
+/// Add distribution-specific commands to all the variants in the specified structure.
+///
+/// The commands are keyed by category and name; any existing ones with the same
+/// category and name are replaced and no longer treated as templates.
+#[inline]
+pub fn merge_commands_into(
+    variants: &mut VariantDefTop,
+    extra: &HashMap<String, HashMap<String, Vec<String>>>,
+) {
+    for var in variants.variants.values_mut() {
+        for (category, cmds) in extra {
+            for (name, cmd) in cmds {
+                let cmd_id = format!("{category}.{name}");
+                var.templated_commands
+                    .retain(|templated| *templated != cmd_id);
+                var.commands
+                    .entry(category.clone())
+                    .or_default()
+                    .insert(name.clone(), cmd.clone());
+            }
+        }
+    }
+}
+
 /// Get information about all variants in the order of inheritance between them.
 #[inline]
 pub fn get_all_variants_in_order() -> impl Iterator<Item = &'static Variant> {
@@ -362,6 +1633,92 @@ pub fn get_all_variants_in_order_from(variants: &VariantDefTop) -> impl Iterator
     variants.order.iter().map(|kind| &variants.variants[kind])
 }
 
+/// Check the supplied variant data for internal consistency.
+///
+/// Returns a list of human-readable descriptions of the problems found;
+/// an empty list means that the data is consistent.
+#[inline]
+#[must_use]
+pub fn check_variants(variants: &VariantDefTop) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if variants.format.version.major == 0 {
+        problems.push("The format version is not set".to_owned());
+    }
+    if variants.version.is_empty() {
+        problems.push("The program version is not set".to_owned());
+    }
+
+    for kind in enum_iterator::all::<VariantKind>() {
+        match variants
+            .order
+            .iter()
+            .filter(|other| **other == kind)
+            .count()
+        {
+            0 => problems.push(format!("The {kind} variant is not in the detection order")),
+            1 => (),
+            count => problems.push(format!(
+                "The {kind} variant is listed {count} times in the detection order"
+            )),
+        }
+        let var = match variants.variants.get(&kind) {
+            Some(var) => var,
+            None => {
+                problems.push(format!("No data for the {kind} variant"));
+                continue;
+            }
+        };
+        if var.kind != kind {
+            problems.push(format!(
+                "The data for the {kind} variant is for {other}",
+                other = var.kind
+            ));
+        }
+        if !var.parent.is_empty()
+            && !var
+                .parent
+                .parse::<VariantKind>()
+                .map_or(false, |parent| variants.variants.contains_key(&parent))
+        {
+            problems.push(format!(
+                "{kind}: unknown parent variant '{parent}'",
+                parent = var.parent
+            ));
+        }
+        for (field, regex) in [
+            ("detect.regex", &var.detect.regex),
+            ("detect.os_version_regex", &var.detect.os_version_regex),
+        ] {
+            if let Err(err) = RegexBuilder::new(regex).ignore_whitespace(true).build() {
+                problems.push(format!("{kind}: invalid {field} '{regex}': {err}"));
+            }
+        }
+        let (keyrings, repofile) = match var.repo {
+            Repo::Deb(ref deb) => (&deb.keyrings, &deb.sources),
+            Repo::Yum(ref yum) => (&yum.keyrings, &yum.yumdef),
+        };
+        if keyrings.default.is_empty()
+            || keyrings.by_repotype.values().any(Vec::is_empty)
+            || keyrings.all().iter().any(|path| path.is_empty())
+        {
+            problems.push(format!("{kind}: no repository keyring file"));
+        }
+        if repofile.is_empty() {
+            problems.push(format!("{kind}: no repository definition file"));
+        }
+        if var
+            .post_add
+            .iter()
+            .any(|cmd| cmd.first().map_or(true, String::is_empty))
+        {
+            problems.push(format!("{kind}: empty post-add command"));
+        }
+    }
+
+    problems
+}
+
 /// Get the metadata format version of the variant data.
 #[inline]
 #[must_use]
@@ -376,6 +1733,170 @@ pub const fn get_format_version_from(variants: &VariantDefTop) -> (u32, u32) {
     (variants.format.version.major, variants.format.version.minor)
 }
 
+/// Get the metadata format version of the supplied variant data structure as an object.
+#[inline]
+#[must_use]
+pub const fn get_format_version_struct_from(variants: &VariantDefTop) -> VariantFormatVersion {
+    variants.format.version
+}
+
+/// Check whether this library can handle the format of the supplied variant data.
+///
+/// Logs a warning if the data declares a format version that is not compatible with
+/// the one of the embedded variant definitions, e.g. a newer minor version.
+#[inline]
+#[must_use]
+pub fn check_format_version_from(variants: &VariantDefTop) -> bool {
+    let supported = get_format_version_struct_from(build_variants());
+    let required = get_format_version_struct_from(variants);
+    let compatible = supported.is_compatible_with(required);
+    if !compatible {
+        warn!(
+            "The variant data declares format version {rmaj}.{rmin}, only {smaj}.{smin} is supported",
+            rmaj = required.major,
+            rmin = required.minor,
+            smaj = supported.major,
+            smin = supported.minor
+        );
+    }
+    compatible
+}
+
+/// Get the machine architecture of the current host, e.g. "x86_64" or "aarch64".
+///
+/// Since the variant data does not depend on the architecture, this is
+/// reported separately from the [`Variant`] structure.
+#[inline]
+#[must_use]
+pub const fn get_host_arch() -> &'static str {
+    std::env::consts::ARCH
+}
+
+/// The init system that the host was booted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum InitSystem {
+    /// The host was booted with systemd.
+    Systemd,
+    /// The host uses upstart.
+    Upstart,
+    /// The host uses the traditional System V init.
+    SysVinit,
+    /// None of the known init systems was found.
+    Unknown,
+}
+
+impl AsRef<str> for InitSystem {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        match *self {
+            Self::Systemd => "systemd",
+            Self::Upstart => "upstart",
+            Self::SysVinit => "sysvinit",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl Display for InitSystem {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_ref())
+    }
+}
+
+/// Detect the init system of the current host.
+///
+/// This does not depend on the detected variant: e.g. a container built from
+/// a distribution that uses systemd may still be running without it.
+#[inline]
+#[must_use]
+pub fn detect_init_system() -> InitSystem {
+    detect_init_system_in(Path::new("/"))
+}
+
+/// Detect the init system by examining the files below the specified root directory.
+///
+/// A `run/systemd/system/` directory means that the host was booted with systemd,
+/// as described in the sd_booted(3) manual page; otherwise, the target of
+/// the `sbin/init` symlink is examined, and a real `sbin/init` file with
+/// an `sbin/initctl` tool next to it is taken to be upstart.
+#[inline]
+#[must_use]
+pub fn detect_init_system_in(root: &Path) -> InitSystem {
+    if root.join("run/systemd/system").is_dir() {
+        return InitSystem::Systemd;
+    }
+    let init = root.join("sbin/init");
+    match fs::read_link(&init) {
+        Ok(target) => {
+            let target = target.to_string_lossy();
+            if target.contains("systemd") {
+                InitSystem::Systemd
+            } else if target.contains("upstart") {
+                InitSystem::Upstart
+            } else {
+                InitSystem::SysVinit
+            }
+        }
+        Err(_) if init.is_file() => {
+            if root.join("sbin/initctl").is_file() {
+                InitSystem::Upstart
+            } else {
+                InitSystem::SysVinit
+            }
+        }
+        Err(_) => InitSystem::Unknown,
+    }
+}
+
+/// Facts about the environment that the host runs in.
+///
+/// These are only informational: they do not affect the variant detection,
+/// but some of the installation steps may behave differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub struct HostEnvironment {
+    /// Is this the Windows Subsystem for Linux?
+    pub wsl: bool,
+    /// Is this a Docker, Podman, LXC, or similar container?
+    pub container: bool,
+}
+
+/// The control group name components that show that the init process runs in a container.
+const CONTAINER_CGROUP_MARKERS: [&str; 5] = ["docker", "kubepods", "containerd", "libpod", "lxc"];
+
+/// Detect the environment that the current host runs in.
+#[inline]
+#[must_use]
+pub fn detect_environment() -> HostEnvironment {
+    detect_environment_in(Path::new("/"))
+}
+
+/// Detect the environment by examining the files below the specified root directory.
+///
+/// A `proc/version` file that mentions Microsoft means WSL.
+/// A `.dockerenv` or `run/.containerenv` file, or a control group of
+/// the init process named after a container runtime, means a container.
+#[inline]
+#[must_use]
+pub fn detect_environment_in(root: &Path) -> HostEnvironment {
+    let wsl = fs::read_to_string(root.join("proc/version")).map_or(false, |version| {
+        version.to_lowercase().contains("microsoft")
+    });
+    let container = root.join(".dockerenv").exists()
+        || root.join("run/.containerenv").exists()
+        || fs::read_to_string(root.join("proc/1/cgroup")).map_or(false, |cgroups| {
+            cgroups.lines().any(|line| {
+                CONTAINER_CGROUP_MARKERS
+                    .iter()
+                    .any(|marker| line.contains(marker))
+            })
+        });
+    debug!("Host environment: WSL {wsl}, container {container}");
+    HostEnvironment { wsl, container }
+}
+
 /// Get the program version from the variant data.
 #[inline]
 #[must_use]
@@ -0,0 +1,48 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Helpers for testing code that depends on the variant detection.
+//!
+//! These let the test suites of other crates replay an os-release file's
+//! fields against the built-in variant definitions without duplicating
+//! the detection rules. Enable the `test-util` feature to use them.
+
+use std::collections::HashMap;
+
+use crate::{DetectConfig, DetectionResult, VariantDefTop, VariantError};
+
+/// Build the map of os-release fields that the detection functions expect.
+#[inline]
+#[must_use]
+pub fn os_release_map(fields: &[(&str, &str)]) -> HashMap<String, String> {
+    fields
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Detect a variant from the specified os-release fields using the built-in definitions.
+///
+/// # Errors
+/// Same as for [`crate::detect_from_os_release()`].
+#[inline]
+pub fn detect_os_release(
+    fields: &[(&str, &str)],
+) -> Result<Option<DetectionResult<'static>>, VariantError> {
+    detect_os_release_with(crate::build_variants(), fields, &DetectConfig::default())
+}
+
+/// Detect a variant from the specified os-release fields using the specified
+/// definitions and options.
+///
+/// # Errors
+/// Same as for [`crate::detect_from_os_release()`].
+#[inline]
+pub fn detect_os_release_with<'defs>(
+    variants: &'defs VariantDefTop,
+    fields: &[(&str, &str)],
+    config: &DetectConfig,
+) -> Result<Option<DetectionResult<'defs>>, VariantError> {
+    crate::detect_result_from_os_release(variants, &os_release_map(fields), config)
+}
@@ -2,11 +2,25 @@
  * SPDX-FileCopyrightText: 2021 - 2023  StorPool <support@storpool.com>
  * SPDX-License-Identifier: BSD-2-Clause
  */
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::os::unix::fs as unix_fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
 use enum_iterator::{all, cardinality};
 
-use super::{VariantError, VariantKind};
+use super::keyring::{self, InstallerError, KeyringError, KeyringInstaller};
+use super::test_util::{self, os_release_map};
+use super::yai::YAIError;
+use super::{
+    Confidence, CpeName, DebRepo, DetectConfig, HostEnvironment, InitSystem, PackageManager, Repo,
+    RepoType, VariantDefTop, VariantError, VariantFormatVersion, VariantKind, YumRepo,
+};
 
 #[test]
 fn test_detect() -> Result<(), VariantError> {
@@ -15,9 +29,19 @@ fn test_detect() -> Result<(), VariantError> {
     Ok(())
 }
 
+#[test]
+fn test_current() -> Result<(), VariantError> {
+    let first = crate::current()?;
+    let second = crate::current()?;
+    println!("Current: {kind}", kind = first.kind);
+    assert!(std::ptr::eq(first, second));
+    assert_eq!(first.kind, crate::detect()?.kind);
+    Ok(())
+}
+
 #[test]
 fn test_roundtrip() {
-    println!("");
+    println!();
     let all = crate::build_variants();
     assert_eq!(all.order.len(), all.variants.len());
 
@@ -25,7 +49,7 @@ fn test_roundtrip() {
 
     for kind in &all.order {
         println!("Checking {kind}", kind = kind.as_ref());
-        let var = &all.variants[&kind];
+        let var = &all.variants[kind];
 
         let name = var.kind.as_ref().to_string();
         let alias = &var.builder.alias;
@@ -34,7 +58,7 @@ fn test_roundtrip() {
         assert!(!seen.contains(&name));
         seen.insert(name);
 
-        let avar = crate::get_by_alias_from(&all, alias).unwrap();
+        let avar = crate::get_by_alias_from(all, alias).unwrap();
         assert_eq!(avar.kind, *kind);
     }
 
@@ -48,7 +72,7 @@ fn test_roundtrip() {
     for name in &seen_vec {
         print!(" {name}");
     }
-    println!("");
+    println!();
     assert_eq!(seen_vec.len(), all.order.len());
 }
 
@@ -62,7 +86,7 @@ fn test_get_all() -> Result<(), VariantError> {
         all::<VariantKind>().collect::<HashSet<_>>()
     );
 
-    let all_hash_from_built = crate::get_all_variants_from(&built);
+    let all_hash_from_built = crate::get_all_variants_from(built);
     assert_eq!(all_hash_from_built.len(), count);
     for (key, value) in all_hash_from_built.iter() {
         assert_eq!(*key, value.kind);
@@ -71,7 +95,7 @@ fn test_get_all() -> Result<(), VariantError> {
     let all_hash = crate::get_all_variants();
     assert_eq!(*all_hash, *all_hash_from_built);
 
-    let in_order_from_built: Vec<_> = crate::get_all_variants_in_order_from(&built).collect();
+    let in_order_from_built: Vec<_> = crate::get_all_variants_in_order_from(built).collect();
     assert_eq!(
         in_order_from_built
             .iter()
@@ -84,3 +108,1144 @@ fn test_get_all() -> Result<(), VariantError> {
     assert_eq!(in_order, in_order_from_built);
     Ok(())
 }
+
+#[test]
+fn test_repo_filename() {
+    for (original, ext, expected) in [
+        ("storpool.sources", "-staging", "storpool-staging.sources"),
+        ("storpool.sources", "", "storpool.sources"),
+        (
+            "debian/repo/storpool.sources",
+            "-infra",
+            "storpool-infra.sources",
+        ),
+        (
+            "storpool-centos.el8.repo",
+            "-staging",
+            "storpool-centos.el8-staging.repo",
+        ),
+        ("storpool", "-staging", "storpool-staging"),
+        (".storpool", "-staging", ".storpool-staging"),
+        ("repo/.storpool.list", "-infra", ".storpool-infra.list"),
+    ] {
+        println!("- {original:?} {ext:?}: expected {expected:?}");
+        assert_eq!(crate::repo_filename(original, ext), expected);
+    }
+}
+
+const OS_RELEASE_MINT_21: [(&str, &str); 7] = [
+    ("NAME", "Linux Mint"),
+    ("VERSION", "21 (Vanessa)"),
+    ("ID", "linuxmint"),
+    ("ID_LIKE", "ubuntu debian"),
+    ("VERSION_ID", "21"),
+    ("UBUNTU_CODENAME", "jammy"),
+    ("VERSION_CODENAME", "vanessa"),
+];
+
+const OS_RELEASE_RASPBIAN_11: [(&str, &str); 7] = [
+    ("PRETTY_NAME", "Raspbian GNU/Linux 11 (bullseye)"),
+    ("NAME", "Raspbian GNU/Linux"),
+    ("VERSION_ID", "11"),
+    ("VERSION", "11 (bullseye)"),
+    ("VERSION_CODENAME", "bullseye"),
+    ("ID", "raspbian"),
+    ("ID_LIKE", "debian"),
+];
+
+const OS_RELEASE_AMZN_2: [(&str, &str); 7] = [
+    ("NAME", "Amazon Linux"),
+    ("VERSION", "2"),
+    ("ID", "amzn"),
+    ("ID_LIKE", "centos rhel fedora"),
+    ("VERSION_ID", "2"),
+    ("PRETTY_NAME", "Amazon Linux 2"),
+    ("CPE_NAME", "cpe:2.3:o:amazon:amazon_linux:2"),
+];
+
+const OS_RELEASE_FEDORA_38: [(&str, &str); 6] = [
+    ("NAME", "Fedora Linux"),
+    ("VERSION", "38 (Container Image)"),
+    ("ID", "fedora"),
+    ("VERSION_ID", "38"),
+    ("PLATFORM_ID", "platform:f38"),
+    ("PRETTY_NAME", "Fedora Linux 38 (Container Image)"),
+];
+
+#[test]
+fn test_detect_id_like() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let data = os_release_map(&OS_RELEASE_MINT_21);
+
+    let res = crate::detect_from_os_release(all, &data, &DetectConfig::default())?;
+    println!("Linux Mint 21 without ID_LIKE: {res:?}");
+    assert!(res.is_none());
+
+    let var =
+        crate::detect_from_os_release(all, &data, &DetectConfig::default().with_id_like(true))?
+            .unwrap();
+    println!(
+        "Linux Mint 21 with ID_LIKE: {kind}",
+        kind = var.kind.as_ref()
+    );
+    assert_eq!(var.kind, VariantKind::UBUNTU2204);
+    Ok(())
+}
+
+#[test]
+fn test_detect_lenient() -> Result<(), VariantError> {
+    let lenient = DetectConfig::default().with_lenient(true);
+    let detect = |all: &VariantDefTop, version_id: &str, config: &DetectConfig| {
+        let data = os_release_map(&[("ID", "centos"), ("VERSION_ID", version_id)]);
+        crate::detect_result_from_os_release(all, &data, config)
+            .map(|res| res.map(|res| (res.variant.kind, res.confidence)))
+    };
+
+    // Pretend that the variant definitions only know about CentOS 7.0 through 7.9.
+    let mut older: serde_json::Value = serde_json::to_value(crate::build_variants()).unwrap();
+    older["variants"]["CENTOS7"]["detect"]["os_version_regex"] =
+        serde_json::Value::from(r"^7(?:$|\.[0-9]$)");
+    let older: VariantDefTop = serde_json::from_value(older).unwrap();
+    assert_eq!(
+        detect(&older, "7.9", &DetectConfig::default())?,
+        Some((VariantKind::CENTOS7, Confidence::Exact))
+    );
+    assert_eq!(detect(&older, "7.10", &DetectConfig::default())?, None);
+    assert_eq!(
+        detect(&older, "7.10", &lenient)?,
+        Some((VariantKind::CENTOS7, Confidence::Lenient))
+    );
+
+    // CentOS 8.3 is too old for the CENTOS8 variant, but close enough.
+    let all = crate::build_variants();
+    assert_eq!(detect(all, "8.3", &DetectConfig::default())?, None);
+    assert_eq!(
+        detect(all, "8.3", &lenient)?,
+        Some((VariantKind::CENTOS8, Confidence::Lenient))
+    );
+
+    // An unknown major version is not mistaken for an older one.
+    assert_eq!(detect(all, "10", &lenient)?, None);
+    assert_eq!(detect(all, "10.1", &lenient)?, None);
+    assert_eq!(Confidence::Lenient.to_string(), "lenient");
+    Ok(())
+}
+
+#[test]
+fn test_required_packages() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let var = crate::get_from(all, "DEBIAN10")?;
+    match var.repo {
+        Repo::Deb(ref deb) => assert!(!deb.req_packages.is_empty()),
+        _ => panic!("Expected a Debian repository for {var:?}"),
+    }
+    assert_eq!(var.required_packages(), ["ca-certificates"]);
+    assert_eq!(
+        crate::get_from(all, "ALMA9")?.required_packages(),
+        ["ca-certificates"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_package_commands() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let packages = vec!["curl".to_owned(), "jq".to_owned()];
+    for (name, install_tool, remove_tool) in [
+        ("DEBIAN12", "apt-get", "apt-get"),
+        ("ALMA9", "dnf", "yum"),
+        ("CENTOS7", "yum", "yum"),
+    ] {
+        let var = crate::get_from(all, name)?;
+        let install = var.package_install_command(&packages)?;
+        println!("{name}: install: {install:?}");
+        assert!(install.iter().any(|word| word == install_tool));
+        assert!(install.ends_with(&packages));
+
+        let remove = var.package_remove_command(&packages)?;
+        println!("{name}: remove: {remove:?}");
+        assert!(remove.iter().any(|word| word == remove_tool));
+        assert!(remove.ends_with(&packages));
+
+        assert!(matches!(
+            var.command("package", "no-such-command"),
+            Err(VariantError::UnknownCommand(_, _))
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pkgfile_install_program() {
+    for var in crate::get_all_variants_in_order() {
+        let install = var.command("pkgfile", "install").unwrap();
+        println!("{kind}: {install:?}", kind = var.kind.as_ref());
+        let program = install.first().unwrap();
+        assert!(
+            !program.is_empty() && !program.contains(char::is_whitespace),
+            "{kind}: pkgfile.install does not start with a program name",
+            kind = var.kind.as_ref()
+        );
+    }
+}
+
+#[test]
+fn test_kind_serde() {
+    for kind in all::<VariantKind>() {
+        let name = kind.to_string();
+        assert_eq!(name, kind.as_ref());
+
+        let json = serde_json::to_string(&kind).unwrap();
+        println!("{kind}: {json}");
+        assert_eq!(json, format!("\"{name}\""));
+
+        let parsed: VariantKind = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, kind);
+        assert_eq!(name.parse::<VariantKind>().unwrap(), kind);
+    }
+
+    assert!(serde_json::from_str::<VariantKind>("\"NO-SUCH-VARIANT\"").is_err());
+}
+
+#[test]
+fn test_check_variants() {
+    let problems = crate::check_variants(crate::build_variants());
+    println!("{problems:?}");
+    assert!(problems.is_empty());
+
+    let mut broken: serde_json::Value = serde_json::to_value(crate::build_variants()).unwrap();
+    broken["variants"]
+        .as_object_mut()
+        .unwrap()
+        .remove("DEBIAN12")
+        .unwrap();
+    let broken: VariantDefTop = serde_json::from_value(broken).unwrap();
+    let problems = crate::check_variants(&broken);
+    println!("{problems:?}");
+    assert_eq!(
+        problems,
+        [
+            "DEBIAN11: unknown parent variant 'DEBIAN12'".to_owned(),
+            "No data for the DEBIAN12 variant".to_owned(),
+        ]
+    );
+
+    let mut data = serde_json::to_value(crate::build_variants()).unwrap();
+    data["variants"]["ALMA9"]["post_add"] = serde_json::json!([["yum"], []]);
+    let broken: VariantDefTop = serde_json::from_value(data).unwrap();
+    assert_eq!(
+        crate::check_variants(&broken),
+        ["ALMA9: empty post-add command".to_owned()]
+    );
+}
+
+#[test]
+fn test_format_version() {
+    let current = crate::get_format_version_struct_from(crate::build_variants());
+    assert_eq!(
+        (current.major, current.minor),
+        crate::get_format_version_from(crate::build_variants())
+    );
+    assert!(crate::check_format_version_from(crate::build_variants()));
+
+    let supported = VariantFormatVersion::new(1, 4);
+    for (required, compatible) in [
+        ((1, 0), true),
+        ((1, 3), true),
+        ((1, 4), true),
+        ((1, 5), false),
+        ((0, 4), false),
+        ((2, 0), false),
+    ] {
+        let required = VariantFormatVersion::new(required.0, required.1);
+        println!("{supported:?} / {required:?}: expect {compatible}");
+        assert_eq!(supported.is_compatible_with(required), compatible);
+    }
+
+    assert!(VariantFormatVersion::new(1, 4) < VariantFormatVersion::new(1, 10));
+    assert!(VariantFormatVersion::new(1, 10) < VariantFormatVersion::new(2, 0));
+
+    let mut newer: serde_json::Value = serde_json::to_value(crate::build_variants()).unwrap();
+    newer["format"]["version"]["minor"] = serde_json::Value::from(current.minor + 1);
+    let newer: VariantDefTop = serde_json::from_value(newer).unwrap();
+    assert!(!crate::check_format_version_from(&newer));
+}
+
+#[test]
+fn test_detect_fedora() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let data = os_release_map(&OS_RELEASE_FEDORA_38);
+    let var = crate::detect_from_os_release(all, &data, &DetectConfig::default())?.unwrap();
+    println!("Fedora 38: {kind}", kind = var.kind);
+    assert_eq!(var.kind, VariantKind::FEDORA38);
+    assert_eq!(var.family, "redhat");
+    assert_eq!(var.command("package", "install")?[0], "dnf");
+    assert_eq!(var.package_remove_command(&[])?[0], "dnf");
+    Ok(())
+}
+
+#[test]
+fn test_keyrings_serde() {
+    let single: YumRepo = serde_json::from_str(
+        r#"{"yumdef": "redhat/repo/storpool-centos.repo", "keyring": "redhat/repo/RPM-GPG-KEY-StorPool"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        single.keyrings.default,
+        ["redhat/repo/RPM-GPG-KEY-StorPool"]
+    );
+    assert!(single.keyrings.by_repotype.is_empty());
+    assert_eq!(
+        serde_json::to_value(&single).unwrap()["keyring"],
+        "redhat/repo/RPM-GPG-KEY-StorPool"
+    );
+
+    let multiple: DebRepo = serde_json::from_str(
+        r#"{
+            "codename": "bookworm",
+            "vendor": "debian",
+            "sources": "debian/repo/storpool.sources",
+            "keyring": ["debian/repo/storpool-keyring.gpg", "debian/repo/storpool-keyring-new.gpg"],
+            "req_packages": []
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        multiple.keyrings.default,
+        [
+            "debian/repo/storpool-keyring.gpg",
+            "debian/repo/storpool-keyring-new.gpg"
+        ]
+    );
+    let reparsed: DebRepo =
+        serde_json::from_str(&serde_json::to_string(&multiple).unwrap()).unwrap();
+    assert_eq!(reparsed, multiple);
+}
+
+#[test]
+fn test_repo_types() {
+    let names: Vec<&str> = crate::repo_types().iter().map(RepoType::name).collect();
+    assert_eq!(names, ["contrib", "staging", "infra"]);
+    let extensions: Vec<&str> = crate::repo_types()
+        .iter()
+        .map(RepoType::extension)
+        .collect();
+    assert_eq!(extensions, ["", "-staging", "-infra"]);
+    for repotype in crate::repo_types() {
+        assert_eq!(repotype.name().parse::<RepoType>().unwrap(), *repotype);
+    }
+    let err = "all".parse::<RepoType>().unwrap_err();
+    assert!(matches!(err, VariantError::BadRepoType(ref name) if name == "all"));
+}
+
+#[test]
+fn test_package_manager() {
+    for var in crate::get_all_variants().values() {
+        let expected = match var.repo {
+            Repo::Deb(_) => PackageManager::Apt,
+            Repo::Yum(_) => PackageManager::Yum,
+        };
+        assert_eq!(var.package_manager(), expected, "{kind}", kind = var.kind);
+    }
+    let deb = crate::get_from(crate::build_variants(), "UBUNTU2404").unwrap();
+    assert_eq!(deb.package_manager(), PackageManager::Apt);
+    assert_eq!(deb.package_manager().tool(), "apt-get");
+    let yum = crate::get_from(crate::build_variants(), "ROCKY9").unwrap();
+    assert_eq!(yum.package_manager(), PackageManager::Yum);
+    assert_eq!(yum.package_manager().as_ref(), "yum");
+}
+
+#[test]
+fn test_keyrings_repotype() {
+    let by_type: YumRepo = serde_json::from_str(
+        r#"{
+            "yumdef": "redhat/repo/storpool-centos.repo",
+            "keyring": {
+                "default": "redhat/repo/RPM-GPG-KEY-StorPool",
+                "staging": ["redhat/repo/RPM-GPG-KEY-StorPool-staging"]
+            }
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        by_type.keyrings.for_repotype("contrib"),
+        ["redhat/repo/RPM-GPG-KEY-StorPool"]
+    );
+    assert_eq!(
+        by_type.keyrings.for_repotype("staging"),
+        ["redhat/repo/RPM-GPG-KEY-StorPool-staging"]
+    );
+    assert_eq!(
+        by_type.keyrings.all(),
+        [
+            "redhat/repo/RPM-GPG-KEY-StorPool",
+            "redhat/repo/RPM-GPG-KEY-StorPool-staging"
+        ]
+    );
+    assert_eq!(
+        serde_json::to_value(&by_type).unwrap()["keyring"],
+        serde_json::json!({
+            "default": "redhat/repo/RPM-GPG-KEY-StorPool",
+            "staging": "redhat/repo/RPM-GPG-KEY-StorPool-staging",
+        })
+    );
+    let reparsed: YumRepo =
+        serde_json::from_str(&serde_json::to_string(&by_type).unwrap()).unwrap();
+    assert_eq!(reparsed, by_type);
+
+    let mut var = crate::get_from(crate::build_variants(), "ALMA9")
+        .unwrap()
+        .clone();
+    var.repo = Repo::Yum(by_type);
+    assert_eq!(
+        keyring::keyring_filenames_for(&var, "staging").unwrap(),
+        ["RPM-GPG-KEY-StorPool-staging"]
+    );
+    assert_eq!(
+        keyring::keyring_filenames(&var).unwrap(),
+        ["RPM-GPG-KEY-StorPool", "RPM-GPG-KEY-StorPool-staging"]
+    );
+
+    let err = serde_json::from_str::<YumRepo>(
+        r#"{"yumdef": "storpool-centos.repo", "keyring": {"staging": "RPM-GPG-KEY-StorPool"}}"#,
+    )
+    .unwrap_err();
+    println!("{err}");
+}
+
+#[test]
+fn test_detect_confidence() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let data = os_release_map(&OS_RELEASE_FEDORA_38);
+    let res = crate::detect_result_from_os_release(all, &data, &DetectConfig::default())?.unwrap();
+    println!("Fedora 38: {res:?}");
+    assert_eq!(res.variant.kind, VariantKind::FEDORA38);
+    assert_eq!(res.confidence, Confidence::Exact);
+
+    let data = os_release_map(&OS_RELEASE_MINT_21);
+    let res = crate::detect_result_from_os_release(
+        all,
+        &data,
+        &DetectConfig::default().with_id_like(true),
+    )?
+    .unwrap();
+    println!("Linux Mint 21: {res:?}");
+    assert_eq!(res.variant.kind, VariantKind::UBUNTU2204);
+    assert_eq!(res.confidence, Confidence::Fallback);
+    assert_eq!(res.confidence.to_string(), "fallback");
+    assert_eq!(res.matched_rule(), "UBUNTU2204:fallback");
+    Ok(())
+}
+
+#[test]
+fn test_detect_matched_rule() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    for (kind, fields) in [
+        (
+            VariantKind::DEBIAN12,
+            &[("ID", "debian"), ("VERSION_ID", "12")],
+        ),
+        (
+            VariantKind::ALMA9,
+            &[("ID", "almalinux"), ("VERSION_ID", "9.4")],
+        ),
+    ] {
+        let res = test_util::detect_os_release_with(all, fields, &DetectConfig::default())?
+            .expect("The host was not detected");
+        println!("{kind}: {res:?}");
+        assert_eq!(res.variant.kind, kind);
+        let rule = res.matched_rule();
+        assert_eq!(rule, format!("{kind}:exact"));
+        let (data_key, _) = rule.split_once(':').unwrap();
+        assert!(all.variants.contains_key(&data_key.parse::<VariantKind>()?));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_detect_all_matches() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let config = DetectConfig::default().with_id_like(true);
+
+    // An Ubuntu 22.04 derivative that also claims to be Debian bookworm.
+    let data = os_release_map(&[
+        ("ID", "ubuntu"),
+        ("VERSION_ID", "22.04"),
+        ("ID_LIKE", "debian"),
+        ("VERSION_CODENAME", "bookworm"),
+    ]);
+    let found: Vec<(VariantKind, Confidence)> =
+        crate::detect_all_from_os_release(all, &data, &config)?
+            .into_iter()
+            .map(|res| (res.variant.kind, res.confidence))
+            .collect();
+    println!("Ubuntu 22.04 like bookworm: {found:?}");
+    assert_eq!(
+        found,
+        [
+            (VariantKind::UBUNTU2204, Confidence::Exact),
+            (VariantKind::DEBIAN12, Confidence::Fallback),
+        ]
+    );
+    assert_eq!(
+        crate::detect_result_from_os_release(all, &data, &config)?.map(|res| res.variant.kind),
+        Some(VariantKind::UBUNTU2204)
+    );
+
+    let data = os_release_map(&OS_RELEASE_FEDORA_38);
+    assert_eq!(
+        crate::detect_all_from_os_release(all, &data, &config)?
+            .into_iter()
+            .map(|res| res.variant.kind)
+            .collect::<Vec<_>>(),
+        [VariantKind::FEDORA38]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_detect_cpe_name() -> Result<(), VariantError> {
+    assert_eq!(
+        CpeName::parse("cpe:/o:rocky:rocky:8:GA"),
+        Some(CpeName {
+            vendor: "rocky".to_owned(),
+            product: "rocky".to_owned(),
+            version: "8".to_owned(),
+        })
+    );
+    assert_eq!(
+        CpeName::parse("cpe:2.3:o:almalinux:almalinux:8:*:*:*:*:*:*:*")
+            .map(|cpe| (cpe.vendor, cpe.version)),
+        Some(("almalinux".to_owned(), "8".to_owned()))
+    );
+    for invalid in [
+        "",
+        "rocky:8",
+        "cpe:/a:rocky:rocky:8",
+        "cpe:/o:rocky:rocky",
+        "cpe:2.3:o:rocky:rocky:*",
+    ] {
+        assert_eq!(CpeName::parse(invalid), None, "{invalid:?}");
+    }
+
+    // A rebuild that only tells us what it is based on in the CPE name.
+    let all = crate::build_variants();
+    let detect = |cpe_name: &str| {
+        let data = os_release_map(&[
+            ("ID", "derived"),
+            ("VERSION_ID", "8.8"),
+            ("ID_LIKE", "rhel centos fedora"),
+            ("CPE_NAME", cpe_name),
+        ]);
+        crate::detect_result_from_os_release(all, &data, &DetectConfig::default())
+            .map(|res| res.map(|res| (res.variant.kind, res.confidence)))
+    };
+    assert_eq!(
+        detect("cpe:/o:rocky:rocky:8:GA")?,
+        Some((VariantKind::ROCKY8, Confidence::Cpe))
+    );
+    assert_eq!(
+        detect("cpe:/o:almalinux:almalinux:8::baseos")?,
+        Some((VariantKind::ALMA8, Confidence::Cpe))
+    );
+    assert_eq!(
+        detect("cpe:/o:oracle:linux:8:8:server")?,
+        Some((VariantKind::ORACLE8, Confidence::Cpe))
+    );
+    assert_eq!(detect("cpe:/o:example:linux:8")?, None);
+    assert_eq!(Confidence::Cpe.to_string(), "cpe");
+
+    // The "ID" field still takes precedence.
+    let data = os_release_map(&[
+        ("ID", "almalinux"),
+        ("VERSION_ID", "8.8"),
+        ("CPE_NAME", "cpe:/o:rocky:rocky:8:GA"),
+    ]);
+    assert_eq!(
+        crate::detect_from_os_release(all, &data, &DetectConfig::default())?.map(|var| var.kind),
+        Some(VariantKind::ALMA8)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_detect_raspbian() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let data = os_release_map(&OS_RELEASE_RASPBIAN_11);
+
+    let res = crate::detect_from_os_release(all, &data, &DetectConfig::default())?;
+    println!("Raspbian 11 without ID_LIKE: {res:?}");
+    assert!(res.is_none());
+
+    let res = crate::detect_result_from_os_release(
+        all,
+        &data,
+        &DetectConfig::default().with_id_like(true),
+    )?
+    .unwrap();
+    println!("Raspbian 11 with ID_LIKE: {kind}", kind = res.variant.kind);
+    assert_eq!(res.variant.kind, VariantKind::DEBIAN11);
+    assert_eq!(res.confidence, Confidence::Fallback);
+    Ok(())
+}
+
+#[test]
+fn test_sources_filename() -> Result<(), VariantError> {
+    let var = crate::get_from(crate::build_variants(), "DEBIAN11")?;
+    let mut deb = match var.repo {
+        Repo::Deb(ref deb) => deb.clone(),
+        _ => panic!("Expected a Debian repository for {var:?}"),
+    };
+    assert_eq!(deb.sources_filename("", "arm"), "storpool.sources");
+
+    deb.arch_suffixes
+        .insert("arm".to_owned(), "-armhf".to_owned());
+    assert_eq!(deb.sources_filename("", "arm"), "storpool-armhf.sources");
+    assert_eq!(
+        deb.sources_filename("-staging", "arm"),
+        "storpool-staging-armhf.sources"
+    );
+    assert_eq!(
+        deb.sources_filename("-staging", "x86_64"),
+        "storpool-staging.sources"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_detect_amzn2() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let data = os_release_map(&OS_RELEASE_AMZN_2);
+    let var = crate::detect_from_os_release(all, &data, &DetectConfig::default())?.unwrap();
+    println!("Amazon Linux 2: {kind}", kind = var.kind);
+    assert_eq!(var.kind, VariantKind::AMZN2);
+    assert_eq!(var.family, "redhat");
+    assert_eq!(var.parent, "CENTOS7");
+    assert_eq!(var.command("package", "install")?[0], "yum");
+    match var.repo {
+        Repo::Yum(ref yum) => assert_eq!(yum.yumdef, "redhat/repo/storpool-amzn.repo"),
+        _ => panic!("Expected a Yum repository for {var:?}"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_detect_numeric_version() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    for (version_id, expected) in [
+        ("8", Some(VariantKind::ALMA8)),
+        ("8.9", Some(VariantKind::ALMA8)),
+        ("8.x", None),
+        ("8.9x", None),
+        ("8.", None),
+        ("８.9", None),
+    ] {
+        let data = os_release_map(&[
+            ("NAME", "AlmaLinux"),
+            ("ID", "almalinux"),
+            ("ID_LIKE", "rhel centos fedora"),
+            ("VERSION_ID", version_id),
+        ]);
+        let res =
+            crate::detect_from_os_release(all, &data, &DetectConfig::default().with_id_like(true))?;
+        println!(
+            "VERSION_ID {version_id:?}: {res:?}",
+            res = res.map(|var| var.kind)
+        );
+        assert_eq!(res.map(|var| var.kind), expected);
+    }
+
+    // Long, date-like components are still numeric.
+    assert!(crate::is_numeric_version("2023.20240101123456789"));
+    assert!(!crate::is_numeric_version(""));
+    Ok(())
+}
+
+/// Record the keyring installation steps, fail on request.
+#[derive(Debug, Default)]
+struct FakeInstaller {
+    calls: RefCell<Vec<String>>,
+    fail: bool,
+}
+
+impl KeyringInstaller for FakeInstaller {
+    fn install_file(&self, srcdir: &str, fname: &str, dstdir: &str) -> Result<(), InstallerError> {
+        self.calls
+            .borrow_mut()
+            .push(format!("install {srcdir}/{fname} {dstdir}"));
+        if self.fail {
+            return Err("no space left".into());
+        }
+        Ok(())
+    }
+
+    fn import_keys(&self, cmdvec: &[String]) -> Result<(), InstallerError> {
+        self.calls.borrow_mut().push(cmdvec.join(" "));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_install_keyring() -> Result<(), KeyringError> {
+    let all = crate::build_variants();
+
+    let deb = crate::get_from(all, "DEBIAN12").unwrap();
+    let fake = FakeInstaller::default();
+    keyring::install_keyring(deb, "/repo", None, &fake)?;
+    assert_eq!(
+        *fake.calls.borrow(),
+        ["install /repo/DEBIAN12/storpool-keyring.gpg /usr/share/keyrings"]
+    );
+
+    let yum = crate::get_from(all, "ALMA9").unwrap();
+    let fake = FakeInstaller::default();
+    keyring::install_keyring(yum, "/repo", Some("/opt/keys"), &fake)?;
+    let mut expected = vec!["install /repo/ALMA9/RPM-GPG-KEY-StorPool /opt/keys".to_owned()];
+    if let Some(cmdvec) = keyring::key_import_command(yum, "/opt/keys")? {
+        expected.push(cmdvec.join(" "));
+    }
+    assert_eq!(*fake.calls.borrow(), expected);
+
+    let mut inline = deb.clone();
+    match inline.repo {
+        Repo::Deb(ref mut repo) => repo.inline_key = true,
+        _ => panic!("Expected a Debian repository for {inline:?}"),
+    }
+    let fake = FakeInstaller::default();
+    keyring::install_keyring(&inline, "/repo", None, &fake)?;
+    assert!(fake.calls.borrow().is_empty());
+
+    let fake = FakeInstaller {
+        fail: true,
+        ..FakeInstaller::default()
+    };
+    let err = keyring::install_keyring(yum, "/repo", None, &fake).unwrap_err();
+    println!("{err}");
+    assert!(matches!(err, KeyringError::Install(..)));
+    assert_eq!(fake.calls.borrow().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_key_import_fallback() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let yum = crate::get_from(all, "ALMA9")?;
+    let deb = crate::get_from(all, "DEBIAN12")?;
+    let fnames = ["RPM-GPG-KEY-StorPool"];
+    let import_with = |var, tools: &[&str]| {
+        keyring::key_import_command_using(var, "/keys", &fnames, |path| {
+            tools.iter().any(|tool| path == format!("/usr/bin/{tool}"))
+        })
+    };
+
+    assert_eq!(
+        import_with(yum, &["rpmkeys", "rpm", "gpg"]).unwrap(),
+        ["rpmkeys", "--import", "/keys/RPM-GPG-KEY-StorPool"]
+    );
+    assert_eq!(
+        import_with(yum, &["rpm", "gpg"]).unwrap(),
+        ["rpm", "--import", "/keys/RPM-GPG-KEY-StorPool"]
+    );
+    assert_eq!(import_with(yum, &["rpm"]), None);
+    assert_eq!(import_with(yum, &["gpg"]), None);
+    assert_eq!(import_with(deb, &["rpmkeys", "rpm", "gpg"]), None);
+    Ok(())
+}
+
+#[test]
+fn test_render_repo_files() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let tempd = tempfile::tempdir().unwrap();
+    let repodir = tempd.path().to_str().unwrap();
+    let vdir = tempd.path().join("DEBIAN12");
+    fs::create_dir(&vdir).unwrap();
+
+    let var = crate::get_from(all, "DEBIAN12")?;
+    let sources_fname = match var.repo {
+        Repo::Deb(ref deb) => deb.sources_filename("-staging", crate::get_host_arch()),
+        _ => panic!("Expected a Debian repository for {var:?}"),
+    };
+    fs::write(
+        vdir.join(&sources_fname),
+        "Types: deb\nSigned-By: /usr/share/keyrings/storpool-keyring.gpg\n",
+    )
+    .unwrap();
+    fs::write(
+        vdir.join("storpool-keyring.gpg"),
+        format!("{header}\n\nmQINBF\n", header = keyring::ARMORED_KEY_HEADER),
+    )
+    .unwrap();
+
+    let rendered = var.render_repo_files("-staging", repodir)?;
+    assert_eq!(
+        rendered
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .collect::<Vec<_>>(),
+        [
+            format!("/etc/apt/sources.list.d/{sources_fname}").as_str(),
+            "/usr/share/keyrings/storpool-keyring.gpg",
+        ]
+    );
+    assert_eq!(
+        rendered[0].1,
+        b"Types: deb\nSigned-By: /usr/share/keyrings/storpool-keyring.gpg\n"
+    );
+
+    // With the keys embedded, only the sources list file is rendered.
+    let mut inline = var.clone();
+    match inline.repo {
+        Repo::Deb(ref mut repo) => repo.inline_key = true,
+        _ => panic!("Expected a Debian repository for {inline:?}"),
+    }
+    let rendered = inline.render_repo_files("-staging", repodir)?;
+    assert_eq!(rendered.len(), 1);
+    assert_eq!(
+        String::from_utf8(rendered[0].1.clone()).unwrap(),
+        format!(
+            "Types: deb\nSigned-By:\n {header}\n .\n mQINBF\n",
+            header = keyring::ARMORED_KEY_HEADER
+        )
+    );
+
+    let err = var.render_repo_files("-infra", repodir).unwrap_err();
+    println!("{err}");
+    assert!(matches!(err, VariantError::RepoFile(..)));
+    Ok(())
+}
+
+/// Make sure that the error types can be used with `anyhow` and across threads.
+fn assert_thread_safe_error<E: Error + Send + Sync + 'static>() {}
+
+#[test]
+fn test_error_traits() {
+    assert_thread_safe_error::<VariantError>();
+    assert_thread_safe_error::<YAIError>();
+    assert_thread_safe_error::<KeyringError>();
+
+    let err: Box<dyn Error + Send + Sync> = Box::new(VariantError::OsRelease(YAIError::FileRead(
+        IoError::new(ErrorKind::PermissionDenied, "no access"),
+    )));
+    assert_eq!(err.to_string(), "Could not parse the /etc/os-release file");
+    let yai_err = err.source().unwrap();
+    assert!(yai_err.is::<YAIError>());
+    let io_err = yai_err.source().unwrap();
+    assert_eq!(io_err.to_string(), "no access");
+    assert!(io_err.source().is_none());
+
+    let err: Box<dyn Error + Send + Sync> = Box::new(VariantError::FileRead(
+        "CENTOS7".to_owned(),
+        "/etc/redhat-release".to_owned(),
+        IoError::new(ErrorKind::PermissionDenied, "no access"),
+    ));
+    assert!(err.to_string().contains("/etc/redhat-release"));
+    assert!(err.source().unwrap().is::<IoError>());
+
+    let err: Box<dyn Error + Send + Sync> = Box::new(VariantError::UnknownVariant);
+    assert!(err.source().is_none());
+    let any_err = anyhow::Error::from(VariantError::BadVariant("NOPE".to_owned()));
+    assert_eq!(any_err.to_string(), "Unknown variant 'NOPE'");
+}
+
+#[test]
+fn test_prefer_usr_lib() -> Result<(), VariantError> {
+    let tempd = tempfile::tempdir().unwrap();
+    let root = fs::canonicalize(tempd.path()).unwrap();
+    let etc = root.join("etc-os-release");
+    let usr_lib = root.join("usr-lib-os-release");
+    fs::write(&etc, "ID=debian\nVERSION_ID=\"12\"\n").unwrap();
+    fs::write(&usr_lib, "ID=debian\nVERSION_ID=\"11\"\n").unwrap();
+    let candidates = [&etc, &usr_lib];
+    let detect = |config: &DetectConfig| -> Result<_, VariantError> {
+        let (path, data) = crate::read_os_release_from(&candidates, config)?.unwrap();
+        let var = crate::detect_from_os_release(crate::build_variants(), &data, config)?;
+        Ok((path, var.map(|var| var.kind)))
+    };
+
+    for config in [
+        DetectConfig::default(),
+        DetectConfig::default().with_dereference_symlinks(true),
+    ] {
+        assert_eq!(detect(&config)?, (etc.clone(), Some(VariantKind::DEBIAN12)));
+        assert_eq!(
+            detect(&config.with_prefer_usr_lib(true))?,
+            (usr_lib.clone(), Some(VariantKind::DEBIAN11))
+        );
+    }
+
+    // With symlinks resolved, a missing vendor file is not fatal.
+    fs::remove_file(&usr_lib).unwrap();
+    let config = DetectConfig::default()
+        .with_dereference_symlinks(true)
+        .with_prefer_usr_lib(true);
+    assert_eq!(detect(&config)?, (etc, Some(VariantKind::DEBIAN12)));
+
+    // The commands' ${VERSION_ID} values are read from the same file.
+    assert_eq!(
+        crate::os_release_path(&DetectConfig::default())?,
+        Some(PathBuf::from("/etc/os-release"))
+    );
+    assert_eq!(
+        crate::os_release_path(&DetectConfig::default().with_prefer_usr_lib(true))?,
+        Some(PathBuf::from("/usr/lib/os-release"))
+    );
+    Ok(())
+}
+
+/// A reader that takes its time before returning the os-release contents.
+struct SlowReader {
+    delay: Duration,
+    contents: Option<&'static [u8]>,
+}
+
+impl Read for SlowReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        thread::sleep(self.delay);
+        match self.contents.take() {
+            Some(contents) => {
+                buf[..contents.len()].copy_from_slice(contents);
+                Ok(contents.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+#[test]
+fn test_lsb_release_fallback() -> Result<(), VariantError> {
+    let all = crate::build_variants();
+    let config = DetectConfig::default().with_lsb_release(true);
+    let calls = Cell::new(0_u32);
+    let fake_lsb_release = |output: &'static str| {
+        || {
+            calls.set(calls.get() + 1);
+            Ok(output.to_owned())
+        }
+    };
+
+    let mut data = os_release_map(&[("ID", "debian")]);
+    assert!(crate::detect_result_from_os_release(all, &data, &config)?.is_none());
+    assert!(crate::fill_version_id_from_lsb_release(
+        &mut data,
+        fake_lsb_release("12\n")
+    ));
+    assert_eq!(calls.get(), 1);
+    assert_eq!(data["VERSION_ID"], "12");
+    let res = crate::detect_result_from_os_release(all, &data, &config)?.unwrap();
+    assert_eq!(res.variant.kind, VariantKind::DEBIAN12);
+    assert_eq!(res.confidence, Confidence::Exact);
+
+    // A usable VERSION_ID field is left alone, and so is an unknown distribution.
+    for fields in [
+        &[("ID", "debian"), ("VERSION_ID", "11")][..],
+        &[("NAME", "Debian")][..],
+    ] {
+        let mut data = os_release_map(fields);
+        assert!(!crate::fill_version_id_from_lsb_release(
+            &mut data,
+            fake_lsb_release("12\n")
+        ));
+        assert_eq!(data, os_release_map(fields));
+    }
+    assert_eq!(calls.get(), 1);
+
+    // Unusable output or a missing tool do not change anything either.
+    let mut data = os_release_map(&[("ID", "debian"), ("VERSION_ID", "n/a")]);
+    assert!(!crate::fill_version_id_from_lsb_release(
+        &mut data,
+        fake_lsb_release("testing\n")
+    ));
+    assert_eq!(calls.get(), 2);
+    assert_eq!(data["VERSION_ID"], "n/a");
+    assert!(!crate::fill_version_id_from_lsb_release(&mut data, || Err(
+        IoError::from(ErrorKind::NotFound)
+    )));
+    Ok(())
+}
+
+#[test]
+fn test_detect_timeout() -> Result<(), VariantError> {
+    let slow_read = |delay: Duration| {
+        move || {
+            let mut contents = String::new();
+            SlowReader {
+                delay,
+                contents: Some(b"ID=debian\nVERSION_ID=11\n"),
+            }
+            .read_to_string(&mut contents)?;
+            Ok(contents)
+        }
+    };
+    let path = Path::new("/etc/os-release");
+
+    let contents = crate::read_with_timeout(
+        path,
+        Duration::from_secs(10),
+        slow_read(Duration::from_millis(1)),
+    )?
+    .unwrap();
+    assert_eq!(contents, "ID=debian\nVERSION_ID=11\n");
+
+    let err = crate::read_with_timeout(
+        path,
+        Duration::from_millis(50),
+        slow_read(Duration::from_secs(2)),
+    )
+    .unwrap_err();
+    println!("{err}");
+    assert!(matches!(
+        err,
+        VariantError::DetectionTimeout(ref err_path, timeout)
+            if err_path == "/etc/os-release" && timeout == Duration::from_millis(50)
+    ));
+
+    // A file that is read in time is parsed as usual, a missing one is still not fatal.
+    let tempd = tempfile::tempdir().unwrap();
+    let os_release = tempd.path().join("os-release");
+    fs::write(&os_release, "ID=debian\nVERSION_ID=\"12\"\n").unwrap();
+    let config = DetectConfig::default().with_timeout(Some(Duration::from_secs(10)));
+    let (_, data) = crate::read_os_release_from(&[&os_release], &config)?.unwrap();
+    assert_eq!(data["VERSION_ID"], "12");
+    assert!(crate::read_os_release_from(&[tempd.path().join("nonexistent")], &config)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_detect_init_system() {
+    let tempd = tempfile::tempdir().unwrap();
+    let root = tempd.path();
+    assert_eq!(crate::detect_init_system_in(root), InitSystem::Unknown);
+
+    fs::create_dir_all(root.join("sbin")).unwrap();
+    fs::write(root.join("sbin/init"), "").unwrap();
+    assert_eq!(crate::detect_init_system_in(root), InitSystem::SysVinit);
+
+    fs::write(root.join("sbin/initctl"), "").unwrap();
+    assert_eq!(crate::detect_init_system_in(root), InitSystem::Upstart);
+
+    fs::remove_file(root.join("sbin/init")).unwrap();
+    unix_fs::symlink("/lib/systemd/systemd", root.join("sbin/init")).unwrap();
+    assert_eq!(crate::detect_init_system_in(root), InitSystem::Systemd);
+
+    // A booted systemd host, even if /sbin/init points elsewhere.
+    fs::remove_file(root.join("sbin/init")).unwrap();
+    unix_fs::symlink("/sbin/upstart", root.join("sbin/init")).unwrap();
+    assert_eq!(crate::detect_init_system_in(root), InitSystem::Upstart);
+    fs::create_dir_all(root.join("run/systemd/system")).unwrap();
+    assert_eq!(crate::detect_init_system_in(root), InitSystem::Systemd);
+    assert_eq!(InitSystem::Systemd.to_string(), "systemd");
+
+    println!("This host: {init}", init = crate::detect_init_system());
+}
+
+#[test]
+fn test_detect_environment() {
+    let tempd = tempfile::tempdir().unwrap();
+    let root = tempd.path();
+    assert_eq!(
+        crate::detect_environment_in(root),
+        HostEnvironment::default()
+    );
+
+    fs::create_dir_all(root.join("proc/1")).unwrap();
+    fs::write(
+        root.join("proc/version"),
+        "Linux version 6.1.0-18-amd64 (debian-kernel@lists.debian.org) (gcc-12 (Debian 12.2.0-14) 12.2.0) #1 SMP PREEMPT_DYNAMIC Debian 6.1.76-1 (2024-02-01)\n",
+    )
+    .unwrap();
+    fs::write(root.join("proc/1/cgroup"), "0::/init.scope\n").unwrap();
+    assert_eq!(
+        crate::detect_environment_in(root),
+        HostEnvironment::default()
+    );
+
+    fs::write(
+        root.join("proc/version"),
+        "Linux version 5.15.146.1-microsoft-standard-WSL2 (root@65c757a075e2) (gcc (GCC) 11.2.0, GNU ld (GNU Binutils) 2.37) #1 SMP Thu Jan 11 04:09:03 UTC 2024\n",
+    )
+    .unwrap();
+    let env = crate::detect_environment_in(root);
+    assert!(env.wsl);
+    assert!(!env.container);
+
+    fs::write(
+        root.join("proc/version"),
+        "Linux version 4.4.0-19041-Microsoft (Microsoft@Microsoft.com) (gcc version 5.4.0 (GCC) ) #1237-Microsoft Sat Sep 11 14:32:00 PST 2021\n",
+    )
+    .unwrap();
+    assert!(crate::detect_environment_in(root).wsl);
+
+    fs::write(
+        root.join("proc/1/cgroup"),
+        "12:pids:/docker/0123456789abcdef\n11:memory:/docker/0123456789abcdef\n",
+    )
+    .unwrap();
+    assert!(crate::detect_environment_in(root).container);
+
+    fs::write(root.join("proc/1/cgroup"), "0::/\n").unwrap();
+    assert!(!crate::detect_environment_in(root).container);
+    fs::write(root.join(".dockerenv"), "").unwrap();
+    assert!(crate::detect_environment_in(root).container);
+
+    println!("This host: {env:?}", env = crate::detect_environment());
+}
+
+#[test]
+fn test_resolve_os_release() -> Result<(), VariantError> {
+    let tempd = tempfile::tempdir().unwrap();
+    let root = fs::canonicalize(tempd.path()).unwrap();
+    fs::create_dir_all(root.join("etc")).unwrap();
+    fs::create_dir_all(root.join("usr/lib")).unwrap();
+    let etc = root.join("etc/os-release");
+    let usr_lib = root.join("usr/lib/os-release");
+    let candidates = [&etc, &usr_lib];
+
+    assert_eq!(crate::resolve_os_release(&candidates)?, None);
+
+    // A dangling symlink is skipped.
+    unix_fs::symlink("../usr/lib/os-release", &etc).unwrap();
+    assert_eq!(crate::resolve_os_release(&candidates)?, None);
+    fs::write(root.join("usr/lib/other-release"), "ID=debian\n").unwrap();
+    let second = [
+        root.join("etc/nonexistent"),
+        root.join("usr/lib/other-release"),
+    ];
+    assert_eq!(
+        crate::resolve_os_release(&second)?,
+        Some(root.join("usr/lib/other-release"))
+    );
+
+    // The symlink is resolved to the file that is actually read.
+    fs::write(&usr_lib, "ID=debian\nVERSION_ID=\"12\"\n").unwrap();
+    let resolved = crate::resolve_os_release(&candidates)?.unwrap();
+    assert_eq!(resolved, usr_lib);
+    let data = crate::yai::parse(&resolved).unwrap();
+    let var =
+        crate::detect_from_os_release(crate::build_variants(), &data, &DetectConfig::default())?;
+    assert_eq!(var.map(|var| var.kind), Some(VariantKind::DEBIAN12));
+    Ok(())
+}
+
+#[test]
+fn test_util_detect_os_release() -> Result<(), VariantError> {
+    let res = test_util::detect_os_release(&[
+        ("ID", "debian"),
+        ("VERSION_ID", "11"),
+        ("VERSION_CODENAME", "bullseye"),
+    ])?
+    .expect("Debian 11 was not detected");
+    assert_eq!(res.variant.kind, VariantKind::DEBIAN11);
+    assert_eq!(res.confidence, Confidence::Exact);
+
+    assert!(test_util::detect_os_release(&[("ID", "debian"), ("VERSION_ID", "4")])?.is_none());
+
+    let res = test_util::detect_os_release_with(
+        crate::build_variants(),
+        &[("ID", "debian"), ("VERSION_ID", "11.7")],
+        &DetectConfig::default().with_lenient(true),
+    )?
+    .expect("Debian 11.7 was not detected");
+    assert_eq!(res.variant.kind, VariantKind::DEBIAN11);
+    assert_eq!(res.confidence, Confidence::Lenient);
+    Ok(())
+}
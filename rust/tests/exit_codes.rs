@@ -0,0 +1,79 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Run the `storpool_variant` tool, check its exit codes.
+
+#![allow(clippy::print_stdout)]
+#![allow(clippy::unwrap_used)]
+
+use std::process::{Command, Output};
+
+/// Run the tool with a fixed build variant, return its output and exit status.
+fn run_tool(args: &[&str]) -> Output {
+    run_tool_as(args, "DEBIAN12")
+}
+
+/// Run the tool with the specified build variant, return its output and exit status.
+fn run_tool_as(args: &[&str], variant: &str) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_storpool_variant"))
+        .args(args)
+        .env("SP_VARIANT_OVERRIDE", variant)
+        .output()
+        .unwrap();
+    println!("{args:?}: {output:?}");
+    output
+}
+
+#[test]
+fn test_exit_codes() {
+    for (args, expected) in [
+        (&["detect"][..], 0),
+        (&["detect", "--check"][..], 0),
+        // Without the JSON support, there is no `show` subcommand at all.
+        (
+            &["show", "NOPE"][..],
+            if cfg!(feature = "json") { 1 } else { 2 },
+        ),
+        (&["detect", "--bogus"][..], 2),
+        (&["nonexistent-subcommand"][..], 2),
+        (
+            &["repo", "add", "-N", "-d", "/nonexistent/sp-variant/repo"][..],
+            4,
+        ),
+    ] {
+        assert_eq!(run_tool(args).status.code(), Some(expected), "{args:?}");
+    }
+}
+
+#[test]
+fn test_detect_output() {
+    let output = run_tool(&["detect"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"DEBIAN12\n");
+
+    let output = run_tool(&["detect", "--check"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_detect_quiet() {
+    // Without --quiet, the override itself is reported on the standard error stream.
+    let output = run_tool(&["detect", "--check"]);
+    assert!(!output.stderr.is_empty());
+
+    let output = run_tool(&["detect", "--quiet"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = run_tool_as(&["detect", "-q"], "NONEXISTENT");
+    assert_ne!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = run_tool_as(&["detect", "--check"], "NONEXISTENT");
+    assert_ne!(output.status.code(), Some(0));
+    assert!(!output.stderr.is_empty());
+}
@@ -0,0 +1,60 @@
+/*
+ * SPDX-FileCopyrightText: 2024  StorPool <support@storpool.com>
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+//! Make sure that the library and the tool still build without the JSON support.
+
+#![allow(clippy::print_stdout)]
+#![allow(clippy::unwrap_used)]
+
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+/// Ask the Rust compiler that cargo will use for the triple of the host it runs on.
+fn host_triple() -> String {
+    let output = Command::new(env::var_os("RUSTC").unwrap_or_else(|| OsString::from("rustc")))
+        .arg("-vV")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .unwrap()
+        .to_owned()
+}
+
+#[test]
+fn test_build_without_json() {
+    // Use a separate target directory so as not to wait for the lock held by
+    // the `cargo test` run that started us.
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("minimal-build");
+    // All the dependencies needed for the minimal build have already been
+    // fetched for this one.
+    // Build for the host explicitly: the default target set in the cargo
+    // configuration may not be installed.
+    let output = Command::new(env!("CARGO"))
+        .args([
+            "check",
+            "--offline",
+            "--no-default-features",
+            "--lib",
+            "--bins",
+            "--manifest-path",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+            "--target",
+            &host_triple(),
+        ])
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .output()
+        .unwrap();
+    println!("{output:?}");
+    assert!(
+        output.status.success(),
+        "{stderr}",
+        stderr = String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -36,10 +36,22 @@ pub enum YAIError {
     #[error("Mismatched open/close quotes in the {0:?} os-release line")]
     MismatchedQuotes(String),
 
+    /// A value contains a control character other than a tab.
+    #[error("The value in the {0:?} os-release line contains a control character")]
+    ControlCharInValue(String),
+
     /// Could not read the /etc/os-release file.
     #[error("Could not read the /etc/os-release file")]
     FileRead(#[source] IoError),
 
+    /// Some of the required keys are not defined in the file.
+    #[error("Missing required os-release keys: {}", .0.join(", "))]
+    MissingKeys(Vec<String>),
+
+    /// A problem with a specific line in the file, numbered from 1.
+    #[error("os-release line {0}: {1}")]
+    AtLine(usize, Box<YAIError>),
+
     /// An internal error occurred
     #[error("YAI parser internal error: {0}")]
     Internal(String),
@@ -61,6 +73,47 @@ const RE_LINE: &str = "(?x)
     ) $
 ";
 
+/// The characters that a backslash escapes within a double-quoted value.
+///
+/// As in systemd's os-release parser, a backslash before any other character
+/// is taken literally, and no `$` or backtick expansion is ever performed.
+const DOUBLE_QUOTED_ESCAPES: &str = "\"\\`$";
+
+/// Remove the escaping backslashes from a double-quoted or unquoted value.
+fn unescape(line: &str, value: &str, double_quoted: bool) -> Result<String, YAIError> {
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => match chars.next() {
+                Some(next) if !double_quoted || DOUBLE_QUOTED_ESCAPES.contains(next) => {
+                    res.push(next);
+                }
+                Some(next) => {
+                    res.push(chr);
+                    res.push(next);
+                }
+                None => return Err(YAIError::BackslashAtEnd(line.to_owned())),
+            },
+            '"' if double_quoted => return Err(YAIError::QuoteInQuoted(line.to_owned())),
+            other => res.push(other),
+        }
+    }
+    Ok(res)
+}
+
+/// Make sure a value does not contain any control characters except for tabs.
+fn check_value(
+    line: &str,
+    varname: &str,
+    value: String,
+) -> Result<Option<(String, String)>, YAIError> {
+    if value.chars().any(|chr| chr.is_control() && chr != '\t') {
+        return Err(YAIError::ControlCharInValue(line.to_owned()));
+    }
+    Ok(Some((varname.to_owned(), value)))
+}
+
 fn parse_line(line: &str) -> Result<Option<(String, String)>, YAIError> {
     static RE: Lazy<Result<Regex, RegexError>> = Lazy::new(|| Regex::new(RE_LINE));
     match RE
@@ -91,56 +144,107 @@ fn parse_line(line: &str) -> Result<Option<(String, String)>, YAIError> {
                 if q_close != q_open {
                     return Err(YAIError::MismatchedQuotes(line.to_owned()));
                 }
-                return Ok(Some((varname.to_owned(), quoted_top.to_owned())));
+                return check_value(line, varname, quoted_top.to_owned());
             }
 
-            let quoted = match q_open {
+            let value = match q_open {
                 Some("\"") => {
                     if q_close != q_open {
                         return Err(YAIError::MismatchedQuotes(line.to_owned()));
                     }
-                    quoted_top
+                    unescape(line, quoted_top, true)?
                 }
                 Some(other) => {
                     return Err(YAIError::Internal(format!(
                         "YAI parse_line: {line:?}: q_open {other:?}"
                     )))
                 }
-                None => cap("full")?,
-            }
-            .to_owned();
-            match quoted
-                .chars()
-                .fold((false, String::new()), |(escaped, mut acc), chr| {
-                    if escaped || chr != '\\' {
-                        acc.push(chr);
-                        (false, acc)
-                    } else {
-                        (true, acc)
-                    }
-                }) {
-                (false, res) => Ok(Some((varname.to_owned(), res))),
-                (true, _) => Err(YAIError::BackslashAtEnd(line.to_owned())),
-            }
+                None => unescape(line, cap("full")?, false)?,
+            };
+            check_value(line, varname, value)
         }
         None => Err(YAIError::BadLine(line.to_owned())),
     }
 }
 
+/// Options that control the parsing of an os-release file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    /// The keys that must be defined in the file, e.g. "ID" and "VERSION_ID".
+    pub required_keys: Vec<String>,
+}
+
+impl ParseOptions {
+    /// Require the specified keys to be defined in the file.
+    #[inline]
+    #[must_use]
+    pub fn with_required_keys(self, required_keys: &[&str]) -> Self {
+        Self {
+            required_keys: required_keys.iter().map(|&key| key.to_owned()).collect(),
+        }
+    }
+}
+
 /// Parse a file, return a name: value mapping.
 ///
+/// A leading UTF-8 byte order mark is skipped and the lines may end in
+/// either LF or CRLF, so that files edited on other systems are parsed
+/// the same way as the usual LF-terminated ones.
+///
 /// # Errors
 /// - I/O or text decoding errors from reading the file
-/// - [`YAIError`] parse errors from examining the INI-file structure
-#[allow(clippy::missing_inline_in_public_items)]
+/// - [`YAIError`] parse errors from examining the INI-file structure,
+///   wrapped in [`YAIError::AtLine`] to report the number of the offending line
+#[inline]
 pub fn parse<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>, YAIError> {
-    fs::read_to_string(path)
-        .map_err(YAIError::FileRead)?
+    parse_str(&fs::read_to_string(path).map_err(YAIError::FileRead)?)
+}
+
+/// Parse the contents of an os-release file that has already been read.
+///
+/// # Errors
+/// The same parse errors as for [`parse()`].
+#[allow(clippy::missing_inline_in_public_items)]
+pub fn parse_str(contents: &str) -> Result<HashMap<String, String>, YAIError> {
+    contents
+        .strip_prefix('\u{feff}')
+        .unwrap_or(contents)
         .lines()
-        .filter_map(|line| parse_line(line).transpose())
+        .zip(1_usize..)
+        .filter_map(|(line, line_no)| {
+            parse_line(line)
+                .map_err(|err| YAIError::AtLine(line_no, Box::new(err)))
+                .transpose()
+        })
         .collect()
 }
 
+/// Parse a file, then make sure that all the required keys are defined.
+///
+/// # Errors
+/// - the same as for [`parse()`]
+/// - [`YAIError::MissingKeys`] listing all the required keys that are not
+///   defined, in the order specified in the options
+#[allow(clippy::missing_inline_in_public_items)]
+pub fn parse_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<HashMap<String, String>, YAIError> {
+    let res = parse(path)?;
+    let missing: Vec<String> = options
+        .required_keys
+        .iter()
+        .filter(|key| !res.contains_key(*key))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        Ok(res)
+    } else {
+        Err(YAIError::MissingKeys(missing))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::print_stdout)]
@@ -149,18 +253,23 @@ mod tests {
     use std::error::Error;
     use std::fs;
 
-    const LINES_BAD: [&str; 5] = [
+    const LINES_BAD: [&str; 9] = [
         "NAME='",
         "NAME=\"foo'",
         "FOO BAR=baz",
         "FOO=bar\\",
         "FOO=\"meow\\\"",
+        "NAME=\"foo\0bar\"",
+        "NAME='foo\0bar'",
+        "NAME=foo\x1b[0m",
+        "NAME=\"foo\\\x7f\"",
     ];
 
     const LINES_COMMENTS: [&str; 4] = ["", "   \t  ", "  \t  # something", "#"];
 
-    const LINES_OK: [(&str, (&str, &str)); 5] = [
+    const LINES_OK: [(&str, (&str, &str)); 6] = [
         ("ID=centos", ("ID", "centos")),
+        ("NAME='tab\tseparated'", ("NAME", "tab\tseparated")),
         ("ID='centos'", ("ID", "centos")),
         (
             "NAME='something long \"and weird'",
@@ -176,6 +285,27 @@ mod tests {
         ),
     ];
 
+    /// Cases derived from the quoting rules in systemd's os-release(5) manual page
+    /// and the behavior of its env-file parser: no `$` or backtick expansion,
+    /// and a backslash in a double-quoted value only escapes `"`, `\`, `` ` ``, and `$`.
+    const LINES_SYSTEMD: [(&str, &str); 12] = [
+        (r#"NAME="$HOME""#, "$HOME"),
+        (r#"NAME="\$HOME""#, "$HOME"),
+        (r#"NAME="${HOME}/x""#, "${HOME}/x"),
+        (r#"NAME="`uname -r`""#, "`uname -r`"),
+        (r#"NAME="\`uname -r\`""#, "`uname -r`"),
+        (r#"NAME="C:\Windows""#, r"C:\Windows"),
+        (r#"NAME="a\\b""#, r"a\b"),
+        (r#"NAME="tab\tnot expanded""#, r"tab\tnot expanded"),
+        (r#"NAME="it's""#, "it's"),
+        (r"NAME='$HOME `id` \n'", r"$HOME `id` \n"),
+        (r"NAME=$HOME", "$HOME"),
+        (r"NAME=\$HOME\`id\`", "$HOME`id`"),
+    ];
+
+    /// Lines that systemd would parse differently, e.g. as a concatenation.
+    const LINES_SYSTEMD_BAD: [&str; 2] = [r#"NAME="foo"bar""#, r#"NAME="foo" "bar""#];
+
     const CFG_TEXT: &str = "PRETTY_NAME=\"Debian GNU/Linux 11 (bullseye)\"
 NAME=\"Debian GNU/Linux\"
 VERSION_ID=\"11\"
@@ -202,6 +332,14 @@ BUG_REPORT_URL=\"https://bugs.debian.org/\"";
         }
     }
 
+    #[test]
+    fn parse_control_char() {
+        println!("\nMaking sure control characters in values are rejected");
+        let err = super::parse_line("NAME=\"Debian\0GNU/Linux\"").unwrap_err();
+        println!("- {err}");
+        assert!(matches!(err, super::YAIError::ControlCharInValue(_)));
+    }
+
     #[test]
     fn parse_comments() {
         println!("\nMaking sure comments and empty lines are ignored");
@@ -225,6 +363,23 @@ BUG_REPORT_URL=\"https://bugs.debian.org/\"";
         }
     }
 
+    #[test]
+    fn parse_systemd_quoting() {
+        println!("\nMaking sure values are unquoted as systemd does it");
+        for (line, value) in &LINES_SYSTEMD {
+            println!("- {line:?}");
+            let (_, p_value) = super::parse_line(line).unwrap().unwrap();
+            println!("  - value {p_value:?}");
+            assert_eq!(value, &p_value);
+        }
+        for line in &LINES_SYSTEMD_BAD {
+            println!("- {line:?}");
+            let err = super::parse_line(line).unwrap_err();
+            println!("  - {err}");
+            assert!(matches!(err, super::YAIError::QuoteInQuoted(_)));
+        }
+    }
+
     #[test]
     fn parse() -> Result<(), Box<dyn Error>> {
         let dir = tempfile::tempdir()?;
@@ -237,7 +392,7 @@ BUG_REPORT_URL=\"https://bugs.debian.org/\"";
         let res = super::parse(&path)?;
         assert_eq!(res.len(), 9);
         for (name, value) in &CFG_EXPECTED {
-            let pvalue = res.get(&name.to_string());
+            let pvalue = res.get(*name);
             println!("- {name:?}: expected {value:?}, got {pvalue:?}");
             match value {
                 Some(value) => match pvalue {
@@ -249,4 +404,80 @@ BUG_REPORT_URL=\"https://bugs.debian.org/\"";
         }
         Ok(())
     }
+
+    #[test]
+    fn parse_line_number() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("os-release");
+        let mut lines: Vec<&str> = CFG_TEXT.lines().collect();
+        lines.insert(6, "FOO BAR=baz");
+        fs::write(&path, lines.join("\n"))?;
+        let err = super::parse(&path).unwrap_err();
+        println!("{err}");
+        assert_eq!(
+            err.to_string(),
+            "os-release line 7: Unexpected os-release line \"FOO BAR=baz\""
+        );
+        match err {
+            super::YAIError::AtLine(line_no, inner) => {
+                assert_eq!(line_no, 7);
+                assert!(matches!(*inner, super::YAIError::BadLine(_)));
+            }
+            other => panic!("Unexpected error {other:?}"),
+        }
+
+        // The byte order mark does not affect the numbering.
+        fs::write(&path, format!("\u{feff}{text}", text = lines.join("\r\n")))?;
+        let err = super::parse(&path).unwrap_err();
+        assert!(err.to_string().starts_with("os-release line 7: "));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_required_keys() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("os-release");
+        fs::write(&path, CFG_TEXT.as_bytes())?;
+        let options = super::ParseOptions::default().with_required_keys(&["ID", "VERSION_ID"]);
+        assert_eq!(
+            super::parse_with_options(&path, &options)?,
+            super::parse(&path)?
+        );
+
+        let lines: Vec<&str> = CFG_TEXT
+            .lines()
+            .filter(|line| !line.starts_with("ID="))
+            .collect();
+        fs::write(&path, lines.join("\n"))?;
+        super::parse(&path)?;
+        let err = super::parse_with_options(&path, &options).unwrap_err();
+        println!("{err}");
+        assert_eq!(err.to_string(), "Missing required os-release keys: ID");
+        assert!(matches!(err, super::YAIError::MissingKeys(ref keys) if keys == &["ID"]));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bom_crlf() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("os-release");
+        fs::write(&path, CFG_TEXT.as_bytes())?;
+        let expected = super::parse(&path)?;
+
+        let variants = [
+            ("BOM", format!("\u{feff}{CFG_TEXT}")),
+            ("CRLF", CFG_TEXT.replace('\n', "\r\n")),
+            (
+                "BOM and CRLF",
+                format!("\u{feff}{text}\r\n", text = CFG_TEXT.replace('\n', "\r\n")),
+            ),
+        ];
+        for (tag, contents) in &variants {
+            println!("\nParsing an os-release file with {tag}");
+            fs::write(&path, contents.as_bytes())?;
+            let res = super::parse(&path)?;
+            assert_eq!(res, expected);
+        }
+        Ok(())
+    }
 }
@@ -32,6 +32,12 @@ detect_from_os_release()
 	if [ -z "$os_id" ] || [ -z "$version_id" ]; then
 		return
 	fi
+	# Only dot-separated decimal numbers are matched against the variant definitions.
+	case "$version_id" in
+		*[!0123456789.]*|.*|*.|*..*)
+			return
+			;;
+	esac
 	
 	if [ "$os_id" = 'almalinux' ] && printf -- '%s\n' "$version_id" | grep -Eqe '^8($|\.[4-9]|\.[1-9][0-9])'; then
 		printf -- '%s\n' 'ALMA8'
@@ -43,6 +49,11 @@ detect_from_os_release()
 		return
 	fi
 	
+	if [ "$os_id" = 'amzn' ] && printf -- '%s\n' "$version_id" | grep -Eqe '^2$'; then
+		printf -- '%s\n' 'AMZN2'
+		return
+	fi
+	
 	if [ "$os_id" = 'centos' ] && printf -- '%s\n' "$version_id" | grep -Eqe '^7($|\.[0-9])'; then
 		printf -- '%s\n' 'CENTOS7'
 		return
@@ -78,6 +89,11 @@ detect_from_os_release()
 		return
 	fi
 	
+	if [ "$os_id" = 'fedora' ] && printf -- '%s\n' "$version_id" | grep -Eqe '^38$'; then
+		printf -- '%s\n' 'FEDORA38'
+		return
+	fi
+	
 	if [ "$os_id" = 'ol' ] && printf -- '%s\n' "$version_id" | grep -Eqe '^7($|\.[0-9])'; then
 		printf -- '%s\n' 'ORACLE7'
 		return
@@ -146,6 +162,16 @@ cmd_detect()
 	fi
 
 	
+	if [ -r '/etc/system-release' ] && grep -Eqe '^Amazon[[:space:]]+Linux[[:space:]]+release[[:space:]]+2[[:space:]]' -- '/etc/system-release'; then
+		printf -- '%s\n' 'AMZN2'
+		return
+	fi
+	
+	if [ -r '/etc/fedora-release' ] && grep -Eqe '^Fedora[[:space:]]+release[[:space:]]+38[[:space:]]' -- '/etc/fedora-release'; then
+		printf -- '%s\n' 'FEDORA38'
+		return
+	fi
+	
 	if [ -r '/etc/redhat-release' ] && grep -Eqe '^Rocky[[:space:]]+Linux[[:space:]].*[[:space:]]8\.([4-9]|[1-9][0-9])' -- '/etc/redhat-release'; then
 		printf -- '%s\n' 'ROCKY8'
 		return
@@ -348,7 +374,10 @@ show_ALMA8()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -454,7 +483,116 @@ show_ALMA9()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
+}
+EOVARIANT_JSON
+}
+
+show_AMZN2()
+{
+	cat <<'EOVARIANT_JSON'
+  {
+  "builder": {
+    "alias": "amzn2",
+    "base_image": "amazonlinux:2",
+    "branch": "",
+    "kernel_package": "kernel",
+    "utf8_locale": "en_US.utf8"
+  },
+  "commands": {
+    "package": {
+      "install": [
+        "yum",
+        "--disablerepo=*",
+        "--enablerepo=amzn2-core",
+        "--enablerepo=storpool-contrib",
+        "install",
+        "-q",
+        "-y"
+      ],
+      "list_all": [
+        "rpm",
+        "-qa",
+        "--qf",
+        "%{Name}\\t%{EVR}\\t%{Arch}\\tii\\n",
+        "--"
+      ],
+      "purge": [
+        "yum",
+        "remove",
+        "-q",
+        "-y",
+        "--"
+      ],
+      "remove": [
+        "yum",
+        "remove",
+        "-q",
+        "-y",
+        "--"
+      ],
+      "remove_impl": [
+        "rpm",
+        "-e",
+        "--"
+      ],
+      "update_db": [
+        "true"
+      ]
+    },
+    "pkgfile": {
+      "dep_query": [
+        "sh",
+        "-c",
+        "rpm -qpR -- \"$pkg\""
+      ],
+      "install": [
+        "sh",
+        "-c",
+        "\nunset to_install to_reinstall\nfor f in $packages; do\n    package=\"$(rpm -qp \"$f\")\"\n    if rpm -q -- \"$package\"; then\n        to_reinstall=\"$to_reinstall ./$f\"\n    else\n        to_install=\"$to_install ./$f\"\n    fi\ndone\n\nif [ -n \"$to_install\" ]; then\n    yum install -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install\nfi\nif [ -n \"$to_reinstall\" ]; then\n    yum reinstall -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall\nfi\n"
+      ]
+    }
+  },
+  "descr": "Amazon Linux 2",
+  "detect": {
+    "filename": "/etc/system-release",
+    "os_id": "amzn",
+    "os_version_regex": "^2$",
+    "regex": "^ Amazon \\s+ Linux \\s+ release \\s+ 2 \\s"
+  },
+  "family": "redhat",
+  "file_ext": "rpm",
+  "initramfs_flavor": "mkinitrd",
+  "min_sys_python": "3.6",
+  "name": "AMZN2",
+  "package": {
+    "KMOD": "kmod",
+    "LIBCGROUP": "libcgroup-tools",
+    "LIBUDEV": "systemd-libs",
+    "OPENSSL": "openssl-libs",
+    "PERL_AUTODIE": "perl-autodie",
+    "PERL_FILE_PATH": "perl-File-Path",
+    "PERL_LWP_PROTO_HTTPS": "perl-LWP-Protocol-https",
+    "PERL_SYS_SYSLOG": "perl-Sys-Syslog",
+    "PROCPS": "procps-ng",
+    "PYTHON_SIMPLEJSON": "python2-simplejson",
+    "UDEV": "systemd"
+  },
+  "parent": "CENTOS7",
+  "repo": {
+    "keyring": "redhat/repo/RPM-GPG-KEY-StorPool",
+    "yumdef": "redhat/repo/storpool-amzn.repo"
+  },
+  "supported": {
+    "repo": true
+  },
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -519,6 +657,8 @@ show_CENTOS7()
         "rpm -qpR -- \"$pkg\""
       ],
       "install": [
+        "sh",
+        "-c",
         "\nunset to_install to_reinstall\nfor f in $packages; do\n    package=\"$(rpm -qp \"$f\")\"\n    if rpm -q -- \"$package\"; then\n        to_reinstall=\"$to_reinstall ./$f\"\n    else\n        to_install=\"$to_install ./$f\"\n    fi\ndone\n\nif [ -n \"$to_install\" ]; then\n    yum install -y --disablerepo='*' --enablerepo=base,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install\nfi\nif [ -n \"$to_reinstall\" ]; then\n    yum reinstall -y --disablerepo='*' --enablerepo=base,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall\nfi\n"
       ]
     }
@@ -556,7 +696,10 @@ show_CENTOS7()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -662,7 +805,10 @@ show_CENTOS8()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -768,7 +914,10 @@ show_CENTOS9()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -882,7 +1031,10 @@ show_DEBIAN10()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -996,7 +1148,10 @@ show_DEBIAN11()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1110,7 +1265,10 @@ show_DEBIAN12()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1224,7 +1382,118 @@ show_DEBIAN13()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
+}
+EOVARIANT_JSON
+}
+
+show_FEDORA38()
+{
+	cat <<'EOVARIANT_JSON'
+  {
+  "builder": {
+    "alias": "fedora38",
+    "base_image": "fedora:38",
+    "branch": "",
+    "kernel_package": "kernel-core",
+    "utf8_locale": "C.UTF-8"
+  },
+  "commands": {
+    "package": {
+      "install": [
+        "dnf",
+        "--disablerepo=*",
+        "--enablerepo=fedora",
+        "--enablerepo=updates",
+        "--enablerepo=storpool-contrib",
+        "install",
+        "-q",
+        "-y",
+        "--"
+      ],
+      "list_all": [
+        "rpm",
+        "-qa",
+        "--qf",
+        "%{Name}\\t%{EVR}\\t%{Arch}\\tii\\n",
+        "--"
+      ],
+      "purge": [
+        "dnf",
+        "remove",
+        "-q",
+        "-y",
+        "--"
+      ],
+      "remove": [
+        "dnf",
+        "remove",
+        "-q",
+        "-y",
+        "--"
+      ],
+      "remove_impl": [
+        "rpm",
+        "-e",
+        "--"
+      ],
+      "update_db": [
+        "true"
+      ]
+    },
+    "pkgfile": {
+      "dep_query": [
+        "sh",
+        "-c",
+        "rpm -qpR -- \"$pkg\""
+      ],
+      "install": [
+        "sh",
+        "-c",
+        "\nunset to_install to_reinstall\nfor f in $packages; do\n    package=\"$(rpm -qp \"$f\")\"\n    if rpm -q -- \"$package\"; then\n        to_reinstall=\"$to_reinstall ./$f\"\n    else\n        to_install=\"$to_install ./$f\"\n    fi\ndone\n\nif [ -n \"$to_install\" ]; then\n    dnf install -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install\nfi\nif [ -n \"$to_reinstall\" ]; then\n    dnf reinstall -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall\nfi\n"
+      ]
+    }
+  },
+  "descr": "Fedora 38",
+  "detect": {
+    "filename": "/etc/fedora-release",
+    "os_id": "fedora",
+    "os_version_regex": "^38$",
+    "regex": "^ Fedora \\s+ release \\s+ 38 \\s"
+  },
+  "family": "redhat",
+  "file_ext": "rpm",
+  "initramfs_flavor": "mkinitrd",
+  "min_sys_python": "3.11",
+  "name": "FEDORA38",
+  "package": {
+    "KMOD": "kmod",
+    "LIBCGROUP": "bash",
+    "LIBUDEV": "systemd-libs",
+    "OPENSSL": "openssl-libs",
+    "PERL_AUTODIE": "perl-autodie",
+    "PERL_FILE_PATH": "perl-File-Path",
+    "PERL_LWP_PROTO_HTTPS": "perl-LWP-Protocol-https",
+    "PERL_SYS_SYSLOG": "perl-Sys-Syslog",
+    "PROCPS": "procps-ng",
+    "PYTHON_SIMPLEJSON": "bash",
+    "UDEV": "systemd"
+  },
+  "parent": "ALMA9",
+  "repo": {
+    "keyring": "redhat/repo/RPM-GPG-KEY-StorPool",
+    "yumdef": "redhat/repo/storpool-fedora.repo"
+  },
+  "supported": {
+    "repo": false
+  },
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1289,6 +1558,8 @@ show_ORACLE7()
         "rpm -qpR -- \"$pkg\""
       ],
       "install": [
+        "sh",
+        "-c",
         "\nunset to_install to_reinstall\nfor f in $packages; do\n    package=\"$(rpm -qp \"$f\")\"\n    if rpm -q -- \"$package\"; then\n        to_reinstall=\"$to_reinstall ./$f\"\n    else\n        to_install=\"$to_install ./$f\"\n    fi\ndone\n\nif [ -n \"$to_install\" ]; then\n    yum install -y --disablerepo='*' --enablerepo=base,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install\nfi\nif [ -n \"$to_reinstall\" ]; then\n    yum reinstall -y --disablerepo='*' --enablerepo=base,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall\nfi\n"
       ]
     }
@@ -1326,7 +1597,10 @@ show_ORACLE7()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1432,7 +1706,10 @@ show_ORACLE8()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1538,7 +1815,10 @@ show_ORACLE9()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1644,7 +1924,10 @@ show_RHEL8()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1750,7 +2033,10 @@ show_ROCKY8()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1856,7 +2142,10 @@ show_ROCKY9()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "usr/lib/systemd/system"
+  "systemd_lib": "usr/lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -1970,7 +2259,10 @@ show_UBUNTU1804()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -2084,7 +2376,10 @@ show_UBUNTU2004()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -2198,7 +2493,10 @@ show_UBUNTU2204()
   "supported": {
     "repo": true
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -2312,7 +2610,10 @@ show_UBUNTU2404()
   "supported": {
     "repo": false
   },
-  "systemd_lib": "lib/systemd/system"
+  "systemd_lib": "lib/systemd/system",
+  "templated_commands": [
+    "pkgfile.install"
+  ]
 }
 EOVARIANT_JSON
 }
@@ -2330,10 +2631,12 @@ cmd_show_all()
   "format": {
     "version": {
       "major": 1,
-      "minor": 4
+      "minor": 5
     }
   },
   "order": [
+    "AMZN2",
+    "FEDORA38",
     "ROCKY8",
     "ROCKY9",
     "RHEL8",
@@ -2364,6 +2667,9 @@ EOPROLOGUE
   printf -- '    "%s": ' 'ALMA9'
   show_ALMA9
   echo ','
+  printf -- '    "%s": ' 'AMZN2'
+  show_AMZN2
+  echo ','
   printf -- '    "%s": ' 'CENTOS7'
   show_CENTOS7
   echo ','
@@ -2385,6 +2691,9 @@ EOPROLOGUE
   printf -- '    "%s": ' 'DEBIAN13'
   show_DEBIAN13
   echo ','
+  printf -- '    "%s": ' 'FEDORA38'
+  show_FEDORA38
+  echo ','
   printf -- '    "%s": ' 'ORACLE7'
   show_ORACLE7
   echo ','
@@ -2432,7 +2741,7 @@ show_variant()
   "format": {
     "version": {
       "major": 1,
-      "minor": 4
+      "minor": 5
     }
   },
   "variant":
@@ -2670,6 +2979,104 @@ fi
 			esac
 			;;
 		
+		AMZN2)
+			case "$cmd_cat" in
+				
+				package)
+					case "$cmd_item" in
+						
+						install)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'yum' '--disablerepo=*' '--enablerepo=amzn2-core' '--enablerepo=storpool-contrib' 'install' '-q' '-y'  "$@"
+							;;
+						
+						list_all)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'rpm' '-qa' '--qf' '%{Name}\t%{EVR}\t%{Arch}\tii\n' '--'  "$@"
+							;;
+						
+						purge)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'yum' 'remove' '-q' '-y' '--'  "$@"
+							;;
+						
+						remove)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'yum' 'remove' '-q' '-y' '--'  "$@"
+							;;
+						
+						remove_impl)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'rpm' '-e' '--'  "$@"
+							;;
+						
+						update_db)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'true'  "$@"
+							;;
+						
+
+						*)
+							echo "Invalid command '$cmd_item' in the '$cmd_cat' category" 1>&2
+							exit 1
+							;;
+					esac
+					;;
+				
+				pkgfile)
+					case "$cmd_item" in
+						
+						dep_query)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'sh' '-c' 'rpm -qpR -- "$pkg"'  "$@"
+							;;
+						
+						install)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'sh' '-c' '
+unset to_install to_reinstall
+for f in $packages; do
+    package="$(rpm -qp "$f")"
+    if rpm -q -- "$package"; then
+        to_reinstall="$to_reinstall ./$f"
+    else
+        to_install="$to_install ./$f"
+    fi
+done
+
+if [ -n "$to_install" ]; then
+    yum install -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install
+fi
+if [ -n "$to_reinstall" ]; then
+    yum reinstall -y --disablerepo='*' --enablerepo=amzn2-core,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall
+fi
+'  "$@"
+							;;
+						
+
+						*)
+							echo "Invalid command '$cmd_item' in the '$cmd_cat' category" 1>&2
+							exit 1
+							;;
+					esac
+					;;
+				
+
+				*)
+					echo "Invalid command category '$cmd_cat'" 1>&2
+					exit 1
+					;;
+			esac
+			;;
+		
 		CENTOS7)
 			case "$cmd_cat" in
 				
@@ -2732,7 +3139,7 @@ fi
 						install)
 							# The commands are quoted exactly as much as necessary.
 							# shellcheck disable=SC2016
-							$noop '
+							$noop 'sh' '-c' '
 unset to_install to_reinstall
 for f in $packages; do
     package="$(rpm -qp "$f")"
@@ -3288,6 +3695,104 @@ fi
 			esac
 			;;
 		
+		FEDORA38)
+			case "$cmd_cat" in
+				
+				package)
+					case "$cmd_item" in
+						
+						install)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'dnf' '--disablerepo=*' '--enablerepo=fedora' '--enablerepo=updates' '--enablerepo=storpool-contrib' 'install' '-q' '-y' '--'  "$@"
+							;;
+						
+						list_all)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'rpm' '-qa' '--qf' '%{Name}\t%{EVR}\t%{Arch}\tii\n' '--'  "$@"
+							;;
+						
+						purge)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'dnf' 'remove' '-q' '-y' '--'  "$@"
+							;;
+						
+						remove)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'dnf' 'remove' '-q' '-y' '--'  "$@"
+							;;
+						
+						remove_impl)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'rpm' '-e' '--'  "$@"
+							;;
+						
+						update_db)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'true'  "$@"
+							;;
+						
+
+						*)
+							echo "Invalid command '$cmd_item' in the '$cmd_cat' category" 1>&2
+							exit 1
+							;;
+					esac
+					;;
+				
+				pkgfile)
+					case "$cmd_item" in
+						
+						dep_query)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'sh' '-c' 'rpm -qpR -- "$pkg"'  "$@"
+							;;
+						
+						install)
+							# The commands are quoted exactly as much as necessary.
+							# shellcheck disable=SC2016
+							$noop 'sh' '-c' '
+unset to_install to_reinstall
+for f in $packages; do
+    package="$(rpm -qp "$f")"
+    if rpm -q -- "$package"; then
+        to_reinstall="$to_reinstall ./$f"
+    else
+        to_install="$to_install ./$f"
+    fi
+done
+
+if [ -n "$to_install" ]; then
+    dnf install -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_install
+fi
+if [ -n "$to_reinstall" ]; then
+    dnf reinstall -y --disablerepo='*' --enablerepo=fedora,updates,storpool-contrib --setopt=localpkg_gpgcheck=0 -- $to_reinstall
+fi
+'  "$@"
+							;;
+						
+
+						*)
+							echo "Invalid command '$cmd_item' in the '$cmd_cat' category" 1>&2
+							exit 1
+							;;
+					esac
+					;;
+				
+
+				*)
+					echo "Invalid command category '$cmd_cat'" 1>&2
+					exit 1
+					;;
+			esac
+			;;
+		
 		ORACLE7)
 			case "$cmd_cat" in
 				
@@ -3350,7 +3855,7 @@ fi
 						install)
 							# The commands are quoted exactly as much as necessary.
 							# shellcheck disable=SC2016
-							$noop '
+							$noop 'sh' '-c' '
 unset to_install to_reinstall
 for f in $packages; do
     package="$(rpm -qp "$f")"
@@ -4353,6 +4858,12 @@ cmd_repo_add()
 			
 			;;
 		
+		AMZN2)
+			
+			repo_add_yum 'AMZN2' "$vdir" "$repotype" 'redhat/repo/storpool-amzn.repo' 'redhat/repo/RPM-GPG-KEY-StorPool'
+			
+			;;
+		
 		CENTOS7)
 			
 			repo_add_yum 'CENTOS7' "$vdir" "$repotype" 'redhat/repo/storpool-centos.repo' 'redhat/repo/RPM-GPG-KEY-StorPool'
@@ -4395,6 +4906,12 @@ cmd_repo_add()
 			
 			;;
 		
+		FEDORA38)
+			
+			repo_add_yum 'FEDORA38' "$vdir" "$repotype" 'redhat/repo/storpool-fedora.repo' 'redhat/repo/RPM-GPG-KEY-StorPool'
+			
+			;;
+		
 		ORACLE7)
 			
 			repo_add_yum 'ORACLE7' "$vdir" "$repotype" 'redhat/repo/storpool-centos.repo' 'redhat/repo/RPM-GPG-KEY-StorPool'
@@ -4506,7 +5023,7 @@ cmd_command()
 
 cmd_features()
 {
-	echo 'Features: format=1.4 version=3.5.3'
+	echo 'Features: format=1.5 version=3.5.3'
 }
 
 case "$1" in
@@ -4562,6 +5079,10 @@ case "$1" in
 				show_variant 'ALMA9'
 				;;
 			
+			AMZN2)
+				show_variant 'AMZN2'
+				;;
+			
 			CENTOS7)
 				show_variant 'CENTOS7'
 				;;
@@ -4590,6 +5111,10 @@ case "$1" in
 				show_variant 'DEBIAN13'
 				;;
 			
+			FEDORA38)
+				show_variant 'FEDORA38'
+				;;
+			
 			ORACLE7)
 				show_variant 'ORACLE7'
 				;;